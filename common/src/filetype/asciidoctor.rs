@@ -36,6 +36,7 @@ impl Analyse for AsciiDoctor {
         let mut walker = Walker::new(prev, source);
 
         let mut outline = Vec::new();
+        let mut outline_offsets = Vec::new();
         let mut links = Vec::new();
         let mut attributes = HashMap::new();
 
@@ -101,6 +102,7 @@ impl Analyse for AsciiDoctor {
                     if !(equal_count == 3 && rest[..line_end].is_empty()) {
                         walker.increment_post_by(line_end);
                         outline.push((equal_count as u8, Cow::Borrowed(&rest[..line_end])));
+                        outline_offsets.push(start - "=".len()); // {start} is after the first
                     }
                     state = M::Text;
                 } //_ => {}
@@ -108,6 +110,7 @@ impl Analyse for AsciiDoctor {
         }
         Metadata {
             outline,
+            outline_offsets,
             links,
             attributes,
         }
@@ -22,14 +22,15 @@ impl Analyse for CommonMark {
     }
 
     fn metadata<'a>(&self, source: &'a str) -> Metadata<'a> {
-        let frontmatter = source.strip_prefix("---\n").and_then(|post_dashes| {
+        // Split into the frontmatter and the body after the closing dashes
+        let (frontmatter, body) = source.strip_prefix("---\n").and_then(|post_dashes| {
             // @TODO: simplify this into one loop? Might be less idomatic
             if let Some(end) = post_dashes.find("\n---\n") {
-                Some(&post_dashes[..end])
+                Some((&post_dashes[..end], &post_dashes[end + "\n---\n".len()..]))
             } else {
-                source.strip_suffix("\n---")
+                post_dashes.strip_suffix("\n---").map(|frontmatter| (frontmatter, ""))
             }
-        }).unwrap_or("");
+        }).unwrap_or(("", source));
 
         let mut attributes = HashMap::new();
         for line in frontmatter.lines() {
@@ -49,12 +50,15 @@ impl Analyse for CommonMark {
         let mut build_link = (Cow::Borrowed(""), Cow::Borrowed(""));
         let mut build_header = (0, Cow::Borrowed(""));
         let mut outline = Vec::new();
+        let mut outline_offsets = Vec::new();
         let mut links = Vec::new();
-        for event in Parser::new(&source[frontmatter.len()..]) {
+        let body_offset = source.len() - body.len();
+        for (event, range) in Parser::new(body).into_offset_iter() {
             let mut event_text = None;
             match event {
                 Event::Start(Tag::Heading(heading_level, _, _)) => {
                     debug_assert!(!is_header);
+                    outline_offsets.push(body_offset + range.start);
                     build_header.0 = heading_level as u8;
                     is_header = true;
                 }
@@ -101,6 +105,7 @@ impl Analyse for CommonMark {
         }
         Metadata {
             outline,
+            outline_offsets,
             links,
             attributes,
         }
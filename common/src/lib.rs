@@ -10,10 +10,13 @@ pub use walker::Walker;
 mod filetype;
 use filetype::*;
 mod metadata;
+pub use metadata::json_push_str;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Metadata<'a> {
     pub outline: Vec<(u8, Cow<'a, str>)>,
+    // Where each heading of {outline} starts in the source, e.g. at its "#"
+    pub outline_offsets: Vec<usize>,
     pub links: Vec<(Cow<'a, str>, Cow<'a, str>)>,
     pub attributes: HashMap<&'a str, &'a str>,
 }
@@ -57,7 +60,12 @@ impl Analyse for FileType {
 }
 
 struct Todo();
-impl Analyse for Todo {}
+impl Analyse for Todo {
+    // So that 'Api::metadata()' works with every filetype
+    fn metadata<'a>(&self, _source: &'a str) -> Metadata<'a> {
+        Metadata::default()
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -49,7 +49,7 @@ impl<'a> Metadata<'a> {
     }
 }
 
-fn json_push_str(buffer: &mut String, to_push: &str) {
+pub fn json_push_str(buffer: &mut String, to_push: &str) {
     buffer.push('"');
 
     // @TODO: This would actually be a good place to use SIMD
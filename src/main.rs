@@ -1,10 +1,12 @@
 use std::env;
 use std::fs;
 use std::io::{self, Read, Write};
+use std::path::PathBuf;
 
 use tetra::{
     self as tetralib,
     api::{Analyse, FileType, Config},
+    run::{Bindings, Trace},
 };
 //use xflags;
 
@@ -31,6 +33,12 @@ mod flags {
             ///// Sets the filetype of
             optional -o, --output-type output_type: String
 
+            /// Prints an execution trace to STDERR, either 'log' or 'chrome'
+            optional --trace trace_format: String
+
+            /// Where 'figure' writes its files, by default 'assets' next to the output
+            optional --assets assets_path: String
+
             /// Parse tree
             cmd parse
                 ///
@@ -58,6 +66,27 @@ mod flags {
                 ///
                 required out_path: String
             {}
+
+            /// Prints the command dependency graph as Graphviz DOT instead of running
+            cmd graph
+                ///
+                required inp_path: String
+
+                ///
+                optional out_path: String
+            {}
+
+            /// Lists the functions of the default flavour, or just the one named
+            cmd functions
+                ///
+                optional name: String
+            {
+                /// Print as Markdown reference documentation
+                optional --markdown
+
+                /// Print as a JSON array
+                optional --json
+            }
         }
     }
 }
@@ -65,7 +94,7 @@ mod flags {
 //run: cargo run -- parse-and-json ../readme-source.md /dev/null | jq
 fn main() {
     // Process global flags first
-    let (inp_filetype, out_filetype, subcommands) = match flags::Tetra::from_env() {
+    let (inp_filetype, out_filetype, trace_format, assets_path, subcommands) = match flags::Tetra::from_env() {
         Ok(args) if args.help => {
             eprintln!("{}", flags::Tetra::HELP);
             std::process::exit(1)
@@ -85,7 +114,15 @@ fn main() {
                     std::process::exit(1);
                 })
             });
-            (inp, out, args.subcommand)
+
+            match args.trace.as_deref() {
+                None | Some("log") | Some("chrome") => {}
+                Some(format) => {
+                    eprintln!("{} is an unsupported trace format. Use 'log' or 'chrome'", format);
+                    std::process::exit(1);
+                }
+            }
+            (inp, out, args.trace, args.assets, args.subcommand)
         }
         Err(err) => {
             eprintln!("{}\n{}", err, flags::Tetra::HELP);
@@ -94,15 +131,20 @@ fn main() {
     };
 
     // Intepret the subcommands
-    let (inp_path, out_path, is_print_json) = match subcommands {
-        flags::TetraCmd::Parse(p) => (Some(p.inp_path), p.out_path, false),
-        flags::TetraCmd::ParseStdin(p) => (None, p.out_path, false),
-        flags::TetraCmd::ParseAndJson(p) => (Some(p.inp_path), Some(p.out_path), true),
+    let (inp_path, out_path, is_print_json, is_graph) = match subcommands {
+        flags::TetraCmd::Parse(p) => (Some(p.inp_path), p.out_path, false, false),
+        flags::TetraCmd::ParseStdin(p) => (None, p.out_path, false, false),
+        flags::TetraCmd::ParseAndJson(p) => (Some(p.inp_path), Some(p.out_path), true, false),
+        flags::TetraCmd::Graph(p) => (Some(p.inp_path), p.out_path, false, true),
+        flags::TetraCmd::Functions(p) => {
+            print_functions(p);
+            return;
+        }
     };
 
 
     // Read the file from STDIN or {inp_path}, setting {inp_filetype} if appropriate
-    let (inp_content, inp_filetype) = if let Some(path) = inp_path {
+    let (inp_content, inp_filetype) = if let Some(path) = &inp_path {
         // Prefer the '--input-type' switch override. Else find it from {path}
         let ft = inp_filetype.unwrap_or_else(|| path
             .rfind(|c| c == '.')
@@ -110,13 +152,25 @@ fn main() {
             // No extension or extension not supported, just use 'FileType::Default'
             .unwrap_or(FileType::Default)
         );
-        (log(&path, fs::read_to_string(&path)), ft)
+        (log(path, fs::read_to_string(path)), ft)
     } else {
         let mut stdin = String::new();
         log("STDIN", io::stdin().read_to_string(&mut stdin));
         (stdin, FileType::Default)
     };
 
+    // Just parse, no need to run anything
+    if is_graph {
+        let ast = log("parsing", Bindings::<(), ()>::build(&inp_content));
+        let dot = ast.to_dot(&inp_content);
+        if let Some(path) = out_path {
+            log(&path, fs::write(&path, dot.as_bytes()));
+        } else {
+            print!("{}", dot);
+        }
+        return;
+    }
+
     // Set the {out_filetype} if not overridden by the '--output-type' switch
     let out_filetype = out_filetype.unwrap_or_else(|| {
         out_path
@@ -132,8 +186,33 @@ fn main() {
 
     // Compile
     let ctx = tetralib::default_context();
-    let config = Config::new(inp_filetype, out_filetype);
-    let out_content = log("compiling", ctx.compile(&inp_content, config));
+    let mut config = Config::new(inp_filetype, out_filetype);
+    config.input_path = inp_path.map(PathBuf::from);
+    config.output_path = out_path.clone().map(PathBuf::from);
+    config.assets_path = assets_path.map(PathBuf::from);
+
+    // Stream straight to STDOUT so that previews update progressively. Files
+    // are only written once compiled, so a failure keeps the previous output
+    if trace_format.is_none() && !is_print_json && out_path.is_none() {
+        let source = log("pre-lex hooks", ctx.pre_lex(&inp_content, &mut config));
+        let ast = log("parsing", Bindings::<(), ()>::build_spliced(&source, &config.splices));
+        let mut stdout = io::stdout().lock();
+        log("compiling", ctx.run_to_writer(&ast, config, &source, &mut stdout));
+        log("STDOUT", writeln!(stdout));
+        return;
+    }
+
+    let out_content = if let Some(format) = trace_format {
+        let mut trace = Trace::new();
+        let result = ctx.compile_with_tracer(&inp_content, config, &mut trace);
+        match format.as_str() {
+            "chrome" => eprintln!("{}", trace.to_chrome_json()),
+            _ => eprint!("{}", trace.to_log()),
+        }
+        log("compiling", result)
+    } else {
+        log("compiling", ctx.compile(&inp_content, config))
+    };
 
     // Write to output
     if let Some(path) = out_path {
@@ -150,6 +229,34 @@ fn main() {
 
 }
 
+fn print_functions(args: flags::Functions) {
+    let ctx = tetralib::default_context();
+    let functions = match &args.name {
+        Some(name) => match ctx.function_info(name) {
+            Some(info) => vec![info],
+            None => {
+                eprintln!("{:?} is not a function of the default flavour", name);
+                std::process::exit(1);
+            }
+        },
+        None => ctx.functions().collect(),
+    };
+
+    if args.json {
+        let entries = functions.iter().map(|f| f.to_json()).collect::<Vec<_>>();
+        println!("[{}]", entries.join(","));
+    } else if args.markdown {
+        println!("# Functions\n");
+        for f in &functions {
+            print!("{}", f.to_markdown());
+        }
+    } else {
+        for f in &functions {
+            print!("{}", f.to_text());
+        }
+    }
+}
+
 fn log<T, E: std::fmt::Debug>(path: &str, result: Result<T, E>) -> T {
     match result {
         Ok(s) => s,
@@ -1,50 +1,139 @@
 //run: cargo test -- --nocapture
 
 //use std::collections::HashMap;
+use std::cell::OnceCell;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 pub use common::*;
 
+use crate::framework::Source;
+
 // Maybe see OPML spec for design
 // {usize} is the level, so we can pack it into an array
-#[derive(Debug)]
-pub struct OutlineEntry<'source>(usize, &'source str);
+#[derive(Debug, PartialEq, Eq)]
+pub struct OutlineEntry<'a>(pub usize, pub &'a str);
 
 // Metadata
 #[derive(Clone, Debug)]
 pub struct Config {
     pub input_filetype: FileType,
     pub output_filetype: FileType,
+    pub input_path: Option<PathBuf>, // None if e.g. read from STDIN
+    pub output_path: Option<PathBuf>,
+    // Where 'figure' writes its files. None for "assets" next to the output
+    pub assets_path: Option<PathBuf>,
+    // Filled in by the pre-lex hooks that splice in other files, e.g. the
+    // includes of the default flavour. Hooks that change the source after
+    // that must keep them in step
+    pub splices: Vec<Splice>,
     //build_command: String,
 }
 
+// Where a part of the source came from, so that errors in it name the file.
+// {start}..{close} is a byte range of the source once the pre-lex hooks have
+// run, and {line} the line of the file (from 1) that {start} is on
+#[derive(Clone, Debug)]
+pub struct Splice {
+    pub start: usize,
+    pub close: usize,
+    pub path: PathBuf,
+    pub line: usize,
+}
+
 impl Config {
     pub fn new(input_filetype: FileType, output_filetype: FileType) -> Self {
         Self {
             input_filetype,
             output_filetype,
+            input_path: None,
+            output_path: None,
+            assets_path: None,
+            splices: Vec::new(),
             //build_command: String::new(),
         }
     }
+
+    // The splice that the source at {offset} is from, if any
+    pub fn splice_at(&self, offset: usize) -> Option<&Splice> {
+        self.splices.iter().find(|splice| splice.start <= offset && offset < splice.close)
+    }
 }
 
+// What a function knows about the document and where it is being called from
 #[derive(Debug)]
 pub struct Api<'source> {
-    pub meta: Config,
+    pub meta: &'source Config,
     source: &'source str,
     //opts: &HashMap<(usize, K), V>,
     id: usize, // id for ooptions
-    pub outline: Vec<OutlineEntry<'source>>,
+    span: Source,
+    // Shared between all the calls of a run so that it is analysed only once
+    metadata: Rc<OnceCell<Metadata<'source>>>,
 }
 
 impl<'source> Api<'source> {
-    pub fn new(source: &'source str, id: usize, meta: &Config) -> Self {
+    pub fn new(source: &'source str, id: usize, meta: &'source Config) -> Self {
+        Api {
+            meta,
+            source,
+            id,
+            span: Source::Range(0, source.len()),
+            metadata: Rc::default(),
+        }
+    }
+
+    pub(crate) fn for_command(
+        source: &'source str,
+        id: usize,
+        span: Source,
+        meta: &'source Config,
+        metadata: &Rc<OnceCell<Metadata<'source>>>,
+    ) -> Self {
         Api {
-            meta: meta.clone(),
+            meta,
             source,
             id,
-            outline: Vec::new(),
+            span,
+            metadata: Rc::clone(metadata),
         }
     }
+
+    // Index of the command being run. Commands are run in increasing order
+    // on every pass of the executor
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    // The entire document
+    pub fn source(&self) -> &'source str {
+        self.source
+    }
+
+    // Where in {source} the function was called, from its name to its last
+    // argument (e.g. the body of `{| run "sh" |}body`)
+    pub fn span(&self) -> &Source {
+        &self.span
+    }
+
+    pub fn line_number(&self) -> usize {
+        self.span.line_number(self.source)
+    }
+
+    // The attributes (frontmatter), outline (headings) and links of the
+    // document, as written in the source (i.e. before any commands are run).
+    // Commands run before the document is knitted, so headings that contain
+    // or are output by commands appear as written, e.g. "# {$ title $}". See
+    // 'toc' for analysing the knitted document in a post-knit hook instead.
+    pub fn metadata(&self) -> &Metadata<'source> {
+        self.metadata.get_or_init(|| self.meta.input_filetype.metadata(self.source))
+    }
+
+    // The headings of 'metadata()'
+    pub fn outline(&self) -> Vec<OutlineEntry<'_>> {
+        let outline = self.metadata().outline.iter();
+        outline.map(|(level, text)| OutlineEntry(usize::from(*level), text)).collect()
+    }
 }
 
 //pub trait Api {
@@ -0,0 +1,171 @@
+//run: cargo test -- --nocapture
+
+// Citations without pandoc
+//
+// Entries are read from BibTeX (".bib") and CSL-JSON (".json") files into a
+// 'Bibliography', which then formats all the citations of a document at once
+// (see "bibliography/style.rs") so that they can be numbered and told apart.
+// Only the fields that the styles use are kept.
+
+mod bibtex;
+mod csl_json;
+mod style;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use common::FileType;
+
+pub use style::Style;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub kind: String, // e.g. "article" or "book", as named by the file
+    pub authors: Vec<Name>,
+    pub title: Option<String>,
+    pub year: Option<String>,
+    pub container: Option<String>, // the journal or the book it is in
+    pub publisher: Option<String>,
+    pub volume: Option<String>,
+    pub issue: Option<String>,
+    pub pages: Option<String>,
+    pub doi: Option<String>,
+    pub url: Option<String>,
+}
+
+// Organisations have no {given} name
+#[derive(Clone, Debug, PartialEq)]
+pub struct Name {
+    pub family: String,
+    pub given: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Bibliography {
+    entries: Vec<Entry>,
+    by_key: HashMap<String, usize>,
+}
+
+impl Bibliography {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The format is decided by the extension of {path}
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("Could not read the bibliography {:?}. {}", path, err))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("bib") => self.add_bibtex(&source),
+            Some("json") => self.add_csl_json(&source),
+            _ => Err(format!(
+                "{:?} is not a BibTeX (.bib) or CSL-JSON (.json) bibliography",
+                path
+            )),
+        }
+        .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn add_bibtex(&mut self, source: &str) -> Result<(), String> {
+        bibtex::parse(source).map(|entries| self.extend(entries))
+    }
+
+    pub fn add_csl_json(&mut self, source: &str) -> Result<(), String> {
+        csl_json::parse(source).map(|entries| self.extend(entries))
+    }
+
+    // Entries replace those added before with the same key
+    fn extend(&mut self, entries: Vec<Entry>) {
+        for entry in entries {
+            match self.by_key.get(&entry.key) {
+                Some(i) => self.entries[*i] = entry,
+                None => {
+                    self.by_key.insert(entry.key.clone(), self.entries.len());
+                    self.entries.push(entry);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.by_key.get(key).map(|i| &self.entries[*i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Returns the text of each of {citations} and the reference list of the
+    // works cited, in the syntax of {filetype}
+    pub fn format(
+        &self,
+        citations: &[Citation],
+        style: Style,
+        filetype: FileType,
+    ) -> Result<(Vec<String>, String), String> {
+        style::format(self, citations, style, filetype)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// One in-text citation, possibly of several works
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Citation {
+    pub keys: Vec<String>,
+    // e.g. "Smith (2001)" rather than "(Smith 2001)"
+    pub is_narrative: bool,
+    pub prefix: Option<String>, // e.g. "see", before the first work
+    pub page: Option<String>,   // e.g. "12" or "3-5", after the last work
+    // e.g. "(2001)" rather than "(Smith 2001)" when the text already names them
+    pub suppress_author: bool,
+}
+
+impl Citation {
+    // Takes pandoc's syntax, i.e. "[@a; @b]", or "@a" for narrative, though
+    // the '@' and brackets are optional, e.g. "a, b"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let inner = text.strip_prefix('[').and_then(|s| s.strip_suffix(']'));
+        let is_narrative = inner.is_none() && text.starts_with('@');
+
+        let mut keys = Vec::new();
+        for key in inner.unwrap_or(text).split([';', ',']) {
+            let key = key.trim();
+            let key = key.strip_prefix('@').unwrap_or(key);
+            if key.is_empty() || key.contains(char::is_whitespace) {
+                return Err(format!("{:?} is not a list of citekeys", text));
+            }
+            keys.push(key.to_string());
+        }
+        Ok(Self { keys, is_narrative, ..Self::default() })
+    }
+
+    // e.g. "p. 12" or "pp. 3-5"
+    pub fn locator(&self) -> Option<String> {
+        self.page.as_deref().map(|page| match page.contains(['-', '–', ',']) {
+            true => format!("pp. {}", page),
+            false => format!("p. {}", page),
+        })
+    }
+
+    // In pandoc's Markdown, e.g. "[see -@a; @b, p. 12]" or "@a [p. 12]"
+    pub fn to_pandoc(&self) -> String {
+        let suppress = if self.suppress_author { "-" } else { "" };
+        let keys = self.keys.iter().map(|key| format!("{}@{}", suppress, key));
+        let keys = keys.collect::<Vec<_>>().join("; ");
+        let prefix = self.prefix.as_deref().map(|s| format!("{} ", s)).unwrap_or_default();
+        match (self.is_narrative, self.locator()) {
+            (true, Some(locator)) => format!("{}{} [{}]", prefix, keys, locator),
+            (true, None) => format!("{}{}", prefix, keys),
+            (false, Some(locator)) => format!("[{}{}, {}]", prefix, keys, locator),
+            (false, None) => format!("[{}{}]", prefix, keys),
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//run: cargo test -- --nocapture
+
+// BibTeX, e.g.
+//
+//   @article{capper2012,
+//     author = {Capper, Daniel and van der Berg, Ann},
+//     title = {Reading {LaTeX} \& More},
+//     journal = jcs, year = 2012,
+//   }
+//
+// Text outside of entries is a comment, as are '@comment' entries. Macros
+// defined with '@string' (and the month abbreviations) can be used as values
+// and joined with '#'. The LaTeX in values is reduced to plain text by
+// 'detex()' once names are split, as braces protect " and " in names.
+
+use std::collections::HashMap;
+
+use super::{Entry, Name};
+
+const MONTHS: [(&str, &str); 12] = [
+    ("jan", "January"),
+    ("feb", "February"),
+    ("mar", "March"),
+    ("apr", "April"),
+    ("may", "May"),
+    ("jun", "June"),
+    ("jul", "July"),
+    ("aug", "August"),
+    ("sep", "September"),
+    ("oct", "October"),
+    ("nov", "November"),
+    ("dec", "December"),
+];
+
+pub fn parse(source: &str) -> Result<Vec<Entry>, String> {
+    let mut parser = Parser {
+        source,
+        cursor: 0,
+        macros: MONTHS.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+    };
+    let mut entries = Vec::new();
+    while let Some(at) = source[parser.cursor..].find('@') {
+        parser.cursor += at + '@'.len_utf8();
+        if let Some(entry) = parser.entry()? {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    cursor: usize,
+    macros: HashMap<String, String>,
+}
+
+impl<'a> Parser<'a> {
+    // After the '@'
+    fn entry(&mut self) -> Result<Option<Entry>, String> {
+        let kind = self.identifier().to_lowercase();
+        self.skip_whitespace();
+        // Otherwise the '@' is in a comment, e.g. an email address
+        let close = match self.peek() {
+            Some('{') => '}',
+            Some('(') => ')',
+            _ => return Ok(None),
+        };
+        self.cursor += 1;
+
+        match kind.as_str() {
+            "comment" | "preamble" => {
+                self.skip_group(close)?;
+                return Ok(None);
+            }
+            "string" => {
+                let (name, value) = self.field()?;
+                self.macros.insert(name, value);
+                self.skip_whitespace();
+                self.expect(close)?;
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        let key_len = self.rest().find([',', close]).unwrap_or(self.rest().len());
+        let key = self.rest()[..key_len].trim().to_string();
+        self.cursor += key_len;
+        if key.is_empty() {
+            return Err(self.error("Expected a citekey"));
+        }
+
+        let mut fields = HashMap::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(c) if c == close => {
+                    self.cursor += c.len_utf8();
+                    break;
+                }
+                Some(',') => self.cursor += 1,
+                Some(_) => {
+                    let (name, value) = self.field()?;
+                    fields.insert(name, value);
+                    self.skip_whitespace();
+                    if self.peek() != Some(close) {
+                        self.expect(',')?;
+                    }
+                }
+                None => return Err(self.error(&format!("The entry {:?} is never closed", key))),
+            }
+        }
+        Ok(Some(to_entry(key, kind, fields)))
+    }
+
+    // e.g. "title = {Text}", the name is lowercase
+    fn field(&mut self) -> Result<(String, String), String> {
+        self.skip_whitespace();
+        let name = self.identifier().to_lowercase();
+        if name.is_empty() {
+            return Err(self.error("Expected a field name"));
+        }
+        self.skip_whitespace();
+        self.expect('=')?;
+
+        let mut value = String::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('{') => {
+                    self.cursor += 1;
+                    let start = self.cursor;
+                    self.skip_group('}')?;
+                    value.push_str(&self.source[start..self.cursor - 1]);
+                }
+                Some('"') => {
+                    self.cursor += 1;
+                    let start = self.cursor;
+                    self.skip_group('"')?;
+                    value.push_str(&self.source[start..self.cursor - 1]);
+                }
+                Some(c) if c.is_ascii_digit() => {
+                    let len = self.rest().find(|c: char| !c.is_ascii_digit()).unwrap_or(self.rest().len());
+                    value.push_str(&self.rest()[..len]);
+                    self.cursor += len;
+                }
+                Some(_) => {
+                    let name = self.identifier().to_lowercase();
+                    match self.macros.get(&name) {
+                        Some(expansion) => value.push_str(expansion),
+                        None if name.is_empty() => return Err(self.error("Expected a value")),
+                        None => return Err(self.error(&format!("{:?} is not a defined @string", name))),
+                    }
+                }
+                None => return Err(self.error("Expected a value")),
+            }
+            self.skip_whitespace();
+            if self.peek() == Some('#') {
+                self.cursor += 1;
+            } else {
+                break;
+            }
+        }
+        Ok((name, value))
+    }
+
+    // Skips past {close}, ignoring any inside nested braces
+    fn skip_group(&mut self, close: char) -> Result<(), String> {
+        let start = self.cursor;
+        let mut depth = 0;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                c if c == close && depth == 0 => {
+                    self.cursor += i + c.len_utf8();
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        self.cursor = start;
+        Err(self.error(&format!("Expected a closing {:?}", close)))
+    }
+
+    fn identifier(&mut self) -> &'a str {
+        let rest = &self.source[self.cursor..];
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || "_-:.+/".contains(c)))
+            .unwrap_or(rest.len());
+        self.cursor += len;
+        &rest[..len]
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.cursor += c.len_utf8();
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected {:?}", c)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.cursor += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.cursor..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.source[..self.cursor].matches('\n').count() + 1;
+        format!("line {}: {}", line, message)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn to_entry(key: String, kind: String, mut fields: HashMap<String, String>) -> Entry {
+    let authors = fields
+        .remove("author")
+        .or_else(|| fields.remove("editor"))
+        .map(|names| split_names(&names))
+        .unwrap_or_default();
+    let mut take = |name: &str| fields.remove(name).map(|value| detex(&value));
+    let year = take("year").or_else(|| take("date").map(|date| date.chars().take(4).collect()));
+    Entry {
+        authors,
+        title: take("title"),
+        year,
+        container: take("journal").or_else(|| take("journaltitle")).or_else(|| take("booktitle")),
+        publisher: take("publisher").or_else(|| take("institution")).or_else(|| take("school")),
+        volume: take("volume"),
+        issue: take("number").or_else(|| take("issue")),
+        pages: take("pages"),
+        doi: take("doi"),
+        url: take("url"),
+        key,
+        kind,
+    }
+}
+
+// e.g. "Capper, Daniel and {Barnes and Noble}"
+fn split_names(names: &str) -> Vec<Name> {
+    top_level_split(names, |s| s.strip_prefix(" and ").map(|_| " and ".len()))
+        .into_iter()
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && *name != "others")
+        .map(parse_name)
+        .collect()
+}
+
+// "von Last, First", "von Last, Jr, First" or "First von Last"
+fn parse_name(name: &str) -> Name {
+    let parts = top_level_split(name, |s| s.starts_with(',').then_some(1));
+    if parts.len() > 1 {
+        let given = detex(parts[parts.len() - 1].trim());
+        return Name {
+            family: detex(parts[0].trim()),
+            given: (!given.is_empty()).then_some(given),
+        };
+    }
+
+    let words = top_level_split(name, |s| s.starts_with(char::is_whitespace).then_some(1))
+        .into_iter()
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    // A "von" part (e.g. "van der") starts with lowercase and joins the family
+    let split = words
+        .iter()
+        .take(words.len().saturating_sub(1))
+        .skip(1)
+        .position(|word| word.starts_with(char::is_lowercase))
+        .map_or(words.len().saturating_sub(1), |i| i + 1);
+    let given = detex(&words[..split].join(" "));
+    Name {
+        family: detex(&words[split..].join(" ")),
+        given: (!given.is_empty()).then_some(given),
+    }
+}
+
+// Splits {text} wherever {separator} matches outside of braces, which
+// returns the length of the separator
+fn top_level_split<F: Fn(&str) -> Option<usize>>(text: &str, separator: F) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0_usize;
+    let mut start = 0;
+    let mut skip_to = 0;
+    for (i, c) in text.char_indices() {
+        if i < skip_to {
+            continue;
+        }
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => {
+                if let Some(len) = separator(&text[i..]) {
+                    parts.push(&text[start..i]);
+                    start = i + len;
+                    skip_to = start;
+                }
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Reduces the LaTeX in a value to plain text, e.g. "{\"o}" to "ö"
+pub fn detex(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' => {}
+            '~' => output.push('\u{A0}'),
+            '-' if chars.peek() == Some(&'-') => {
+                chars.next();
+                if chars.peek() == Some(&'-') {
+                    chars.next();
+                    output.push('—');
+                } else {
+                    output.push('–');
+                }
+            }
+            c if c.is_whitespace() => {
+                if !output.ends_with(' ') {
+                    output.push(' ');
+                }
+            }
+            '\\' => match chars.peek().copied() {
+                Some(symbol @ ('&' | '%' | '$' | '#' | '_' | '{' | '}' | ' ')) => {
+                    chars.next();
+                    output.push(symbol);
+                }
+                Some(accent @ ('\'' | '`' | '"' | '^' | '~' | '=' | '.')) => {
+                    chars.next();
+                    push_accented(&mut output, accent, accented_letter(&mut chars));
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let mut command = String::new();
+                    while let Some(c) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                        command.push(*c);
+                        chars.next();
+                    }
+                    match command.as_str() {
+                        "c" => push_accented(&mut output, 'c', accented_letter(&mut chars)),
+                        "ss" => output.push('ß'),
+                        "o" => output.push('ø'),
+                        "O" => output.push('Ø'),
+                        "aa" => output.push('å'),
+                        "AA" => output.push('Å'),
+                        "ae" => output.push('æ'),
+                        "AE" => output.push('Æ'),
+                        "oe" => output.push('œ'),
+                        "OE" => output.push('Œ'),
+                        "l" => output.push('ł'),
+                        "L" => output.push('Ł'),
+                        "i" => output.push('ı'),
+                        "TeX" | "LaTeX" | "BibTeX" => output.push_str(&command),
+                        // e.g. "\emph{text}" is reduced to "text"
+                        _ => {}
+                    }
+                    // The space ending a command is not part of the text
+                    if chars.peek() == Some(&' ') {
+                        chars.next();
+                    }
+                }
+                _ => {}
+            },
+            c => output.push(c),
+        }
+    }
+    output.trim().to_string()
+}
+
+// The letter of e.g. "\'e", "\'{e}" or "\c c"
+fn accented_letter<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) -> Option<char> {
+    while chars.peek().is_some_and(|c| *c == '{' || *c == ' ') {
+        chars.next();
+    }
+    let letter = chars.next();
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    }
+    letter
+}
+
+fn push_accented(output: &mut String, accent: char, letter: Option<char>) {
+    let Some(letter) = letter else { return };
+    let (from, to) = match accent {
+        '\'' => ("aeiouyAEIOUYcnszCNSZ", "áéíóúýÁÉÍÓÚÝćńśźĆŃŚŹ"),
+        '`' => ("aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+        '"' => ("aeiouyAEIOUY", "äëïöüÿÄËÏÖÜŸ"),
+        '^' => ("aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+        '~' => ("anoANO", "ãñõÃÑÕ"),
+        '=' => ("aeiouAEIOU", "āēīōūĀĒĪŌŪ"),
+        '.' => ("zZ", "żŻ"),
+        'c' => ("csCS", "çşÇŞ"),
+        _ => ("", ""),
+    };
+    match from.chars().position(|c| c == letter) {
+        Some(i) => output.push(to.chars().nth(i).unwrap()),
+        None => output.push(letter),
+    }
+}
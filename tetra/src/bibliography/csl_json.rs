@@ -0,0 +1,87 @@
+//run: cargo test -- --nocapture
+
+// CSL-JSON, as exported by Zotero and pandoc, e.g.
+//
+//   [{ "id": "capper2012", "type": "article-journal", "title": "Reading",
+//      "author": [{ "family": "Capper", "given": "Daniel" }],
+//      "issued": { "date-parts": [[2012, 3]] },
+//      "container-title": "Journal of Citations" }]
+
+use super::{Entry, Name};
+use crate::json::{self, Json};
+
+pub fn parse(source: &str) -> Result<Vec<Entry>, String> {
+    match json::parse(source)? {
+        Json::Array(items) => items.into_iter().map(to_entry).collect(),
+        // A single entry
+        json @ Json::Object(_) => Ok(vec![to_entry(json)?]),
+        _ => Err("Expected a list of entries".to_string()),
+    }
+}
+
+fn to_entry(json: Json) -> Result<Entry, String> {
+    let key = match json.get("id") {
+        Some(Json::String(s)) => s.clone(),
+        Some(Json::Number(n)) => n.clone(),
+        _ => return Err("An entry is missing its \"id\"".to_string()),
+    };
+    let text = |field: &str| match json.get(field) {
+        Some(Json::String(s)) if !s.is_empty() => Some(s.clone()),
+        Some(Json::Number(n)) => Some(n.clone()),
+        _ => None,
+    };
+
+    let names = |field: &str| match json.get(field) {
+        Some(Json::Array(names)) => names.iter().filter_map(to_name).collect(),
+        _ => Vec::new(),
+    };
+    let mut authors = names("author");
+    if authors.is_empty() {
+        authors = names("editor");
+    }
+
+    Ok(Entry {
+        kind: text("type").unwrap_or_default(),
+        authors,
+        title: text("title"),
+        year: json.get("issued").and_then(to_year),
+        container: text("container-title"),
+        publisher: text("publisher"),
+        volume: text("volume"),
+        issue: text("issue"),
+        pages: text("page"),
+        doi: text("DOI"),
+        url: text("URL"),
+        key,
+    })
+}
+
+fn to_name(json: &Json) -> Option<Name> {
+    let text = |field: &str| match json.get(field) {
+        Some(Json::String(s)) if !s.is_empty() => Some(s.clone()),
+        _ => None,
+    };
+    // e.g. "van" in "Ludwig van Beethoven"
+    let family = match (text("non-dropping-particle"), text("family")) {
+        (Some(particle), Some(family)) => format!("{} {}", particle, family),
+        (None, Some(family)) => family,
+        (_, None) => return text("literal").map(|family| Name { family, given: None }),
+    };
+    Some(Name { family, given: text("given") })
+}
+
+// e.g. {"date-parts": [[2012, 3]]}, {"raw": "2012-03"} or {"literal": "2012"}
+fn to_year(date: &Json) -> Option<String> {
+    if let Some(Json::Array(parts)) = date.get("date-parts") {
+        if let Some(Json::Array(first)) = parts.first() {
+            return match first.first() {
+                Some(Json::Number(year) | Json::String(year)) => Some(year.clone()),
+                _ => None,
+            };
+        }
+    }
+    match date.get("raw").or_else(|| date.get("literal")) {
+        Some(Json::String(s)) => Some(s.chars().take(4).collect()),
+        _ => None,
+    }
+}
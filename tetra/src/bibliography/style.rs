@@ -0,0 +1,327 @@
+//run: cargo test -- --nocapture
+
+// The citation styles
+//
+// * author-date, e.g. "(Capper 2012; Smith and Jones 2001a)", with the
+//   references sorted by author then year, and a letter after the year to
+//   tell apart works by the same authors in the same year
+// * numeric, e.g. "[1, 2]", with the references numbered in the order that
+//   they are first cited
+//
+// A citation can also have a prefix and a page, e.g. "(see Capper 2012, p. 3)"
+// and "Capper (2012, p. 3)", and leave out the authors, e.g. "(2012)".
+//
+// The references themselves are roughly in the Chicago style, e.g.
+//
+//   Capper, Daniel, and Ann Smith. 2012. “Reading.” *Journal* 3 (1): 1–10.
+//
+// Except for the plain text of 'FileType::Default', citations link to their
+// reference, which is given the id "ref-{citekey}" (the same as pandoc).
+
+use std::collections::HashMap;
+
+use common::FileType;
+
+use super::{Bibliography, Citation, Entry, Name};
+use crate::run::escape::escape;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Style {
+    AuthorDate,
+    Numeric,
+}
+
+impl Style {
+    // e.g. "author-date"
+    pub fn from(name: &str) -> Option<Self> {
+        match name {
+            "author-date" => Some(Style::AuthorDate),
+            "numeric" => Some(Style::Numeric),
+            _ => None,
+        }
+    }
+}
+
+pub fn format(
+    bibliography: &Bibliography,
+    citations: &[Citation],
+    style: Style,
+    filetype: FileType,
+) -> Result<(Vec<String>, String), String> {
+    // The works cited, in the order that they are first cited
+    let mut cited: Vec<&Entry> = Vec::new();
+    let mut numbers: HashMap<&str, usize> = HashMap::new();
+    for key in citations.iter().flat_map(|citation| &citation.keys) {
+        let entry = bibliography
+            .get(key)
+            .ok_or_else(|| format!("There is no {:?} in the bibliography", key))?;
+        if !numbers.contains_key(entry.key.as_str()) {
+            cited.push(entry);
+            numbers.insert(&entry.key, cited.len());
+        }
+    }
+    let years = match style {
+        Style::AuthorDate => {
+            cited.sort_by_cached_key(|entry| sort_key(entry));
+            disambiguated_years(&cited)
+        }
+        Style::Numeric => HashMap::new(),
+    };
+
+    let inline = citations
+        .iter()
+        .map(|citation| {
+            let locator = citation.locator().map(|locator| escape(&locator, filetype));
+            let last = citation.keys.len() - 1;
+            let works = citation.keys.iter().enumerate().map(|(i, key)| {
+                let entry = bibliography.get(key).unwrap();
+                let id = format!("ref-{}", entry.key);
+                let label = escape(&label(entry), filetype);
+                // Narrative citations give the locator of each work with it
+                let locator = match (&locator, citation.is_narrative && i == last) {
+                    (Some(locator), true) => format!(", {}", locator),
+                    _ => String::new(),
+                };
+                match (style, citation.is_narrative) {
+                    (Style::AuthorDate, false) if citation.suppress_author => {
+                        link(&id, &escape(&years[key.as_str()], filetype), filetype)
+                    }
+                    (Style::AuthorDate, false) => {
+                        link(&id, &format!("{} {}", label, escape(&years[key.as_str()], filetype)), filetype)
+                    }
+                    (Style::AuthorDate, true) => link(
+                        &id,
+                        &format!("{} ({}{})", label, escape(&years[key.as_str()], filetype), locator),
+                        filetype,
+                    ),
+                    (Style::Numeric, false) => link(&id, &numbers[key.as_str()].to_string(), filetype),
+                    (Style::Numeric, true) => {
+                        let number = link(&id, &numbers[key.as_str()].to_string(), filetype);
+                        format!("{} [{}{}]", label, number, locator)
+                    }
+                }
+            });
+            let works = works.collect::<Vec<_>>();
+            let prefix = match &citation.prefix {
+                Some(prefix) => format!("{} ", escape(prefix, filetype)),
+                None => String::new(),
+            };
+            let locator = match (&locator, citation.is_narrative) {
+                (Some(locator), false) => format!(", {}", locator),
+                _ => String::new(),
+            };
+            match (style, citation.is_narrative) {
+                (Style::AuthorDate, false) => format!("({}{}{})", prefix, works.join("; "), locator),
+                (Style::Numeric, false) => format!("[{}{}{}]", prefix, works.join(", "), locator),
+                (_, true) => format!("{}{}", prefix, works.join("; ")),
+            }
+        })
+        .collect();
+
+    let references = cited
+        .iter()
+        .map(|entry| {
+            let id = format!("ref-{}", entry.key);
+            let body = match style {
+                Style::AuthorDate => reference(entry, Some(&years[entry.key.as_str()]), filetype),
+                Style::Numeric => {
+                    format!("[{}] {}", numbers[entry.key.as_str()], reference(entry, None, filetype))
+                }
+            };
+            match filetype {
+                FileType::Html => format!("<p id=\"{}\">{}</p>", id, body),
+                _ => format!("{}{}", anchor(&id, filetype), body),
+            }
+        })
+        .collect::<Vec<_>>();
+    let list = match filetype {
+        FileType::Html => format!("<div class=\"references\">{}</div>", references.concat()),
+        _ => references.join("\n\n"),
+    };
+    Ok((inline, list))
+}
+
+// The short form of the authors, e.g. "Capper", "Capper and Smith" or
+// "Capper et al.", or the title if there are no authors
+fn label(entry: &Entry) -> String {
+    match entry.authors.as_slice() {
+        [] => entry.title.clone().unwrap_or_else(|| entry.key.clone()),
+        [only] => only.family.clone(),
+        [first, second] => format!("{} and {}", first.family, second.family),
+        [first, ..] => format!("{} et al.", first.family),
+    }
+}
+
+fn sort_key(entry: &Entry) -> (String, String, String) {
+    let authors = entry
+        .authors
+        .iter()
+        .map(|name| format!("{} {}", name.family, name.given.as_deref().unwrap_or("")))
+        .collect::<Vec<_>>()
+        .join(", ");
+    (
+        authors.to_lowercase(),
+        entry.year.clone().unwrap_or_default(),
+        entry.title.as_deref().unwrap_or("").to_lowercase(),
+    )
+}
+
+// e.g. "2001a" and "2001b" for two works of "Smith 2001", and "n.d." for
+// works without a year. {cited} must be sorted
+fn disambiguated_years<'e>(cited: &[&'e Entry]) -> HashMap<&'e str, String> {
+    let id = |entry: &Entry| (label(entry), entry.year.clone().unwrap_or_else(|| "n.d.".to_string()));
+    let mut counts: HashMap<(String, String), u32> = HashMap::new();
+    for entry in cited {
+        *counts.entry(id(entry)).or_default() += 1;
+    }
+
+    let mut seen: HashMap<(String, String), u32> = HashMap::new();
+    cited
+        .iter()
+        .map(|entry| {
+            let id = id(entry);
+            let year = match counts[&id] {
+                1 => id.1,
+                _ => {
+                    let count = seen.entry(id.clone()).or_default();
+                    *count += 1;
+                    let letter = char::from_u32('a' as u32 + *count - 1).unwrap_or('?');
+                    format!("{}{}", id.1, letter)
+                }
+            };
+            (entry.key.as_str(), year)
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// The year comes after the authors for author-date, and last otherwise
+fn reference(entry: &Entry, year: Option<&str>, filetype: FileType) -> String {
+    let mut body = String::new();
+    let escaped = |text: &Option<String>| text.as_deref().map(|s| escape(s, filetype));
+
+    push_sentence(&mut body, &escape(&names(&entry.authors), filetype));
+    if let Some(year) = year {
+        push_sentence(&mut body, &escape(year, filetype));
+    }
+    match (escaped(&entry.title), &entry.container) {
+        // Articles and chapters are quoted, and books italicised
+        (Some(title), Some(_)) => {
+            let stop = if ends_sentence(&title) { "" } else { "." };
+            body.push_str(&format!("“{}{}” ", title, stop));
+        }
+        (Some(title), None) => push_sentence(&mut body, &italic(&title, filetype)),
+        (None, _) => {}
+    }
+    if let Some(container) = escaped(&entry.container) {
+        let mut part = italic(&container, filetype);
+        if let Some(volume) = escaped(&entry.volume) {
+            part.push(' ');
+            part.push_str(&volume);
+        }
+        if let Some(issue) = escaped(&entry.issue) {
+            part.push_str(&format!(" ({})", issue));
+        }
+        if let Some(pages) = escaped(&entry.pages) {
+            let separator = if entry.volume.is_some() || entry.issue.is_some() { ":" } else { "," };
+            part.push_str(&format!("{} {}", separator, pages));
+        }
+        push_sentence(&mut body, &part);
+    }
+    match (escaped(&entry.publisher), year.is_none().then_some(&entry.year)) {
+        (Some(publisher), Some(Some(year))) => {
+            push_sentence(&mut body, &format!("{}, {}", publisher, escape(year, filetype)))
+        }
+        (Some(publisher), _) => push_sentence(&mut body, &publisher),
+        (None, Some(Some(year))) => push_sentence(&mut body, &escape(year, filetype)),
+        (None, _) => {}
+    }
+    match (&entry.doi, &entry.url) {
+        (Some(doi), _) if doi.starts_with("http") => push_sentence(&mut body, &url(doi, filetype)),
+        (Some(doi), _) => push_sentence(&mut body, &url(&format!("https://doi.org/{}", doi), filetype)),
+        (None, Some(address)) => push_sentence(&mut body, &url(address, filetype)),
+        (None, None) => {}
+    }
+    body.truncate(body.trim_end().len());
+    body
+}
+
+// e.g. "Capper, Daniel, Ann Smith, and Bob Jones"
+fn names(names: &[Name]) -> String {
+    let formatted = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match (&name.given, i) {
+            (Some(given), 0) => format!("{}, {}", name.family, given),
+            (Some(given), _) => format!("{} {}", given, name.family),
+            (None, _) => name.family.clone(),
+        })
+        .collect::<Vec<_>>();
+    match formatted.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{}, and {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+fn push_sentence(body: &mut String, text: &str) {
+    if !text.is_empty() {
+        body.push_str(text);
+        if !ends_sentence(text) {
+            body.push('.');
+        }
+        body.push(' ');
+    }
+}
+
+fn ends_sentence(text: &str) -> bool {
+    text.ends_with(['.', '?', '!'])
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// The syntax of each filetype
+
+fn italic(text: &str, filetype: FileType) -> String {
+    match filetype {
+        FileType::Html => format!("<i>{}</i>", text),
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => format!("*{}*", text),
+        FileType::AsciiDoctor => format!("_{}_", text),
+        FileType::LaTeX | FileType::Pdf => format!("\\textit{{{}}}", text),
+        FileType::Default => text.to_string(),
+    }
+}
+
+fn url(address: &str, filetype: FileType) -> String {
+    match filetype {
+        FileType::Html => {
+            let address = escape(address, filetype);
+            format!("<a href=\"{0}\">{0}</a>", address)
+        }
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => format!("<{}>", address),
+        FileType::LaTeX | FileType::Pdf => format!("\\url{{{}}}", address),
+        FileType::AsciiDoctor | FileType::Default => address.to_string(),
+    }
+}
+
+// Html gives the id to the paragraph of the reference instead
+fn anchor(id: &str, filetype: FileType) -> String {
+    match filetype {
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown | FileType::Html => {
+            format!("<span id=\"{}\"></span>", id)
+        }
+        FileType::AsciiDoctor => format!("[[{}]]", id),
+        FileType::LaTeX | FileType::Pdf => format!("\\hypertarget{{{}}}{{}}", id),
+        FileType::Default => String::new(),
+    }
+}
+
+fn link(id: &str, text: &str, filetype: FileType) -> String {
+    match filetype {
+        FileType::Html => format!("<a href=\"#{}\">{}</a>", id, text),
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => format!("[{}](#{})", text, id),
+        FileType::AsciiDoctor => format!("<<{},{}>>", id, text),
+        FileType::LaTeX | FileType::Pdf => format!("\\hyperlink{{{}}}{{{}}}", id, text),
+        FileType::Default => text.to_string(),
+    }
+}
@@ -1,25 +1,39 @@
 // This default flavour of this templating markup language. If you want
 // to implement your own flavour (i.e. with your own functions), you should
-// be able to copy this file directly.
+// be able to copy this file directly. If you only want to add or swap out a
+// few functions, build on 'default_context()' instead (see "run/compose.rs").
 
 //run: cargo test -- --nocapture
 
 use std::borrow::Cow;
 use std::fs;
+use std::path::{Path, PathBuf};
 
 use common::FileType;
 
 // Do not use super so that if others want to make their own flavour, they
 // can copy this file without issue
-use crate::run::{Bindings, Dirty, Error, PureResult, StatefulResult};
-use crate::run::{Value, Variables};
+use crate::run::{Bindings, Dirty, Error, ParamDef, PureResult, Render, StatefulResult};
+use crate::run::Value;
 
-use crate::run::utility::{shell, concat, env};
+use crate::run::utility::{shell, concat, env, no, yes};
 use crate::run::utility::{fetch_env_var, run_command};
 use crate::run::value as v;
 use crate::run::{LIMITED, UNLIMITED}; // these are just bools
+use crate::typed_function;
 
 use crate::api::Api;
+use crate::Source;
+use crate::bibliography::Citation;
+use crate::highlight::highlight;
+
+mod citations;
+mod figures;
+mod footnotes;
+mod includes;
+mod numbering;
+mod tables;
+mod toc;
 
 // The main difference between pure and stateful functions is that
 // * pure functions run only once (once all their arguments are ready) and
@@ -32,43 +46,146 @@ use crate::api::Api;
 // * reference to function definition
 // * a enum (effectively a bool) that specifies whether to check the number
 //   of arguments or not
-// * a list for what types of arguments the function expects
+// * a list for what types of arguments the function expects, or a 'ParamDef'
+//   for optional, rest and union-typed parameters
+//
+// Functions defined with 'typed_function!' derive the last two from their
+// declaration, so they are registered with just a name and the function
 pub fn default_context<'a>() -> Bindings<'a, CustomKey, CustomValue> {
     let mut ctx = Bindings::new();
     ctx.register_pure_function("env", &env, LIMITED, &[v::TEXT]);
-    ctx.register_pure_function("include", &include, UNLIMITED, &[v::TEXT]);
+    ctx.register_typed_pure_function("include", include {});
+    ctx.register_pre_lex_hook(includes::expand);
 
-    // "r/run <lang> <code-body>"
-    ctx.register_pure_function("run", &shell, UNLIMITED, &[v::TEXT, v::TEXT]);
+    // "r/run <lang> <args>... <code-body>"
+    let run_params = || ParamDef::new().required(v::TEXT).rest(v::TEXT).required(v::TEXT);
+    ctx.register_pure_function_with("run", &shell, run_params());
     //ctx.register_pure_function("r", &shell, LIMITED, &[v::TEXT, v::TEXT]);
-    ctx.register_pure_function(
-        "if_equals",
-        &if_eq_statement,
-        LIMITED,
-        &[v::TEXT, v::TEXT, v::TEXT],
-    );
-    ctx.register_pure_function(
+    // "figure <lang> <args>... <code-body>", writing the output to a file
+    let figure_params = run_params().keywords_from(figures::KEYWORDS);
+    ctx.register_pure_function_with("figure", &figures::figure, figure_params);
+    ctx.register_typed_pure_function("if_equals", if_eq_statement {});
+    ctx.register_pure_function_with(
         "run_if_equals",
-        &run_if_equals,
-        LIMITED,
-        &[v::TEXT, v::TEXT, v::TEXT, v::TEXT],
-    );
-    ctx.register_pure_function(
-        "run_env",
-        &run_env,
-        LIMITED,
-        &[v::TEXT, v::TEXT, v::TEXT, v::TEXT],
+        &run_if_equals {},
+        ParamDef::new().required(v::TEXT).required(v::TEXT).required(v::TEXT).rest(v::TEXT).required(v::TEXT),
     );
+    ctx.register_typed_pure_function("run_env", run_env {});
 
-    ctx.register_pure_function("syntax_highlight", &syntax_highlight, LIMITED, &[v::TEXT, v::TEXT]);
-    ctx.register_pure_function("highlight",        &syntax_highlight, LIMITED, &[v::TEXT, v::TEXT]);
+    ctx.register_typed_pure_function("syntax_highlight", syntax_highlight {});
+    ctx.register_typed_pure_function("highlight",        syntax_highlight {});
     ctx.register_pure_function("concat", &concat, UNLIMITED, &[]);
     ctx.register_pure_function("end", &concat, LIMITED, &[v::TEXT]);
-    ctx.register_stateful_function("cite", &cite, LIMITED, &[v::TEXT]);
-    ctx.register_stateful_function("references", &references, LIMITED, &[]);
-
-    ctx.register_stateful_function("label_set", &label_set, LIMITED, &[v::TEXT, v::TEXT]);
-    ctx.register_stateful_function("label", &label, LIMITED, &[v::TEXT]);
+    ctx.register_pure_function("true", &yes, LIMITED, &[]);
+    ctx.register_pure_function("false", &no, LIMITED, &[]);
+    let cite_params = ParamDef::new().required(v::TEXT).rest(v::TEXT).keywords_from(citations::KEYWORDS);
+    ctx.register_collector("cite", Some("references"), CustomKey::Citations, citations::Cite, cite_params);
+    // These configure 'cite' from anywhere in the document (see "default_markup/citations.rs")
+    let paths_params = ParamDef::new().required(v::TEXT).rest(v::TEXT);
+    ctx.register_collector("bibliography", None, CustomKey::Citations, citations::Setting("bibliography"), paths_params);
+    ctx.register_collector("csl", None, CustomKey::Citations, citations::Setting("csl"), ParamDef::new().required(v::TEXT));
+    ctx.register_collector("citation_locale", None, CustomKey::Citations, citations::Setting("citation_locale"), ParamDef::new().required(v::TEXT));
+
+    ctx.register_typed_stateful_function("label_set", label_set {});
+    ctx.register_typed_stateful_function("label", label {});
+
+    // Same key so that they share their items, see "default_markup/numbering.rs"
+    let number_params = ParamDef::new().required(v::TEXT).optional(v::TEXT);
+    ctx.register_collector("number", None, CustomKey::Numbering, numbering::Number, number_params);
+    let ref_params = ParamDef::new().required(v::TEXT);
+    ctx.register_collector("ref", None, CustomKey::Numbering, numbering::Reference, ref_params);
+
+    // Same key so that each listing knows which notes are its own
+    let footnote_params = ParamDef::new().required(v::TEXT);
+    ctx.register_collector("footnote", None, CustomKey::Footnotes, footnotes::Footnotes, footnote_params);
+    ctx.register_collector("footnotes", None, CustomKey::Footnotes, footnotes::Footnotes, ParamDef::new());
+
+    let table_params = ParamDef::new().required(v::TEXT).keywords_from(tables::KEYWORDS);
+    ctx.register_pure_function_with("table", &tables::table, table_params);
+
+    ctx.register_typed_pure_function("toc", toc::toc {});
+    ctx.register_post_knit_hook_for("toc", toc::fill_in_toc);
+
+    // For `tetra-cli functions`. Parameter names of typed functions are
+    // already filled in by their declaration.
+    ctx.document("env", "The value of the environment variable {name}", &["name"], &[
+        "{$ env \"HOME\" $}",
+    ]);
+    ctx.document("include", "Runs the file at {path}, relative to the file that includes it, as part of the document. Computed paths (e.g. from 'env') are output as is instead. Only includes the {section} with that heading, the {lines} (e.g. \"10-40\"), or the lines between the comments \"tag::{tag}[]\" and \"end::{tag}[]\" if given", &["path"], &[
+        "{$ include \"chapter1.md\" $}",
+        "{$ include \"notes.md\", section: \"Results\" $}",
+        "{$ include \"notes.md\", lines: \"10-40\" $}",
+    ]);
+    ctx.document("run", "Runs the program {cmd} with any extra arguments, passing the last argument as STDIN. Binary outputs (e.g. a PNG) are embedded as images in HTML", &["cmd", "args", "body"], &[
+        "{| run \"sh\" |}echo hello",
+        "{$ run \"python3\", \"-c\", \"print(1 + 1)\", \"\" $}",
+    ]);
+    ctx.document("figure", "Runs the program {cmd} like 'run', writes its output (e.g. an SVG or PNG) to the assets directory, and shows it as a figure with the {caption} and {label} given. The {extension} of the file is detected unless given", &["cmd", "args", "body"], &[
+        "{| figure \"dot\", \"-Tsvg\", caption: \"The pipeline\", label: \"fig:pipeline\" |}digraph { a -> b }",
+        "{$ figure \"dot\", \"-Tpng\", concat(\"digraph { \", env(\"USER\"), \" }\"), caption: \"Built by\" $}",
+    ]);
+    ctx.document("if_equals", "{contents} if {lvalue} and {rvalue} are the same, otherwise nothing", &[], &[
+        "{| if_equals env(\"LANG\"), \"C\" |}Only in the C locale",
+    ]);
+    ctx.document("run_if_equals", "Same as 'run' but only if {lvalue} and {rvalue} are the same", &["lvalue", "rvalue", "cmd", "args", "body"], &[]);
+    ctx.document("run_env", "Same as 'run' but with the environment variable {id} set to {rvalue}. Supports 'sh' and 'dot'", &[], &[]);
+    ctx.document("syntax_highlight", "Highlights {code} as {lang} (e.g. \"sh\", \"rust\", \"python\" or \"dot\") for the output filetype, or with pygmentize if $HIGHLIGHT_BACKEND is \"pygmentize\"", &[], &[
+        "{| syntax_highlight \"rust\" |}fn main() {}",
+    ]);
+    ctx.document("highlight", "Alias of 'syntax_highlight'", &[], &[]);
+    ctx.document("concat", "Joins all its arguments together", &[], &[
+        "{$ concat \"a\", \"b\" $}",
+    ]);
+    ctx.document("end", "Outputs {text} as is", &["text"], &[]);
+    ctx.document("true", "The boolean true, e.g. for keyword arguments", &[], &[
+        "{$ cite \"capper2012\", narrative: true $}",
+    ]);
+    ctx.document("false", "The boolean false", &[], &[]);
+    ctx.document("cite", "An in-text citation of the works {citekey} from the 'bibliography', linked to their entries under 'references'. Formatted by pandoc, or natively if $CITATION_BACKEND is \"native\". A single {citekey} can also be in pandoc's syntax, e.g. \"[@a; @b]\", or \"@a\" for narrative", &["citekey"], &[
+        "{$ cite \"capper2012\" $}",
+        "{$ cite \"capper2012\", \"smith2001\", page: \"12\", prefix: \"see\" $}",
+        "{$ cite \"capper2012\", narrative: true $}",
+        "As Capper puts it {$ cite \"capper2012\", suppress_author: true $}",
+    ]);
+    ctx.document("bibliography", "The BibTeX or CSL-JSON files to 'cite' from, relative to the document. Defaults to the front matter attribute \"bibliography\" then $BIBLIOGRAPHY", &["path"], &[
+        "{$ bibliography \"refs.bib\", \"more.json\" $}",
+    ]);
+    ctx.document("csl", "The CSL file, or \"author-date\" or \"numeric\", to format citations with. Defaults to the front matter attribute \"csl\" then $CITATION_STYLE", &["style"], &[
+        "{$ csl \"apa.csl\" $}",
+        "{$ csl \"numeric\" $}",
+    ]);
+    ctx.document("citation_locale", "The language to format citations in, e.g. \"en-GB\". Defaults to the front matter attribute \"lang\"", &["locale"], &[
+        "{$ citation_locale \"de-DE\" $}",
+    ]);
+    ctx.document("references", "The bibliography listing of everything that has been cited", &[], &[
+        "{$ references $}",
+    ]);
+    ctx.document("label_set", "Sets the label {label_name} to {label} so it can be referenced anywhere with 'label'", &[], &[
+        "{$ label_set \"fig1\", \"Figure 1\" $}",
+    ]);
+    ctx.document("label", "The text set by 'label_set' for {label_name}, even if set later in the document", &[], &[
+        "{$ label \"fig1\" $}",
+    ]);
+    ctx.document("number", "Numbers this {kind} (e.g. \"figure\") in document order, as the target of 'ref' for {label}", &["kind", "label"], &[
+        "{$ number \"figure\", \"fig:pipeline\" $}: The pipeline",
+    ]);
+    ctx.document("ref", "A link to the 'number' with {label}, e.g. \"Figure 3\", even if it comes later", &["label"], &[
+        "See {$ ref \"fig:pipeline\" $}",
+    ]);
+    ctx.document("footnote", "A numbered marker for the footnote {note}, or the footnote itself for AsciiDoctor and LaTeX", &["note"], &[
+        "Tetra{$ footnote \"A templating language\" $} is",
+    ]);
+    ctx.document("footnotes", "The footnotes since the previous call (the last call also lists those after it), for output filetypes that do not place them automatically, where it is needed if there are any footnotes", &[], &[
+        "{$ footnotes $}",
+    ]);
+    ctx.document("table", "The CSV or JSON (a list of objects) file at {path} as a table in the output filetype, with only the {columns} given, renamed to the {headers} given, and aligned by {align} (\"l\", \"c\" or \"r\" for each column or all of them)", &["path"], &[
+        "{$ table \"results.csv\" $}",
+        "{$ table \"results.json\", columns: \"name, score\", headers: \"Name, Score\", align: \"lr\" $}",
+    ]);
+    ctx.document("toc", "A linked table of contents of the headings up to level {depth} (default 3), including those output by cells", &[], &[
+        "{$ toc $}",
+        "{$ toc \"2\" $}",
+    ]);
     ctx
 }
 
@@ -76,245 +193,162 @@ pub fn default_context<'a>() -> Bindings<'a, CustomKey, CustomValue> {
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum CustomKey {
     Citations,
-    CiteCount,
-    CiteState,
+    Footnotes,
+    Numbering,
     Label(String),
 }
 
 #[derive(Clone, Debug)]
 pub enum CustomValue {
-    CiteList(Vec<String>),
-    Citation(usize),
+    Citation(Citation),
 }
 
-////////////////////////////////////////////////////////////////////////////////
-
-// The citations (e.g. parenthetical references) and references (bibliography
-// listing full form citations) functions.
-
-// Counts the citations on the first pass (for `Vec::with_capacity()`,
-// accumulates all the citations on the second pass, pass that to pandoc
-// and then prints out the citations
-fn cite<'a>(
-    args: &[Value<'a, CustomValue>],
-    api: Api<'a>,
-    old_output: Value<'a, CustomValue>,
-    storage: &mut Variables<'a, CustomKey, CustomValue>,
-) -> StatefulResult<'a, CustomValue> {
-    if args.len() != 1 {
-        panic!();
-    }
-    //println!("* {:?} {:?}", &old_output, storage.get(&CustomKey::Citations));
-    let old_state = storage
-        .get(&CustomKey::CiteState)
-        .map(|v| unwrap!(unreachable v => Value::Usize(x) => *x))
-        .unwrap_or(0);
-
-    // Determine what the state is for our FSM
-    // {old_output} determines what pass (epoch/iteration) we are on
-    let (state, id) = match (old_state, &old_output) {
-        // All 'cite' calls first pass => count citations
-        (0, Value::Null) => (0, 0),
-
-        // First cite call, second pass => create citekey list
-        (0, Value::Usize(i)) => (1, *i),
-
-        // Other cite calls, second pass => just append to citekey list
-        (1 | 2, Value::Usize(i)) => (2, *i),
-
-        // First cite call, third pass => run pandoc
-        (1 | 2, Value::Custom(CustomValue::Citation(i))) => (3, *i),
-
-        // Other cite calls, third pass => just output the citation
-        (3 | 4, Value::Custom(CustomValue::Citation(i))) => (4, *i),
-
-        _ => unreachable!("{} {:?}", old_state, &old_output),
-    };
-    storage.insert(CustomKey::CiteState, Value::Usize(state));
-
-    // Init data structures
-    if state == 1 {
-        //let cite_count = storage.get(&CustomKey::CiteCount)
-        //    .map(|v| unwrap!(or_invalid v => Usize(x) => *x))
-        //    .unwrap_or(Ok(0))?;
-
-        // @TODO: String::with_capacity
-        storage.insert(CustomKey::Citations, Value::Text(Cow::Owned(String::new())));
-    } else if state == 3 {
-        let citekeys_value = storage.get(&CustomKey::Citations).unwrap();
-        let citekeys = unwrap!(unreachable citekeys_value => Value::Text(s) => s);
-        let citerefs = pandoc_cite(citekeys, &api.meta.output_filetype)?;
-        storage.insert(CustomKey::Citations, Value::Text(Cow::Owned(citerefs)));
-    }
-
-    //println!("| cite step {:?}", step);
-    match state {
-        0 => {
-            let cite_count = storage
-                .get(&CustomKey::CiteCount)
-                .map(|v| unwrap!(or_invalid v => Value::Usize(x) => *x))
-                .unwrap_or(Ok(0))?;
-            storage.insert(CustomKey::CiteCount, Value::Usize(cite_count + 1));
-            Ok((Dirty::Waiting, Value::Usize(cite_count)))
-        }
-        1 | 2 => {
-            let list_value = storage.get_mut(&CustomKey::Citations).unwrap();
-            let list: &mut String =
-                unwrap!(unreachable list_value => Value::Text(Cow::Owned(s)) => s);
-            list.push_str(unwrap!(unreachable &args[0] => Value::Text(s) => s));
-            list.push('\n');
-            list.push('\n');
-            Ok((Dirty::Waiting, Value::Custom(CustomValue::Citation(id))))
+// Only reaches the output if something else (e.g. 'concat') consumes it
+// before 'cite' is done
+impl Render for CustomValue {
+    fn render(&self, filetype: FileType) -> Cow<'_, str> {
+        match (self, filetype) {
+            (CustomValue::Citation(citation), FileType::LaTeX) => {
+                Cow::Owned(format!("\\cite{{{}}}", citation.keys.join(",")))
+            }
+            (
+                CustomValue::Citation(citation),
+                FileType::Markdown | FileType::RMarkdown | FileType::CommonMark,
+            ) => Cow::Owned(citation.to_pandoc()),
+            (CustomValue::Citation(citation), _) => Cow::Owned(citation.keys.join(", ")),
         }
-        3 | 4 => {
-            let citerefs = storage.get_mut(&CustomKey::Citations).unwrap();
-            let citerefs = unwrap!(unreachable citerefs => Value::Text(s) => s);
-            let citation = citerefs.split("\n\n").nth(id).unwrap().to_string();
-            Ok((Dirty::Ready, Value::Text(Cow::Owned(citation))))
-        }
-
-        _ => unreachable!(),
     }
 }
 
-fn references<'a>(
-    args: &[Value<'a, CustomValue>],
-    _api: Api<'a>,
-    _: Value<'a, CustomValue>,
-    storage: &mut Variables<'a, CustomKey, CustomValue>,
-) -> StatefulResult<'a, CustomValue> {
-    assert_eq!(0, args.len());
-
-    let state = storage
-        .get(&CustomKey::CiteState)
-        .map(|v| unwrap!(unreachable v => Value::Usize(x) => *x))
-        .unwrap_or(0);
-    match state {
-        0 | 1 | 2 => Ok((Dirty::Waiting, Value::Text(Cow::Borrowed("")))),
-        3 | 4 => {
-            let cite_count = storage
-                .get(&CustomKey::CiteCount)
-                .map(|v| unwrap!(unreachable v => Value::Usize(x) => *x))
-                .unwrap_or(0);
-
-            let citerefs = storage.get_mut(&CustomKey::Citations).unwrap();
-            let citerefs = unwrap!(unreachable citerefs => Value::Text(s) => s);
-            //println!("citerefs {}", citerefs);
-            let ref_start = citerefs.split("\n\n").nth(cite_count).unwrap().as_ptr();
-            let references = &citerefs[ref_start as usize - citerefs.as_ptr() as usize..];
-
-            Ok((
-                Dirty::Ready,
-                Value::Text(Cow::Owned(references.to_string())),
-            ))
-        }
-        _ => unreachable!(),
-    }
-}
+////////////////////////////////////////////////////////////////////////////////
 
-pub fn pandoc_cite(citekey: &str, filetype: &FileType) -> Result<String, Error> {
-    let bibliography = fetch_env_var("BIBLIOGRAPHY")?;
-    let write_format = match filetype {
-        FileType::AsciiDoctor => "asciidoctor",
-        FileType::CommonMark => "commonmark",
-        FileType::Markdown => "markdown_strict",
-        FileType::RMarkdown => "markdown_strict",
-        FileType::Pdf => panic!(),
-        FileType::LaTeX => "latex",
-        FileType::Html => "html5",
-        FileType::Default => "plain",
+typed_function! {
+// Syntax highlight, natively (see "highlight.rs") unless $HIGHLIGHT_BACKEND
+// is "pygmentize"
+pub fn syntax_highlight<'a, V>(api: Api<'a>; lang: &str, code: &str) -> PureResult<'a, V> {
+    let filetype = api.meta.output_filetype;
+    let output = match fetch_env_var("HIGHLIGHT_BACKEND").ok().as_deref() {
+        Some("native") | None => {
+            highlight(lang, code, filetype).map_err(|err| Error::Arg(0, Cow::Owned(err)))?
+        }
+        Some("pygmentize") => pygmentize(lang, code, filetype)?,
+        Some(backend) => {
+            return Err(Error::Generic(Cow::Owned(format!(
+                "$HIGHLIGHT_BACKEND is {:?}. Expected \"native\" or \"pygmentize\"",
+                backend
+            ))))
+        }
     };
-    let citation = run_command(
-        "pandoc",
-        Some(citekey),
-        //&["--citeproc", "-M", "suppress-bibliography=true", "-t", "plain",
-        &[
-            "--citeproc",
-            "-t",
-            write_format,
-            "--bibliography",
-            &bibliography,
-        ],
-        None,
-    )?;
-
-    Ok(citation)
+    Ok(Value::Text(Cow::Owned(output)))
+}
 }
 
-////////////////////////////////////////////////////////////////////////////////
-
-// Syntax highlight
-pub fn syntax_highlight<'a, V>(args: &[Value<'a, V>], api: Api<'a>) -> PureResult<'a, V> {
-    let lang: &str = unwrap!(unreachable &args[0] => Value::Text(s) => s);
-    let code: &str = unwrap!(unreachable &args[1] => Value::Text(s) => s);
-    let output_format = match &api.meta.output_filetype {
+fn pygmentize(lang: &str, code: &str, filetype: FileType) -> Result<String, Error> {
+    let output_format = match filetype {
         FileType::AsciiDoctor => "html",
         FileType::CommonMark => "html",
         FileType::Markdown => "html",
         FileType::RMarkdown => "html",
-        FileType::Pdf => panic!(),
+        FileType::Pdf => "latex",
         FileType::LaTeX => "latex",
         FileType::Html => "html",
-        FileType::Default => "html",
+        FileType::Default => "terminal",
     };
-    let output = run_command("pygmentize", Some(code), &[
+    run_command("pygmentize", Some(code), &[
         "-l", lang,
         "-f", output_format,
-    ], None)?;
-
-    Ok(Value::Text(Cow::Owned(output)))
+    ], None)
 }
 
-// includes other files into the current file
-// @TODO: add ability to parse those files as well
-pub fn include<'a, V>(args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V> {
-    let path: &str = unwrap!(unreachable &args[0] => Value::Text(s) => s);
-    let contents = fs::read_to_string(path).map_err(|err| {
+// Includes other files into the current file. Those with a quoted path are
+// spliced in and run before this is ever called (see "default_markup/includes.rs")
+typed_function! {
+pub fn include<'a, V>(
+    api: Api<'a>;
+    path: &str;
+    section: Option<&str>,
+    lines: Option<&str>,
+    tag: Option<&str>,
+) -> PureResult<'a, V> {
+    let keywords = [("section", section), ("lines", lines), ("tag", tag)];
+    let keywords = keywords.into_iter().filter_map(|(key, value)| Some((key, value?)));
+    let fragment = includes::Fragment::new(keywords).map_err(|err| Error::Generic(Cow::Owned(err)))?;
+
+    let path = relative_to_call(&api, path);
+    let contents = fs::read_to_string(&path).map_err(|err| {
         Error::Arg(
             0,
             Cow::Owned(format!("Could not read file {:?}: {}", path, err)),
         )
     })?;
-    //let mut buffer = String::with_capacity(recursive_calc_length(args)?);
-    //recursive_concat::<V>(args, &mut buffer);
-    Ok(Value::Text(Cow::Owned(contents)))
+    let contents = fragment
+        .of(&path, &contents)
+        .map_err(|err| Error::Generic(Cow::Owned(err)))?;
+    Ok(Value::Text(Cow::Owned(contents.into_owned())))
+}
+}
+
+// Paths in a document are relative to it, or to where tetra is run if it
+// has no path (e.g. it is read from STDIN)
+fn relative_to<P: AsRef<Path>>(document: Option<&Path>, path: P) -> PathBuf {
+    match document.and_then(Path::parent) {
+        Some(directory) => directory.join(path),
+        None => path.as_ref().to_path_buf(),
+    }
+}
+
+// Same as 'relative_to()' for the file that the call of {api} is in, which
+// may be an included file
+fn relative_to_call<P: AsRef<Path>>(api: &Api, path: P) -> PathBuf {
+    let Source::Range(start, _) = api.span();
+    let document = match api.meta.splice_at(*start) {
+        Some(splice) => Some(splice.path.as_path()),
+        None => api.meta.input_path.as_deref(),
+    };
+    relative_to(document, path)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-// includes other files into the current file
-// @TODO: add ability to parse those files as well
-pub fn if_eq_statement<'a, V>(args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V> {
-    let lvalue: &str = unwrap!(unreachable &args[0] => Value::Text(s) => s);
-    let rvalue: &str = unwrap!(unreachable &args[1] => Value::Text(s) => s);
+typed_function! {
+pub fn if_eq_statement<'a, V>(
+    _api: Api<'a>;
+    lvalue: &str,
+    rvalue: &str,
+    contents: &str,
+) -> PureResult<'a, V> {
     if lvalue == rvalue {
-        let contents: &str = unwrap!(unreachable &args[2] => Value::Text(s) => s);
         Ok(Value::Text(Cow::Owned(contents.to_string())))
     } else {
         Ok(Value::Text(Cow::Borrowed("")))
     }
 }
+}
 
-pub fn run_if_equals<'a, V>(args: &[Value<'a, V>], api: Api<'a>) -> PureResult<'a, V> {
-    let lvalue: &str = unwrap!(unreachable &args[0] => Value::Text(s) => s);
-    let rvalue: &str = unwrap!(unreachable &args[1] => Value::Text(s) => s);
+typed_function! {
+pub fn run_if_equals<'a, V>(api: Api<'a>; lvalue: &str, rvalue: &str, ..command) -> PureResult<'a, V> {
     if lvalue == rvalue {
-        Ok(shell(&args[2..], api)?)
+        // Errors are about the arguments of {command}, i.e. from the third
+        shell(command, api).map_err(|err| match err {
+            Error::Arg(i, msg) => Error::Arg(i + 2, msg),
+            err => err,
+        })
     } else {
         Ok(Value::Text(Cow::Borrowed("")))
     }
 }
+}
 
 ////////////////////////////////////////////////////////////////////////////////
 
+typed_function! {
 fn label_set<'a>(
-    args: &[Value<'a, CustomValue>],
     _api: Api<'a>,
-    _: Value<'a, CustomValue>,
-    storage: &mut Variables<'a, CustomKey, CustomValue>,
+    _old_output: Value<'a, CustomValue>,
+    storage: &mut Variables<'a, CustomKey, CustomValue>;
+    label_name: &str,
+    label: Cow<'a, str>,
 ) -> StatefulResult<'a, CustomValue> {
-    let label_name: &str = unwrap!(unreachable &args[0] => Value::Text(s) => s);
-    let label: Cow<str> = unwrap!(unreachable &args[1] => Value::Text(s) => s.clone());
     let label_key = CustomKey::Label(label_name.to_string());
     match storage.get(&label_key) {
         Some(_) => Err(Error::Arg(
@@ -322,18 +356,20 @@ fn label_set<'a>(
             Cow::Owned(format!("The label {:?} has already been set", label_name)),
         )),
         None => {
-            storage.insert(label_key, Value::Text(label.clone()));
+            storage.insert_shared(label_key, Value::Text(label.clone()));
             Ok((Dirty::Ready, Value::Text(label)))
         }
     }
 }
+}
+
+typed_function! {
 fn label<'a>(
-    args: &[Value<'a, CustomValue>],
     _api: Api<'a>,
     old_output: Value<'a, CustomValue>,
-    storage: &mut Variables<'a, CustomKey, CustomValue>,
+    storage: &mut Variables<'a, CustomKey, CustomValue>;
+    label_name: &str,
 ) -> StatefulResult<'a, CustomValue> {
-    let label_name: &str = unwrap!(unreachable &args[0] => Value::Text(s) => s);
     let label = storage.get(&CustomKey::Label(label_name.to_string()));
 
     match (old_output, label)  {
@@ -351,15 +387,19 @@ fn label<'a>(
         _ => unreachable!(),
     }
 }
+}
 
 ////////////////////////////////////////////////////////////////////////////////
 
 // Same as `code()` but allows you set the environment variables
-pub fn run_env<'a, V>(args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V> {
-    let id: &str = unwrap!(unreachable &args[0] => Value::Text(s) => s);
-    let rvalue: &str = unwrap!(unreachable &args[1] => Value::Text(s) => s);
-    let lang: &str = unwrap!(unreachable &args[2] => Value::Text(s) => s);
-    let cell_body: &str = unwrap!(unreachable &args[3] => Value::Text(s) => s);
+typed_function! {
+pub fn run_env<'a, V>(
+    _api: Api<'a>;
+    id: &str,
+    rvalue: &str,
+    lang: &str,
+    cell_body: &str,
+) -> PureResult<'a, V> {
 
     match lang {
         "graphviz" | "dot" => {
@@ -370,6 +410,7 @@ pub fn run_env<'a, V>(args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V>
         "sh" => run_command("sh", Some(cell_body), &["-s"], Some(vec![(id, rvalue)]))
             .map(Cow::Owned)
             .map(Value::Text),
-        s => todo!("markup.rs: {}", s),
+        s => Err(Error::Arg(2, Cow::Owned(format!("{:?} is not supported. Expected \"sh\" or \"dot\"", s)))),
     }
 }
+}
@@ -0,0 +1,402 @@
+//run: cargo test -- --nocapture
+
+// Citations
+//
+// `{$ cite "capper2012", "smith2001", page: "12", prefix: "see" $}` outputs an
+// in-text citation (a 'crate::bibliography::Citation', given to pandoc in its
+// own syntax) linked to its entry in `{$ references $}`, the bibliography
+// listing of everything cited. All the citations are formatted at once so
+// that they can be numbered and disambiguated, by pandoc or, if
+// $CITATION_BACKEND is "native", by 'crate::bibliography', which does not
+// need pandoc.
+//
+// The document sets what they are formatted with, from anywhere in it
+// * `{$ bibliography "refs.bib", "more.json" $}`, or else the front matter
+//   attribute "bibliography" (e.g. "[refs.bib, more.json]"), or else
+//   $BIBLIOGRAPHY (a list of paths like $PATH)
+// * `{$ csl "apa.csl" $}`, or else "csl", or else $CITATION_STYLE. Besides
+//   CSL files, this takes the names of the native styles, "author-date" (the
+//   default) and "numeric". The native backend reads which of the two a CSL
+//   file is, but otherwise ignores it
+// * `{$ citation_locale "en-GB" $}`, or else "lang". The native backend is
+//   English only
+// Paths set by the document are relative to the file that sets them, which
+// may be an included file.
+//
+// The settings are collectors (see "run/function.rs") sharing the key of
+// 'cite', so all of them are known before any citation is formatted.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::FileType;
+
+use super::{relative_to, relative_to_call, CustomValue};
+use crate::api::Api;
+use crate::bibliography::{Bibliography, Citation, Style};
+use crate::run::utility::{fetch_env_var, run_command};
+use crate::run::value::{BOOL, TEXT};
+use crate::run::{Batch, Collect, Error, Keywords, Types, Value};
+
+// The keyword parameters of 'cite', shared with its registration
+pub const KEYWORDS: &[(&str, Types)] = &[
+    ("page", Types::one(TEXT)),
+    ("prefix", Types::one(TEXT)),
+    ("suppress_author", Types::one(BOOL)),
+    ("narrative", Types::one(BOOL)),
+];
+
+pub struct Cite;
+// Named after the function it is registered as, e.g. "csl"
+pub struct Setting(pub &'static str);
+
+impl Collect<CustomValue> for Cite {
+    // The citekeys then the {KEYWORDS}
+    fn item<'a>(
+        &self,
+        args: &[Value<'a, CustomValue>],
+        _: Api<'a>,
+    ) -> Result<Value<'a, CustomValue>, Error> {
+        let (citekeys, keywords) = Keywords::split(args, KEYWORDS);
+        let mut citation = Citation::default();
+        for (i, citekey) in citekeys.iter().enumerate() {
+            let citekey = unwrap!(unreachable citekey => Value::Text(s) => s);
+            let parsed = Citation::parse(citekey).map_err(|err| Error::Arg(i, Cow::Owned(err)))?;
+            // Only a lone "@a" is narrative, e.g. not `cite "@a", "@b"`
+            citation.is_narrative = citekeys.len() == 1 && parsed.is_narrative;
+            citation.keys.extend(parsed.keys);
+        }
+
+        citation.page = keywords.text("page").map(String::from);
+        citation.prefix = keywords.text("prefix").map(String::from);
+        citation.suppress_author = matches!(keywords.get("suppress_author"), Value::Bool(true));
+        if let Value::Bool(is_narrative) = keywords.get("narrative") {
+            citation.is_narrative = *is_narrative;
+        }
+        if citation.suppress_author && citation.is_narrative {
+            return Err(Error::Arg(
+                keywords.index("suppress_author"),
+                Cow::Borrowed("A narrative citation cannot leave out the authors"),
+            ));
+        }
+        Ok(Value::Custom(CustomValue::Citation(citation)))
+    }
+
+    fn batch<'a>(
+        &self,
+        items: Vec<Value<'a, CustomValue>>,
+        api: Api<'a>,
+    ) -> Result<Batch<'a, CustomValue>, Error> {
+        resolve(items, &api)
+    }
+}
+
+impl Collect<CustomValue> for Setting {
+    // A list of the name of the setting then its arguments, with paths made
+    // relative to the file that the call is in, which may be an included file
+    fn item<'a>(
+        &self,
+        args: &[Value<'a, CustomValue>],
+        api: Api<'a>,
+    ) -> Result<Value<'a, CustomValue>, Error> {
+        let mut list = vec![Value::Text(Cow::Borrowed(self.0))];
+        list.extend(args.iter().map(|arg| match (self.0, arg) {
+            ("bibliography", Value::Text(path)) => resolve_path(path, &api),
+            ("csl", Value::Text(style)) if style.ends_with(".csl") => resolve_path(style, &api),
+            _ => arg.clone(),
+        }));
+        Ok(Value::List(list))
+    }
+
+    fn batch<'a>(
+        &self,
+        items: Vec<Value<'a, CustomValue>>,
+        api: Api<'a>,
+    ) -> Result<Batch<'a, CustomValue>, Error> {
+        resolve(items, &api)
+    }
+}
+
+fn resolve<'a>(
+    items: Vec<Value<'a, CustomValue>>,
+    api: &Api<'a>,
+) -> Result<Batch<'a, CustomValue>, Error> {
+    let mut settings = Settings::default();
+    let mut citations = Vec::new();
+    for item in &items {
+        match item {
+            Value::Custom(CustomValue::Citation(citation)) => citations.push(citation),
+            Value::List(setting) => settings.set(setting)?,
+            _ => unreachable!(),
+        }
+    }
+
+    let (citations, references) = if citations.is_empty() {
+        (Vec::new(), String::new())
+    } else {
+        settings.fill_in_defaults(api)?;
+        match fetch_env_var("CITATION_BACKEND").ok().as_deref() {
+            Some("native") => native_cite(&citations, &settings, api.meta.output_filetype)?,
+            Some("pandoc") | None => {
+                let input = pandoc_input(&citations);
+                let output = pandoc_cite(&input, &settings, &api.meta.output_filetype)?;
+                split_pandoc_output(&output, citations.len())?
+            }
+            Some(backend) => {
+                return Err(Error::Generic(Cow::Owned(format!(
+                    "$CITATION_BACKEND is {:?}. Expected \"pandoc\" or \"native\"",
+                    backend
+                ))))
+            }
+        }
+    };
+
+    // The settings output nothing
+    let mut citations = citations.into_iter();
+    let slots = items
+        .iter()
+        .map(|item| match item {
+            Value::Custom(_) => Value::Text(Cow::Owned(citations.next().unwrap_or_default())),
+            _ => Value::Text(Cow::Borrowed("")),
+        })
+        .collect();
+    Ok(Batch { slots, aggregate: Value::Text(Cow::Owned(references)) })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default)]
+struct Settings {
+    bibliographies: Vec<PathBuf>,
+    style: Option<CitationStyle>,
+    locale: Option<String>,
+}
+
+enum CitationStyle {
+    Native(Style),
+    Csl(PathBuf),
+}
+
+impl Settings {
+    // From a call of a 'Setting'
+    fn set(&mut self, setting: &[Value<CustomValue>]) -> Result<(), Error> {
+        let mut args = setting
+            .iter()
+            .map(|arg| unwrap!(unreachable arg => Value::Text(s) => s.as_ref()));
+        let name = args.next().unwrap();
+        let is_set = match name {
+            // Already relative to the call (see 'Setting::item()')
+            "bibliography" => {
+                self.bibliographies.extend(args.map(PathBuf::from));
+                false
+            }
+            "csl" => self.style.replace(parse_style(args.next().unwrap(), None)?).is_some(),
+            _ => self.locale.replace(args.next().unwrap().to_string()).is_some(),
+        };
+        if is_set {
+            return Err(Error::Generic(Cow::Owned(format!(
+                "'{}' can only be called once per document",
+                name
+            ))));
+        }
+        Ok(())
+    }
+
+    // For those that the document did not set with functions
+    fn fill_in_defaults(&mut self, api: &Api) -> Result<(), Error> {
+        let attributes = &api.metadata().attributes;
+        if self.bibliographies.is_empty() {
+            if let Some(list) = attributes.get("bibliography") {
+                self.bibliographies = parse_list(list).map(|path| relative_to_document(path, api)).collect();
+            } else if let Ok(paths) = fetch_env_var("BIBLIOGRAPHY") {
+                self.bibliographies = std::env::split_paths(&paths).collect();
+            }
+        }
+        if self.bibliographies.is_empty() {
+            return Err(Error::Generic(Cow::Borrowed(
+                "There is no bibliography to cite from. Set one with 'bibliography', the front matter attribute \"bibliography\" or $BIBLIOGRAPHY",
+            )));
+        }
+
+        if self.style.is_none() {
+            self.style = match attributes.get("csl") {
+                Some(style) => Some(parse_style(style.trim_matches(['"', '\'']), Some(api))?),
+                None => match fetch_env_var("CITATION_STYLE") {
+                    Ok(style) => Some(parse_style(&style, None)?),
+                    Err(_) => None,
+                },
+            };
+        }
+        if self.locale.is_none() {
+            self.locale = attributes.get("lang").map(|lang| lang.trim_matches(['"', '\'']).to_string());
+        }
+        Ok(())
+    }
+}
+
+// Paths from the environment or already resolved are taken as they are,
+// hence the optional {api}
+fn parse_style(style: &str, api: Option<&Api>) -> Result<CitationStyle, Error> {
+    if let Some(style) = Style::from(style) {
+        Ok(CitationStyle::Native(style))
+    } else if style.ends_with(".csl") {
+        Ok(CitationStyle::Csl(match api {
+            Some(api) => relative_to_document(style, api),
+            None => PathBuf::from(style),
+        }))
+    } else {
+        Err(Error::Generic(Cow::Owned(format!(
+            "The citation style {:?} is not a CSL file (.csl), \"author-date\" or \"numeric\"",
+            style
+        ))))
+    }
+}
+
+// e.g. "[refs.bib, 'more.json']" or just "refs.bib"
+fn parse_list(list: &str) -> impl Iterator<Item = &str> {
+    let list = list.trim();
+    let list = list.strip_prefix('[').and_then(|s| s.strip_suffix(']')).unwrap_or(list);
+    list.split(',')
+        .map(|item| item.trim().trim_matches(['"', '\'']))
+        .filter(|item| !item.is_empty())
+}
+
+// For the front matter, which is always in the document itself
+fn relative_to_document(path: &str, api: &Api) -> PathBuf {
+    relative_to(api.meta.input_path.as_deref(), path)
+}
+
+fn resolve_path<'a>(path: &str, api: &Api) -> Value<'a, CustomValue> {
+    Value::Text(Cow::Owned(relative_to_call(api, path).display().to_string()))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn native_cite(
+    citations: &[&Citation],
+    settings: &Settings,
+    filetype: FileType,
+) -> Result<(Vec<String>, String), Error> {
+    let to_error = |err: String| Error::Generic(Cow::Owned(err));
+    if let Some(locale) = settings.locale.as_deref().filter(|locale| !locale.starts_with("en")) {
+        return Err(to_error(format!(
+            "The native citation backend only formats in English, not {:?}. Use pandoc instead",
+            locale
+        )));
+    }
+    let style = match &settings.style {
+        Some(CitationStyle::Native(style)) => *style,
+        Some(CitationStyle::Csl(path)) => csl_category(path).map_err(to_error)?,
+        None => Style::AuthorDate,
+    };
+
+    let mut bibliography = Bibliography::new();
+    for path in &settings.bibliographies {
+        bibliography.load(path).map_err(to_error)?;
+    }
+    let citations = citations.iter().map(|citation| (*citation).clone()).collect::<Vec<_>>();
+    bibliography.format(&citations, style, filetype).map_err(to_error)
+}
+
+// The native style closest to a CSL file, going by its category, e.g.
+// '<category citation-format="author-date"/>'
+fn csl_category(path: &Path) -> Result<Style, String> {
+    let csl = fs::read_to_string(path)
+        .map_err(|err| format!("Could not read the citation style {:?}. {}", path, err))?;
+    let format = csl
+        .split("citation-format=\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next());
+    match format {
+        Some("author-date" | "author") => Ok(Style::AuthorDate),
+        Some("numeric") => Ok(Style::Numeric),
+        Some(format) => Err(format!(
+            "{:?} is a {:?} style, which only pandoc supports",
+            path, format
+        )),
+        None => Err(format!("{:?} does not say its citation-format", path)),
+    }
+}
+
+// U+E000 is in the Private Use Area, so neither documents nor pandoc will
+// output it. Each citation is its own paragraph "\u{E000}{i}\u{E000}{citation}\u{E000}"
+// and the bibliography follows the paragraph "\u{E000}references\u{E000}", so
+// they are told apart whatever pandoc wraps them in (e.g. '<p>' for HTML)
+const SENTINEL: char = '\u{E000}';
+
+fn pandoc_input(citations: &[&Citation]) -> String {
+    let mut input = String::new();
+    for (i, citation) in citations.iter().enumerate() {
+        let citation = citation.to_pandoc();
+        input.push_str(&format!("{0}{1}{0}{2}{0}\n\n", SENTINEL, i, citation));
+    }
+    input.push_str(&format!("{0}references{0}\n\n", SENTINEL));
+    input
+}
+
+fn split_pandoc_output(output: &str, count: usize) -> Result<(Vec<String>, String), Error> {
+    let missing = || Error::Generic(Cow::Borrowed("pandoc did not output every citation"));
+    let mut rest = output;
+    let mut citations = Vec::with_capacity(count);
+    for i in 0..count {
+        let marker = format!("{0}{1}{0}", SENTINEL, i);
+        let start = rest.find(&marker).ok_or_else(missing)? + marker.len();
+        let close = rest[start..].find(SENTINEL).ok_or_else(missing)?;
+        citations.push(rest[start..start + close].to_string());
+        rest = &rest[start + close + SENTINEL.len_utf8()..];
+    }
+
+    // The rest of the line closes the paragraph of the marker
+    let marker = format!("{0}references{0}", SENTINEL);
+    let references = match rest.find(&marker) {
+        Some(start) => {
+            let rest = &rest[start + marker.len()..];
+            rest.find('\n').map_or("", |close| &rest[close + 1..])
+        }
+        None => return Err(missing()),
+    };
+    Ok((citations, references.trim_start_matches('\n').to_string()))
+}
+
+fn pandoc_cite(citekeys: &str, settings: &Settings, filetype: &FileType) -> Result<String, Error> {
+    let write_format = match filetype {
+        FileType::AsciiDoctor => "asciidoctor",
+        FileType::CommonMark => "commonmark",
+        FileType::Markdown => "markdown_strict",
+        FileType::RMarkdown => "markdown_strict",
+        // A PDF is compiled from LaTeX, as in the rest of the flavour
+        FileType::LaTeX | FileType::Pdf => "latex",
+        FileType::Html => "html5",
+        FileType::Default => "plain",
+    };
+
+    // Links each citation to its entry in the bibliography, "#ref-{citekey}"
+    let mut args = ["--citeproc", "-M", "link-citations=true", "-t", write_format]
+        .map(String::from)
+        .to_vec();
+    for path in &settings.bibliographies {
+        args.push("--bibliography".to_string());
+        args.push(path.to_string_lossy().into_owned());
+    }
+    match &settings.style {
+        // pandoc defaults to Chicago author-date
+        Some(CitationStyle::Native(Style::AuthorDate)) | None => {}
+        Some(CitationStyle::Native(Style::Numeric)) => {
+            return Err(Error::Generic(Cow::Borrowed(
+                "pandoc needs a CSL file for numeric citations, see 'csl'",
+            )))
+        }
+        Some(CitationStyle::Csl(path)) => {
+            args.push("--csl".to_string());
+            args.push(path.to_string_lossy().into_owned());
+        }
+    }
+    if let Some(locale) = &settings.locale {
+        args.push("-M".to_string());
+        args.push(format!("lang={}", locale));
+    }
+
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    run_command("pandoc", Some(citekeys), &args, None)
+}
@@ -0,0 +1,150 @@
+//run: cargo test -- --nocapture
+
+// Figures from generated images
+//
+// `{| figure "dot", "-Tsvg", caption: "The pipeline" |}digraph { a -> b }`
+// runs the program like 'run' does, but writes its output to a file instead
+// of into the document, and outputs the markup that shows it, e.g.
+// '<figure>' for HTML or '\includegraphics' for LaTeX. Outputs can be binary,
+// e.g. PNG plots.
+//
+// Files are named by a hash of their contents, so documents that are compiled
+// again reuse them, and stale ones never shadow new ones. They are written to
+// 'Config::assets_path', by default "assets" next to the output file, and
+// linked relative to the output file.
+//
+// The type of the file (its extension) is told from its contents for SVG, PNG,
+// JPEG, GIF and PDF, otherwise it is given with e.g. `extension: "webp"`.
+// {label} is the id of the figure, e.g. for `\ref{label}` in LaTeX. SVGs in
+// LaTeX use '\includesvg', which needs `\usepackage{svg}`.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use common::FileType;
+
+use crate::api::Api;
+use crate::run::escape::escape;
+use crate::run::utility::{run_command_for_bytes, sniff_extension, write_bytes};
+use crate::run::value::TEXT;
+use crate::run::{Error, Keywords, PureResult, Types, Value};
+
+// The keyword parameters of 'figure', shared with its registration
+pub const KEYWORDS: &[(&str, Types)] = &[
+    ("caption", Types::one(TEXT)),
+    ("label", Types::one(TEXT)),
+    ("extension", Types::one(TEXT)),
+];
+
+pub fn figure<'a, V>(args: &[Value<'a, V>], api: Api<'a>) -> PureResult<'a, V> {
+    let (positional, keywords) = Keywords::split(args, KEYWORDS);
+    let cmd = unwrap!(unreachable &positional[0] => Value::Text(s) => s.as_ref());
+    let body = unwrap!(unreachable &positional[positional.len() - 1] => Value::Text(s) => s.as_ref());
+    let cmd_args = positional[1..positional.len() - 1]
+        .iter()
+        .filter_map(text)
+        .collect::<Vec<_>>();
+    let caption = keywords.text("caption");
+    let label = keywords.text("label");
+
+    let contents = run_command_for_bytes(cmd, Some(body), &cmd_args, None)?;
+    let extension = match keywords.text("extension") {
+        Some(extension) => extension.trim_start_matches('.'),
+        None => sniff_extension(&contents).ok_or(Error::Arg(
+            0,
+            Cow::Borrowed("outputs a file of unknown type. Give its extension, e.g. `extension: \"webp\"`"),
+        ))?,
+    };
+
+    let directory = assets_path(api.meta.assets_path.as_deref(), api.meta.output_path.as_deref());
+    let path = write_bytes(&contents, &directory, extension)?;
+
+    let link = link(&path, api.meta.output_path.as_deref());
+    let markup = render(&link, caption, label, extension, api.meta.output_filetype);
+    Ok(Value::Text(Cow::Owned(markup)))
+}
+
+fn text<'v, V>(value: &'v Value<'_, V>) -> Option<&'v str> {
+    match value {
+        Value::Text(s) => Some(s.as_ref()),
+        _ => None,
+    }
+}
+
+fn assets_path(assets_path: Option<&Path>, output_path: Option<&Path>) -> PathBuf {
+    match (assets_path, output_path.and_then(Path::parent)) {
+        (Some(path), _) => path.to_path_buf(),
+        (None, Some(directory)) => directory.join("assets"),
+        (None, None) => PathBuf::from("assets"),
+    }
+}
+
+// {path} as seen from the output file
+fn link(path: &Path, output_path: Option<&Path>) -> String {
+    let directory = output_path.and_then(Path::parent).filter(|dir| !dir.as_os_str().is_empty());
+    let relative = directory.and_then(|dir| path.strip_prefix(dir).ok()).unwrap_or(path);
+    relative.display().to_string()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn render(link: &str, caption: Option<&str>, label: Option<&str>, extension: &str, filetype: FileType) -> String {
+    let mut buffer = String::new();
+    match filetype {
+        FileType::Html => {
+            match label {
+                Some(label) => buffer.push_str(&format!("<figure id=\"{}\">", escape(label, filetype))),
+                None => buffer.push_str("<figure>"),
+            }
+            let alt = escape(caption.unwrap_or(""), filetype);
+            buffer.push_str(&format!("<img src=\"{}\" alt=\"{}\">", escape(link, filetype), alt));
+            if let Some(caption) = caption {
+                buffer.push_str(&format!("<figcaption>{}</figcaption>", caption));
+            }
+            buffer.push_str("</figure>");
+        }
+        // pandoc makes a figure of an image that is alone in a paragraph
+        FileType::Markdown | FileType::RMarkdown => {
+            buffer.push_str(&format!("![{}]({})", caption.unwrap_or(""), link));
+            if let Some(label) = label {
+                buffer.push_str(&format!("{{#{}}}", label));
+            }
+        }
+        // Same as the anchors of 'number'
+        FileType::CommonMark => {
+            if let Some(label) = label {
+                buffer.push_str(&format!("<span id=\"{}\"></span>", label));
+            }
+            buffer.push_str(&format!("![{}]({})", caption.unwrap_or(""), link));
+        }
+        FileType::AsciiDoctor => {
+            if let Some(label) = label {
+                buffer.push_str(&format!("[[{}]]\n", label));
+            }
+            if let Some(caption) = caption {
+                buffer.push_str(&format!(".{}\n", caption));
+            }
+            let alt = caption.unwrap_or("").replace(']', "\\]");
+            buffer.push_str(&format!("image::{}[{}]", link, alt));
+        }
+        FileType::LaTeX | FileType::Pdf => {
+            buffer.push_str("\\begin{figure}\n\\centering\n");
+            match extension {
+                "svg" => buffer.push_str(&format!("\\includesvg{{{}}}\n", link)),
+                _ => buffer.push_str(&format!("\\includegraphics{{{}}}\n", link)),
+            }
+            if let Some(caption) = caption {
+                buffer.push_str(&format!("\\caption{{{}}}\n", caption));
+            }
+            if let Some(label) = label {
+                buffer.push_str(&format!("\\label{{{}}}\n", label));
+            }
+            buffer.push_str("\\end{figure}");
+        }
+        FileType::Default => match caption {
+            Some(caption) => buffer.push_str(&format!("{} ({})", caption, link)),
+            None => buffer.push_str(link),
+        },
+    }
+    buffer
+}
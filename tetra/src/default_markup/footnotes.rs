@@ -0,0 +1,131 @@
+//run: cargo test -- --nocapture
+
+// `{$ footnote "text" $}` outputs a numbered marker and `{$ footnotes $}` the
+// notes themselves, in the syntax of the output filetype. AsciiDoctor and
+// LaTeX place footnotes themselves, so there the note is output inline and
+// 'footnotes' outputs nothing.
+//
+// 'footnotes' can be called more than once, e.g. once per chapter. Each call
+// lists the notes since the previous call, the last one also those after it,
+// and the numbering (and so the HTML ids) continues across them. Documents
+// with footnotes but no 'footnotes' are an error, except for those two.
+
+use std::borrow::Cow;
+use std::fmt::Write as _; // clippy: import without risk of name clashing
+
+use common::FileType;
+
+use super::CustomValue;
+use crate::api::Api;
+use crate::run::{Batch, Collect, Error, Value};
+
+pub struct Footnotes;
+
+impl Collect<CustomValue> for Footnotes {
+    fn batch<'a>(
+        &self,
+        items: Vec<Value<'a, CustomValue>>,
+        api: Api<'a>,
+    ) -> Result<Batch<'a, CustomValue>, Error> {
+        let filetype = api.meta.output_filetype;
+        // A 'footnotes' call has no argument, so its item is 'Value::Null'
+        let notes = items
+            .iter()
+            .filter_map(|item| match item {
+                Value::Text(s) => Some(s.as_ref()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let listing_count = items.len() - notes.len();
+        // Otherwise the markers would point at nothing
+        let is_placed_inline = matches!(filetype, FileType::AsciiDoctor | FileType::LaTeX | FileType::Pdf);
+        if let (0, Some(first), false) = (listing_count, notes.first(), is_placed_inline) {
+            return Err(Error::Generic(Cow::Owned(format!(
+                "The footnote {:?} is never listed. Call 'footnotes' where the notes should go",
+                first
+            ))));
+        }
+
+        let mut number = 0;
+        let mut listed = 0; // How many {notes} the previous listings took
+        let mut listings = 0;
+        let slots = items
+            .iter()
+            .map(|item| {
+                let output = match item {
+                    Value::Text(note) => {
+                        number += 1;
+                        marker(number, note, filetype)
+                    }
+                    _ => {
+                        listings += 1;
+                        let close = if listings == listing_count { notes.len() } else { number };
+                        let output = listing(listed + 1, &notes[listed..close], filetype);
+                        listed = close;
+                        output
+                    }
+                };
+                Value::Text(Cow::Owned(output))
+            })
+            .collect();
+        Ok(Batch { slots, aggregate: Value::Null })
+    }
+}
+
+fn marker(number: usize, note: &str, filetype: FileType) -> String {
+    match filetype {
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => format!("[^{}]", number),
+        FileType::AsciiDoctor => format!("footnote:[{}]", note),
+        FileType::LaTeX | FileType::Pdf => format!("\\footnote{{{}}}", note),
+        // The ids are stable so that they can be linked to from elsewhere
+        FileType::Html => format!(
+            "<sup id=\"fnref{0}\"><a href=\"#fn{0}\" role=\"doc-noteref\">{0}</a></sup>",
+            number
+        ),
+        FileType::Default => format!("[{}]", number),
+    }
+}
+
+// {notes} numbered from {first}
+fn listing(first: usize, notes: &[&str], filetype: FileType) -> String {
+    let mut buffer = String::new();
+    if notes.is_empty() {
+        return buffer;
+    }
+    let notes = notes.iter().enumerate().map(|(i, note)| (first + i, note));
+    match filetype {
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => {
+            for (number, note) in notes {
+                writeln!(buffer, "[^{}]: {}", number, note).unwrap();
+            }
+        }
+        FileType::AsciiDoctor | FileType::LaTeX | FileType::Pdf => {}
+        FileType::Html => {
+            // 'start' so that the list shows the same numbers as the markers
+            match first {
+                1 => buffer.push_str("<section class=\"footnotes\" role=\"doc-endnotes\"><ol>"),
+                _ => write!(buffer, "<section class=\"footnotes\" role=\"doc-endnotes\"><ol start=\"{}\">", first).unwrap(),
+            }
+            for (number, note) in notes {
+                write!(
+                    buffer,
+                    "<li id=\"fn{0}\">{1} <a href=\"#fnref{0}\" role=\"doc-backlink\">↩</a></li>",
+                    number,
+                    note
+                )
+                .unwrap();
+            }
+            buffer.push_str("</ol></section>");
+        }
+        FileType::Default => {
+            for (number, note) in notes {
+                writeln!(buffer, "[{}] {}", number, note).unwrap();
+            }
+        }
+    }
+    // Let the call decide on the line breaks
+    if buffer.ends_with('\n') {
+        buffer.pop();
+    }
+    buffer
+}
@@ -0,0 +1,372 @@
+//run: cargo test -- --nocapture
+
+// `{$ include "chapter2.adoc" $}` splices the source of the file into the
+// document before it is parsed (a pre-lex hook, see "run/hooks.rs"), so that
+// its cells run with everything else. They share the same storage, e.g. the
+// citations of every chapter end up in the one bibliography and numbering
+// carries on across chapters.
+//
+// This is only done for cells that are just an 'include' of a quoted path.
+// Paths that are computed, e.g. `include env("CHAPTER")`, are only known
+// once the document runs, so the 'include' function outputs the file as is.
+//
+// Part of a file can be included instead (see 'Fragment'), e.g.
+// * `include "notes.md", section: "Results"`, the heading "Results" and
+//   everything up to the next heading of the same level or higher, going by
+//   the outline of the metadata of CommonMark and AsciiDoctor files
+// * `include "notes.md", lines: "10-40"`, also "10" or "10-" to the end
+// * `include "notes.md", tag: "summary"`, the lines between the line comments
+//   "tag::summary[]" and "end::summary[]", as in AsciiDoctor, e.g.
+//   "<!-- tag::summary[] -->" in CommonMark. The lines of markers are left
+//   out, including those of other tags
+// These are found when the document is compiled, so they stay in sync with
+// the file.
+//
+// Included files can include others, but not any file that is including
+// them, as that would never finish. Paths are relative to the file they are
+// in, i.e. to the document for its own includes (see 'relative_to()').
+// Errors in an included file name it, as where each part of the source came
+// from is recorded in 'Config::splices'.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::{Analyse, FileType};
+
+use super::relative_to;
+use crate::api::{Config, Splice};
+use crate::parser::{step1_lex, LexType};
+use crate::run::Error;
+use crate::{Source, Token};
+
+pub fn expand(source: String, config: &mut Config) -> Result<String, Error> {
+    // Not worth lexing for
+    if !source.contains("include") {
+        return Ok(source);
+    }
+    let to_error = |err: String| Error::Contextless(Cow::Owned(err));
+    let cells = include_cells(&source).map_err(to_error)?;
+    if cells.is_empty() {
+        return Ok(source);
+    }
+
+    let mut splicer = Splicer {
+        output: String::with_capacity(source.len()),
+        splices: Vec::new(),
+        including: config
+            .input_path
+            .iter()
+            .filter_map(|path| fs::canonicalize(path).ok())
+            .collect(),
+    };
+    splicer.splice(&source, cells, config.input_path.as_deref(), &[]).map_err(to_error)?;
+    config.splices = splicer.splices;
+    Ok(splicer.output)
+}
+
+struct Splicer {
+    output: String,
+    splices: Vec<Splice>,
+    including: Vec<PathBuf>, // The chain of files being included, outermost first
+}
+
+impl Splicer {
+    // Pushes {source}, the contents of the file at {path}, with its include
+    // {cells} replaced by the files. {lines} is where each of its pieces (see
+    // 'Fragment::pieces()') starts and the line of the file it starts on.
+    // It is empty for the document itself, which is not a splice
+    fn splice(&mut self, source: &str, cells: Vec<Cell>, path: Option<&Path>, lines: &[(usize, usize)]) -> Result<(), String> {
+        let mut cursor = 0;
+        for Cell { start, close, path: included, fragment } in cells {
+            self.push(source, cursor, start, path, lines);
+            let included = relative_to(path, &included);
+            let canonical = fs::canonicalize(&included)
+                .map_err(|err| format!("Could not include {:?}. {}", included, err))?;
+            if let Some(i) = self.including.iter().position(|file| *file == canonical) {
+                let chain = self.including[i..]
+                    .iter()
+                    .chain([&canonical])
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>();
+                return Err(format!(
+                    "Could not include {:?} as it is already being included: {}",
+                    included,
+                    chain.join(" -> ")
+                ));
+            }
+            let contents = fs::read_to_string(&canonical)
+                .map_err(|err| format!("Could not include {:?}. {}", included, err))?;
+
+            let pieces = fragment.pieces(&included, &contents)?;
+            let (contents, included_lines) = join(&contents, &pieces);
+
+            self.including.push(canonical);
+            include_cells(&contents)
+                .and_then(|cells| self.splice(&contents, cells, Some(&included), &included_lines))
+                .map_err(|err| format!("In the included file {:?}:\n{}", included, err))?;
+            self.including.pop();
+            cursor = close;
+        }
+        self.push(source, cursor, source.len(), path, lines);
+        Ok(())
+    }
+
+    // Pushes {source[from..to]}, recording the splices it is from
+    fn push(&mut self, source: &str, from: usize, to: usize, path: Option<&Path>, lines: &[(usize, usize)]) {
+        let output_start = self.output.len();
+        self.output.push_str(&source[from..to]);
+        let Some(path) = path else {
+            return;
+        };
+        // A splice for each piece, as lines can be left out between them
+        for (i, (offset, line)) in lines.iter().enumerate() {
+            let next = lines.get(i + 1).map_or(source.len(), |(next, _)| *next);
+            let (start, close) = (from.max(*offset), to.min(next));
+            if start < close {
+                self.splices.push(Splice {
+                    start: output_start + start - from,
+                    close: output_start + close - from,
+                    path: path.to_path_buf(),
+                    line: line + source[*offset..start].matches('\n').count(),
+                });
+            }
+        }
+    }
+}
+
+struct Cell {
+    start: usize,
+    close: usize,
+    path: String,
+    fragment: Fragment,
+}
+
+// Every `{$ include "path" $}` (or `include("path")`) in {source}, with any
+// keyword arguments that 'Fragment' takes, e.g. `section: "Results"`
+fn include_cells(source: &str) -> Result<Vec<Cell>, String> {
+    let lexemes = step1_lex(source, true)
+        .map_err(|token| format!("{} {}", token.get_context(source), token.me))?;
+
+    let mut cells = Vec::new();
+    for (i, lexeme) in lexemes.iter().enumerate() {
+        if !matches!(lexeme.me, LexType::InlineStart) {
+            continue;
+        }
+        let has_paren = match lexemes.get(i + 1).map(|l| (&l.me, l.to_str(source))) {
+            Some((LexType::Ident, "include")) => false,
+            Some((LexType::IdentParen, "include(")) => true,
+            _ => continue,
+        };
+        let mut j = i + 2;
+        let Some(path) = quoted(&lexemes, &mut j, source) else {
+            continue;
+        };
+        let mut keywords = Vec::new();
+        while let [comma, key, colon, ..] = &lexemes[j..] {
+            if !matches!(
+                (&comma.me, &key.me, &colon.me),
+                (LexType::ArgSeparator, LexType::Ident, LexType::KeyValSeparator)
+            ) {
+                break;
+            }
+            j += 3;
+            match quoted(&lexemes, &mut j, source) {
+                Some(value) => keywords.push((key.to_str(source), value)),
+                None => break,
+            }
+        }
+
+        if has_paren {
+            if !matches!(lexemes.get(j).map(|l| &l.me), Some(LexType::ParenClose)) {
+                continue;
+            }
+            j += 1;
+        }
+        let Some(close) = lexemes.get(j).filter(|l| matches!(l.me, LexType::InlineClose)) else {
+            continue;
+        };
+        // Anything else is left for the 'include' function to report
+        let Ok(fragment) = Fragment::new(keywords.iter().map(|(key, value)| (*key, value.as_str()))) else {
+            continue;
+        };
+        let (Source::Range(start, _), Source::Range(_, close)) = (&lexeme.source, &close.source);
+        cells.push(Cell { start: *start, close: *close, path, fragment });
+    }
+    Ok(cells)
+}
+
+// The text of a string starting at {lexemes[*j]}, moving {j} past it
+fn quoted(lexemes: &[Token<LexType>], j: &mut usize, source: &str) -> Option<String> {
+    if !matches!(lexemes.get(*j).map(|l| &l.me), Some(LexType::QuoteStart)) {
+        return None;
+    }
+    let mut text = String::new();
+    for lexeme in &lexemes[*j + 1..] {
+        *j += 1;
+        match lexeme.me {
+            LexType::Text => text.push_str(lexeme.to_str(source)),
+            LexType::QuoteLiteral(s) => text.push_str(s),
+            LexType::QuoteClose => {
+                *j += 1;
+                return Some(text);
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Which part of a file to include
+pub enum Fragment {
+    Whole,
+    Section(String),
+    Lines(String),
+    Tag(String),
+}
+
+impl Fragment {
+    // From the keyword arguments of 'include', of which there can be one
+    pub fn new<'k, I: Iterator<Item = (&'k str, &'k str)>>(keywords: I) -> Result<Self, String> {
+        let mut fragment = Fragment::Whole;
+        for (key, value) in keywords {
+            if !matches!(fragment, Fragment::Whole) {
+                return Err("Only one of section, lines and tag can be given".to_string());
+            }
+            fragment = match key {
+                "section" => Fragment::Section(value.to_string()),
+                "lines" => Fragment::Lines(value.to_string()),
+                "tag" => Fragment::Tag(value.to_string()),
+                _ => return Err(format!("{:?} is not a part of a file", key)),
+            };
+        }
+        Ok(fragment)
+    }
+
+    // This part of {source}, the contents of the file at {path}
+    pub fn of<'s>(&self, path: &Path, source: &'s str) -> Result<Cow<'s, str>, String> {
+        let pieces = self.pieces(path, source)?;
+        Ok(join(source, &pieces).0)
+    }
+
+    // The byte ranges of {source} that make up this part, in order
+    fn pieces(&self, path: &Path, source: &str) -> Result<Vec<(usize, usize)>, String> {
+        match self {
+            Fragment::Whole => Ok(vec![(0, source.len())]),
+            Fragment::Section(name) => section(path, source, name).map(|range| vec![range]),
+            Fragment::Lines(range) => lines(path, source, range).map(|range| vec![range]),
+            Fragment::Tag(name) => tagged(path, source, name),
+        }
+    }
+}
+
+// The text of {pieces}, and where each piece starts in it with the line of
+// {source} (from 1) that it starts on
+fn join<'s>(source: &'s str, pieces: &[(usize, usize)]) -> (Cow<'s, str>, Vec<(usize, usize)>) {
+    let mut lines = Vec::with_capacity(pieces.len());
+    let mut length = 0;
+    for (start, close) in pieces {
+        lines.push((length, source[..*start].matches('\n').count() + 1));
+        length += close - start;
+    }
+    let text = match pieces {
+        [(start, close)] => Cow::Borrowed(&source[*start..*close]),
+        _ => Cow::Owned(pieces.iter().map(|(start, close)| &source[*start..*close]).collect()),
+    };
+    (text, lines)
+}
+
+// Sections and tags depend on the syntax of the file
+fn filetype(path: &Path) -> Result<FileType, String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(FileType::from)
+        .ok_or_else(|| format!("{:?} is not a CommonMark (.md) or AsciiDoctor (.adoc) file", path))
+}
+
+fn section(path: &Path, source: &str, name: &str) -> Result<(usize, usize), String> {
+    let metadata = filetype(path)?.metadata(source);
+    let headings = metadata.outline.iter().zip(&metadata.outline_offsets);
+    let mut headings = headings.skip_while(|((_, title), _)| title.trim() != name);
+    let Some(((level, _), start)) = headings.next() else {
+        let titles = metadata.outline.iter().map(|(_, title)| title.trim()).collect::<Vec<_>>();
+        return Err(format!(
+            "There is no section {:?} in {:?}. Expected one of: {}",
+            name,
+            path,
+            titles.join(", ")
+        ));
+    };
+    let close = headings
+        .find(|((next_level, _), _)| next_level <= level)
+        .map_or(source.len(), |(_, offset)| *offset);
+    Ok((*start, close))
+}
+
+// e.g. "10-40", "10-" or "10", counting from 1
+fn lines(path: &Path, source: &str, range: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("{:?} is not a range of lines, e.g. \"10-40\" or \"10-\"", range);
+    let parse = |line: &str| line.trim().parse::<usize>().ok().filter(|line| *line > 0);
+    let (first, last) = match range.split_once('-') {
+        Some((first, "")) => (parse(first).ok_or_else(invalid)?, usize::MAX),
+        Some((first, last)) => (parse(first).ok_or_else(invalid)?, parse(last).ok_or_else(invalid)?),
+        None => parse(range).map(|line| (line, line)).ok_or_else(invalid)?,
+    };
+    if first > last {
+        return Err(invalid());
+    }
+
+    let mut offsets = source
+        .split_inclusive('\n')
+        .scan(0, |offset, line| {
+            *offset += line.len();
+            Some(*offset - line.len())
+        })
+        .chain([source.len()]);
+    let start = offsets.nth(first - 1).filter(|start| *start < source.len());
+    let start = start.ok_or_else(|| format!("{:?} does not have a line {}", path, first))?;
+    let close = offsets.nth(last.saturating_sub(first)).unwrap_or(source.len());
+    Ok((start, close))
+}
+
+// One piece for each run of lines between markers
+fn tagged(path: &Path, source: &str, name: &str) -> Result<Vec<(usize, usize)>, String> {
+    let filetype = filetype(path)?;
+    let (prefix, suffix) = (filetype.comment_prefix(), filetype.comment_suffix());
+
+    let mut pieces: Vec<(usize, usize)> = Vec::new();
+    let mut is_found = false;
+    let mut is_inside = false;
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let (start, close) = (offset, offset + line.len());
+        offset = close;
+        match marker(line, prefix, suffix) {
+            Some((is_start, tag)) if tag == name => {
+                is_found |= is_start;
+                is_inside = is_start;
+            }
+            Some(_) => {}
+            None if is_inside => match pieces.last_mut() {
+                Some(piece) if piece.1 == start => piece.1 = close,
+                _ => pieces.push((start, close)),
+            },
+            None => {}
+        }
+    }
+    if is_found {
+        Ok(pieces)
+    } else {
+        Err(format!("There is no \"tag::{}[]\" in {:?}", name, path))
+    }
+}
+
+// e.g. "<!-- tag::summary[] -->" is '(true, "summary")' and "// end::summary[]"
+// '(false, "summary")'
+fn marker<'l>(line: &'l str, prefix: &str, suffix: &str) -> Option<(bool, &'l str)> {
+    let line = line.trim().strip_prefix(prefix)?.strip_suffix(suffix)?.trim();
+    let name = line.strip_prefix("tag::").or_else(|| line.strip_prefix("end::"))?;
+    Some((line.starts_with("tag::"), name.strip_suffix("[]")?))
+}
@@ -0,0 +1,149 @@
+//run: cargo test -- --nocapture
+
+// Automatic numbering and cross-references
+//
+// `{$ number "figure", "fig:pipeline" $}` outputs e.g. "Figure 3", counting
+// the 'number' calls of the same kind in document order, and makes it the
+// target of `{$ ref "fig:pipeline" $}`, which links to it with the same text.
+// Any kind can be numbered, e.g. "figure", "table", "equation" or "section".
+//
+// Both are collectors (see "run/function.rs") sharing the same key, so all the
+// labels are known before any reference is output, even those later in the
+// document.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use common::FileType;
+
+use super::CustomValue;
+use crate::api::Api;
+use crate::run::escape::escape;
+use crate::run::{Batch, Collect, Error, Value};
+
+pub struct Number;
+pub struct Reference;
+
+impl Collect<CustomValue> for Number {
+    // A list of the kind and the label (or null)
+    fn item<'a>(
+        &self,
+        args: &[Value<'a, CustomValue>],
+        _: Api<'a>,
+    ) -> Result<Value<'a, CustomValue>, Error> {
+        Ok(Value::List(vec![args[0].clone(), args.get(1).cloned().unwrap_or(Value::Null)]))
+    }
+
+    fn batch<'a>(
+        &self,
+        items: Vec<Value<'a, CustomValue>>,
+        api: Api<'a>,
+    ) -> Result<Batch<'a, CustomValue>, Error> {
+        resolve(items, &api)
+    }
+}
+
+// The item is the label
+impl Collect<CustomValue> for Reference {
+    fn batch<'a>(
+        &self,
+        items: Vec<Value<'a, CustomValue>>,
+        api: Api<'a>,
+    ) -> Result<Batch<'a, CustomValue>, Error> {
+        resolve(items, &api)
+    }
+}
+
+fn resolve<'a>(
+    items: Vec<Value<'a, CustomValue>>,
+    api: &Api<'a>,
+) -> Result<Batch<'a, CustomValue>, Error> {
+    let filetype = api.meta.output_filetype;
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut targets: HashMap<&str, String> = HashMap::new();
+
+    // Number everything first so that references can be resolved
+    let mut numbered = Vec::with_capacity(items.len());
+    for item in &items {
+        numbered.push(match item {
+            Value::List(pair) => {
+                let kind = unwrap!(unreachable &pair[0] => Value::Text(s) => s.as_ref());
+                let count = counts.entry(kind).or_insert(0);
+                *count += 1;
+                let text = format!("{} {}", capitalise(kind), count);
+
+                match &pair[1] {
+                    Value::Text(label) => {
+                        let anchored = anchor(label, &text, filetype);
+                        if targets.insert(label, text).is_some() {
+                            return Err(Error::Generic(Cow::Owned(format!(
+                                "The label {:?} is used more than once",
+                                label
+                            ))));
+                        }
+                        Some(anchored)
+                    }
+                    _ => Some(text),
+                }
+            }
+            _ => None,
+        });
+    }
+
+    let mut slots = Vec::with_capacity(items.len());
+    for (item, numbered) in items.iter().zip(numbered) {
+        let text = match numbered {
+            Some(text) => text,
+            None => {
+                let label = unwrap!(unreachable item => Value::Text(s) => s.as_ref());
+                match targets.get(label) {
+                    Some(text) => link(label, text, filetype),
+                    None => {
+                        return Err(Error::Generic(Cow::Owned(format!(
+                            "There is no 'number' with the label {:?} to reference",
+                            label
+                        ))))
+                    }
+                }
+            }
+        };
+        slots.push(Value::Text(Cow::Owned(text)));
+    }
+    Ok(Batch { slots, aggregate: Value::Null })
+}
+
+// e.g. "figure" -> "Figure"
+fn capitalise(kind: &str) -> String {
+    let mut chars = kind.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+// The label in raw HTML (i.e. the 'id' of the span in the Markdowns) is an
+// HTML attribute, everything else is escaped as the output filetype
+fn anchor(label: &str, text: &str, filetype: FileType) -> String {
+    let text = escape(text, filetype);
+    match filetype {
+        FileType::Html | FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => {
+            format!("<span id=\"{}\">{}</span>", escape(label, FileType::Html), text)
+        }
+        FileType::AsciiDoctor => format!("[[{}]]{}", label, text),
+        FileType::LaTeX | FileType::Pdf => format!("\\hypertarget{{{}}}{{{}}}", escape(label, filetype), text),
+        FileType::Default => text,
+    }
+}
+
+fn link(label: &str, text: &str, filetype: FileType) -> String {
+    let (label, text) = (escape(label, filetype), escape(text, filetype));
+    match filetype {
+        FileType::Html => format!("<a href=\"#{}\">{}</a>", label, text),
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => {
+            format!("[{}](#{})", text, label)
+        }
+        FileType::AsciiDoctor => format!("<<{},{}>>", label, text),
+        FileType::LaTeX | FileType::Pdf => format!("\\hyperlink{{{}}}{{{}}}", label, text),
+        FileType::Default => text,
+    }
+}
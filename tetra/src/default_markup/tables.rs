@@ -0,0 +1,396 @@
+//run: cargo test -- --nocapture
+
+// Tables from data files
+//
+// `{$ table "results.csv" $}` reads a CSV file, whose first row is the header,
+// or a JSON file that is a list of objects, whose columns are their fields in
+// the order that they first appear. The table is then written in the syntax of
+// the output filetype, e.g. a pipe table for CommonMark or a 'tabular' for
+// LaTeX.
+//
+// The keyword arguments take lists separated by commas
+// * `columns: "name, score"` only shows these columns, in this order
+// * `headers: "Name, Score (%)"` renames the columns shown
+// * `align: "lr"` aligns each column shown to the left, center or right, or
+//   all of them with just one letter. Columns are aligned left by default
+
+use std::borrow::Cow;
+use std::fmt::Write as _; // clippy: import without risk of name clashing
+use std::fs;
+use std::path::Path;
+
+use common::FileType;
+
+use crate::api::Api;
+use crate::json::{self, Json};
+use crate::run::escape::escape;
+use crate::run::value::TEXT;
+use crate::run::{Error, Keywords, PureResult, Types, Value};
+
+// The keyword parameters of 'table', shared with its registration
+pub const KEYWORDS: &[(&str, Types)] = &[
+    ("columns", Types::one(TEXT)),
+    ("headers", Types::one(TEXT)),
+    ("align", Types::one(TEXT)),
+];
+
+pub fn table<'a, V>(args: &[Value<'a, V>], api: Api<'a>) -> PureResult<'a, V> {
+    let (positional, keywords) = Keywords::split(args, KEYWORDS);
+    let path = unwrap!(unreachable &positional[0] => Value::Text(s) => s.as_ref());
+    // For the errors about the keyword argument {name}
+    let error = |name: &str| {
+        let i = keywords.index(name);
+        move |err: String| Error::Arg(i, Cow::Owned(err))
+    };
+
+    let mut table = Table::load(Path::new(path)).map_err(|err| Error::Arg(0, Cow::Owned(err)))?;
+    if let Some(columns) = keywords.text("columns") {
+        table.select(&list(columns)).map_err(error("columns"))?;
+    }
+    if let Some(headers) = keywords.text("headers") {
+        let headers = list(headers);
+        if headers.len() != table.headers.len() {
+            return Err(error("headers")(format!(
+                "has {} headers but the table has {} columns",
+                headers.len(),
+                table.headers.len()
+            )));
+        }
+        table.headers = headers.into_iter().map(String::from).collect();
+    }
+    let align = match keywords.text("align") {
+        Some(align) => Align::parse(align, table.headers.len()).map_err(error("align"))?,
+        None => vec![Align::Left; table.headers.len()],
+    };
+
+    Ok(Value::Text(Cow::Owned(table.render(&align, api.meta.output_filetype))))
+}
+
+// e.g. "name, score" -> '["name", "score"]'
+fn list(text: &str) -> Vec<&str> {
+    text.split(',').map(str::trim).collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("Could not read file {:?}: {}", path, err))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Self::from_csv(&source),
+            Some("json") => Self::from_json(&source),
+            _ => Err(format!("{:?} is not a CSV (.csv) or JSON (.json) file", path)),
+        }
+        .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // RFC 4180, i.e. fields with commas, quotes or line breaks are quoted and
+    // quotes inside them are doubled
+    pub fn from_csv(source: &str) -> Result<Self, String> {
+        let mut records = Vec::new();
+        let mut record = Vec::new();
+        let mut field = String::new();
+        let mut line = 1;
+        let mut is_quoted = false;
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (is_quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => is_quoted = false,
+                (false, '"') if field.is_empty() => is_quoted = true,
+                (false, ',') => record.push(std::mem::take(&mut field)),
+                (false, '\r') if chars.peek() == Some(&'\n') => {}
+                (false, '\n') => {
+                    record.push(std::mem::take(&mut field));
+                    records.push((line, std::mem::take(&mut record)));
+                    line += 1;
+                }
+                (_, c) => {
+                    line += usize::from(c == '\n');
+                    field.push(c);
+                }
+            }
+        }
+        if is_quoted {
+            return Err(format!("line {}: A quoted field is never closed", line));
+        }
+        if !field.is_empty() || !record.is_empty() {
+            record.push(field);
+            records.push((line, record));
+        }
+        // Blank lines, e.g. at the end of the file
+        records.retain(|(_, record)| record.len() > 1 || !record[0].is_empty());
+
+        let mut records = records.into_iter();
+        let Some((_, headers)) = records.next() else {
+            return Err("The file is empty. Expected a header".to_string());
+        };
+        let rows = records
+            .map(|(line, row)| match row.len() == headers.len() {
+                true => Ok(row),
+                false => Err(format!(
+                    "line {}: Expected {} fields as in the header, but there are {}",
+                    line,
+                    headers.len(),
+                    row.len()
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Table { headers, rows })
+    }
+
+    // e.g. '[{ "name": "tetra", "score": 97.5 }]'
+    pub fn from_json(source: &str) -> Result<Self, String> {
+        let Json::Array(items) = json::parse(source)? else {
+            return Err("Expected a list of objects".to_string());
+        };
+        if items.is_empty() {
+            return Err("The list is empty, so there are no columns".to_string());
+        }
+        let mut headers: Vec<String> = Vec::new();
+        for item in &items {
+            let Json::Object(fields) = item else {
+                return Err("Expected a list of objects".to_string());
+            };
+            for (name, _) in fields {
+                if !headers.contains(name) {
+                    headers.push(name.clone());
+                }
+            }
+        }
+
+        let mut rows = Vec::with_capacity(items.len());
+        for (i, item) in items.iter().enumerate() {
+            let row = headers.iter().map(|name| match item.get(name) {
+                Some(Json::String(s) | Json::Number(s)) => Ok(s.clone()),
+                Some(Json::Bool(b)) => Ok(b.to_string()),
+                Some(Json::Null) | None => Ok(String::new()),
+                Some(Json::Array(_) | Json::Object(_)) => Err(format!(
+                    "The field {:?} of item {} is a list or an object, which cannot be shown in a table",
+                    name,
+                    i + 1
+                )),
+            });
+            rows.push(row.collect::<Result<_, _>>()?);
+        }
+        Ok(Table { headers, rows })
+    }
+
+    // Keeps only {columns}, in that order
+    pub fn select(&mut self, columns: &[&str]) -> Result<(), String> {
+        let indices = columns
+            .iter()
+            .map(|column| {
+                self.headers.iter().position(|header| header == column).ok_or_else(|| {
+                    format!(
+                        "There is no column {:?}. Expected one of: {}",
+                        column,
+                        self.headers.join(", ")
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let pick = |row: &[String]| indices.iter().map(|i| row[*i].clone()).collect();
+        self.headers = pick(&self.headers);
+        self.rows = self.rows.iter().map(|row| pick(row)).collect();
+        Ok(())
+    }
+
+    // {align} has one entry per column
+    pub fn render(&self, align: &[Align], filetype: FileType) -> String {
+        let mut buffer = String::new();
+        match filetype {
+            FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => {
+                let cells = self.escaped(filetype);
+                // The rule under the header needs at least three dashes
+                let widths = widths(&cells).into_iter().map(|width| width.max(3)).collect::<Vec<_>>();
+                for (i, row) in cells.iter().enumerate() {
+                    buffer.push('|');
+                    for ((cell, width), align) in row.iter().zip(&widths).zip(align) {
+                        write!(buffer, " {} |", align.pad(cell, *width)).unwrap();
+                    }
+                    buffer.push('\n');
+                    if i == 0 {
+                        buffer.push('|');
+                        for (width, align) in widths.iter().zip(align) {
+                            write!(buffer, "{}|", align.markdown_rule(*width)).unwrap();
+                        }
+                        buffer.push('\n');
+                    }
+                }
+            }
+            FileType::AsciiDoctor => {
+                let cols = align.iter().map(|align| align.asciidoctor()).collect::<Vec<_>>();
+                writeln!(buffer, "[cols=\"{}\",options=\"header\"]", cols.join(",")).unwrap();
+                buffer.push_str("|===\n");
+                for (i, row) in self.escaped(filetype).iter().enumerate() {
+                    let cells = row.iter().map(|cell| format!("|{}", cell)).collect::<Vec<_>>();
+                    writeln!(buffer, "{}", cells.join(" ")).unwrap();
+                    if i == 0 {
+                        buffer.push('\n');
+                    }
+                }
+                buffer.push_str("|===\n");
+            }
+            FileType::LaTeX | FileType::Pdf => {
+                let spec = align.iter().map(|align| align.latex()).collect::<String>();
+                writeln!(buffer, "\\begin{{tabular}}{{{}}}", spec).unwrap();
+                buffer.push_str("\\hline\n");
+                for (i, row) in self.escaped(filetype).iter().enumerate() {
+                    writeln!(buffer, "{} \\\\", row.join(" & ")).unwrap();
+                    if i == 0 {
+                        buffer.push_str("\\hline\n");
+                    }
+                }
+                buffer.push_str("\\hline\n\\end{tabular}\n");
+            }
+            FileType::Html => {
+                let cells = self.escaped(filetype);
+                let push_row = |buffer: &mut String, row: &[String], tag: &str| {
+                    buffer.push_str("<tr>");
+                    for (cell, align) in row.iter().zip(align) {
+                        write!(buffer, "<{0} style=\"text-align: {1}\">{2}</{0}>", tag, align.css(), cell).unwrap();
+                    }
+                    buffer.push_str("</tr>\n");
+                };
+                buffer.push_str("<table>\n<thead>\n");
+                push_row(&mut buffer, &cells[0], "th");
+                buffer.push_str("</thead>\n<tbody>\n");
+                for row in &cells[1..] {
+                    push_row(&mut buffer, row, "td");
+                }
+                buffer.push_str("</tbody>\n</table>\n");
+            }
+            FileType::Default => {
+                let cells = self.escaped(filetype);
+                let widths = widths(&cells);
+                for (i, row) in cells.iter().enumerate() {
+                    let padded = row
+                        .iter()
+                        .zip(&widths)
+                        .zip(align)
+                        .map(|((cell, width), align)| align.pad(cell, *width))
+                        .collect::<Vec<_>>();
+                    writeln!(buffer, "{}", padded.join("  ").trim_end()).unwrap();
+                    if i == 0 {
+                        let rules = widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>();
+                        writeln!(buffer, "{}", rules.join("  ")).unwrap();
+                    }
+                }
+            }
+        }
+        buffer
+    }
+
+    // The header and then the rows, escaped for {filetype}
+    fn escaped(&self, filetype: FileType) -> Vec<Vec<String>> {
+        std::iter::once(&self.headers)
+            .chain(&self.rows)
+            .map(|row| row.iter().map(|cell| escape_cell(cell, filetype)).collect())
+            .collect()
+    }
+}
+
+// The widest cell of each column, in characters
+fn widths(cells: &[Vec<String>]) -> Vec<usize> {
+    let mut widths = vec![0; cells[0].len()];
+    for row in cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    widths
+}
+
+// Cells of pipe tables and '|===' tables are one line each, which '|' ends
+fn escape_cell(text: &str, filetype: FileType) -> String {
+    let text = text.replace('\r', "");
+    let escaped = escape(&text, filetype);
+    match filetype {
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown | FileType::AsciiDoctor => {
+            escaped.replace('|', "\\|").replace('\n', " ")
+        }
+        _ => escaped,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Align {
+    // e.g. "lcr", one letter for each of the {count} columns, or one for all
+    pub fn parse(text: &str, count: usize) -> Result<Vec<Self>, String> {
+        let align = text
+            .trim()
+            .chars()
+            .map(|c| match c {
+                'l' => Ok(Align::Left),
+                'c' => Ok(Align::Center),
+                'r' => Ok(Align::Right),
+                _ => Err(format!("{:?} is not an alignment. Expected 'l', 'c' or 'r'", c)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match align.len() {
+            1 => Ok(vec![align[0]; count]),
+            len if len == count => Ok(align),
+            len => Err(format!("aligns {} columns but the table has {}", len, count)),
+        }
+    }
+
+    fn pad(self, cell: &str, width: usize) -> String {
+        match self {
+            Align::Left => format!("{:<1$}", cell, width),
+            Align::Center => format!("{:^1$}", cell, width),
+            Align::Right => format!("{:>1$}", cell, width),
+        }
+    }
+
+    // The line under the header, e.g. "-----:" for a column 4 wide
+    fn markdown_rule(self, width: usize) -> String {
+        let dashes = "-".repeat(width);
+        match self {
+            Align::Left => format!(":{}-", dashes),
+            Align::Center => format!(":{}:", dashes),
+            Align::Right => format!("-{}:", dashes),
+        }
+    }
+
+    fn asciidoctor(self) -> &'static str {
+        match self {
+            Align::Left => "<",
+            Align::Center => "^",
+            Align::Right => ">",
+        }
+    }
+
+    fn latex(self) -> char {
+        match self {
+            Align::Left => 'l',
+            Align::Center => 'c',
+            Align::Right => 'r',
+        }
+    }
+
+    fn css(self) -> &'static str {
+        match self {
+            Align::Left => "left",
+            Align::Center => "center",
+            Align::Right => "right",
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//run: cargo test -- --nocapture
+
+// Table of contents
+//
+// The headings are only all known once every cell has run (cells can output
+// headings), so 'toc' outputs a placeholder that the post-knit hook
+// 'fill_in_toc()' replaces with the headings of the knitted document. LaTeX
+// numbers its own table of contents, so there it is just passed through.
+//
+// {depth} is the deepest heading level listed, e.g. "2" for '#' and '##'.
+// The default is 3, the same as pandoc and LaTeX.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write as _; // clippy: import without risk of name clashing
+
+use common::{Analyse, FileType};
+
+use crate::api::Api;
+use crate::run::escape::push_escaped;
+use crate::run::{Error, PureResult, Value};
+use crate::typed_function;
+
+const DEFAULT_DEPTH: u8 = 3;
+
+// U+E000 is in the Private Use Area, so it will not appear in documents
+const PLACEHOLDER_START: &str = "\u{E000}toc:";
+const PLACEHOLDER_CLOSE: char = '\u{E000}';
+
+typed_function! {
+pub fn toc<'a, V>(api: Api<'a>; depth: Option<&str>) -> PureResult<'a, V> {
+    let depth = match depth {
+        Some(s) => match s.trim().parse::<u8>() {
+            Ok(depth) if depth > 0 => depth,
+            _ => return Err(Error::Arg(0, Cow::Borrowed("is not a heading level (a number from 1)"))),
+        },
+        None => DEFAULT_DEPTH,
+    };
+
+    let output = match api.meta.output_filetype {
+        FileType::LaTeX | FileType::Pdf => {
+            format!("\\setcounter{{tocdepth}}{{{}}}\n\\tableofcontents", depth)
+        }
+        _ => format!("{}{}{}", PLACEHOLDER_START, depth, PLACEHOLDER_CLOSE),
+    };
+    Ok(Value::Text(Cow::Owned(output)))
+}
+}
+
+// Post-knit hook for 'toc'
+pub fn fill_in_toc(output: String, api: &Api) -> Result<String, Error> {
+    if !output.contains(PLACEHOLDER_START) {
+        return Ok(output);
+    }
+
+    // The knit is still in the language of the input
+    let analyser = match api.meta.input_filetype {
+        FileType::Markdown | FileType::RMarkdown => FileType::CommonMark,
+        filetype => filetype,
+    };
+    let metadata = analyser.metadata(&output);
+    let outline = metadata
+        .outline
+        .iter()
+        .map(|(level, text)| (*level, text.trim()))
+        .collect::<Vec<_>>();
+
+    let mut buffer = String::with_capacity(output.len());
+    let mut rest = output.as_str();
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let after = &rest[start + PLACEHOLDER_START.len()..];
+        let placeholder = after.find(PLACEHOLDER_CLOSE).and_then(|close| {
+            let depth = after[..close].parse::<u8>().ok()?;
+            Some((depth, close))
+        });
+        buffer.push_str(&rest[..start]);
+        match placeholder {
+            Some((depth, close)) => {
+                render(&outline, depth, api.meta.output_filetype, &mut buffer);
+                rest = &after[close + PLACEHOLDER_CLOSE.len_utf8()..];
+            }
+            // Not one of ours (e.g. mangled by a later cell), so left as is
+            None => {
+                buffer.push_str(PLACEHOLDER_START);
+                rest = after;
+            }
+        }
+    }
+    buffer.push_str(rest);
+    Ok(buffer)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn render(outline: &[(u8, &str)], depth: u8, filetype: FileType, buffer: &mut String) {
+    let mut slugs = Slugs::default();
+    let entries = nest(outline, depth);
+    if entries.is_empty() {
+        return;
+    }
+    let start = buffer.len();
+    match filetype {
+        FileType::Html => {
+            buffer.push_str("<nav>");
+            let mut open = 0;
+            for (nesting, text) in entries {
+                if nesting < open {
+                    (nesting..open).for_each(|_| buffer.push_str("</li></ul>"));
+                    buffer.push_str("</li>");
+                } else if nesting == open && open > 0 {
+                    buffer.push_str("</li>");
+                }
+                (open..nesting).for_each(|_| buffer.push_str("<ul>"));
+                open = nesting;
+
+                let slug = slugs.github(text);
+                buffer.push_str("<li><a href=\"#");
+                push_escaped(buffer, &slug, FileType::Html);
+                buffer.push_str("\">");
+                push_escaped(buffer, text, FileType::Html);
+                buffer.push_str("</a>");
+            }
+            (0..open).for_each(|_| buffer.push_str("</li></ul>"));
+            buffer.push_str("</nav>");
+        }
+        FileType::AsciiDoctor => {
+            for (nesting, text) in entries {
+                let bullet = "*".repeat(nesting);
+                writeln!(buffer, "{} <<{},{}>>", bullet, slugs.asciidoctor(text), text).unwrap();
+            }
+        }
+        FileType::CommonMark | FileType::Markdown | FileType::RMarkdown => {
+            for (nesting, text) in entries {
+                let indent = "  ".repeat(nesting - 1);
+                writeln!(buffer, "{}- [{}](#{})", indent, text, slugs.github(text)).unwrap();
+            }
+        }
+        FileType::Default | FileType::LaTeX | FileType::Pdf => {
+            for (nesting, text) in entries {
+                writeln!(buffer, "{}{}", "  ".repeat(nesting - 1), text).unwrap();
+            }
+        }
+    }
+    // Let the placeholder decide on the line breaks
+    if buffer.len() > start && buffer.ends_with('\n') {
+        buffer.pop();
+    }
+}
+
+// How deep in the list each heading is (from 1), so that skipping a level
+// (e.g. '#' then '###') only indents once
+fn nest<'b>(outline: &[(u8, &'b str)], depth: u8) -> Vec<(usize, &'b str)> {
+    let mut levels: Vec<u8> = Vec::new();
+    let mut entries = Vec::new();
+    for (level, text) in outline.iter().filter(|(level, _)| *level <= depth) {
+        while levels.last().is_some_and(|last| last >= level) {
+            levels.pop();
+        }
+        levels.push(*level);
+        entries.push((levels.len(), *text));
+    }
+    entries
+}
+
+// The ids that the renderers give headings. Repeated headings get a suffix
+#[derive(Default)]
+struct Slugs(HashMap<String, usize>);
+
+impl Slugs {
+    // e.g. "Hello, World" -> "hello-world"
+    fn github(&mut self, text: &str) -> String {
+        let slug = text
+            .chars()
+            .filter_map(|c| match c {
+                ' ' => Some('-'),
+                '-' | '_' => Some(c),
+                c if c.is_alphanumeric() => Some(c),
+                _ => None,
+            })
+            .flat_map(char::to_lowercase)
+            .collect::<String>();
+        self.dedup(slug, '-')
+    }
+
+    // e.g. "Hello, World" -> "_hello_world"
+    fn asciidoctor(&mut self, text: &str) -> String {
+        let mut slug = String::from("_");
+        for c in text.chars().flat_map(char::to_lowercase) {
+            match c {
+                c if c.is_alphanumeric() => slug.push(c),
+                ' ' | '-' | '_' | '.' if !slug.ends_with('_') => slug.push('_'),
+                _ => {}
+            }
+        }
+        while slug.len() > 1 && slug.ends_with('_') {
+            slug.pop();
+        }
+        self.dedup(slug, '_')
+    }
+
+    fn dedup(&mut self, slug: String, separator: char) -> String {
+        let count = self.0.entry(slug.clone()).or_insert(0);
+        *count += 1;
+        match *count {
+            1 => slug,
+            n => format!("{}{}{}", slug, separator, n - 1),
+        }
+    }
+}
@@ -20,6 +20,8 @@ macro_rules! build_exact_string {
     };
 }
 
+use crate::api::Splice;
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Source {
     Range(usize, usize),
@@ -32,7 +34,39 @@ impl Source {
         }
     }
 
+    // 1-indexed, matches the row number that 'get_context()' prints
+    pub fn line_number(&self, original: &str) -> usize {
+        match self {
+            Source::Range(start, close) => {
+                let start = std::cmp::min(*start, *close);
+                original[0..start].matches('\n').count() + 1
+            }
+        }
+    }
+
     pub fn get_context(&self, original: &str) -> String {
+        self.get_context_from_row(original, 1)
+    }
+
+    // Same as 'get_context()' but names the file of source that a pre-lex
+    // hook spliced in and numbers the rows as in that file
+    pub fn get_context_in(&self, original: &str, splices: &[Splice]) -> String {
+        let Source::Range(start, close) = self;
+        let offset = std::cmp::min(*start, *close);
+        match splices.iter().find(|splice| splice.start <= offset && offset < splice.close) {
+            Some(splice) => {
+                // Rows start from the line of {splice.start}
+                let line_start = original[0..splice.start].rfind('\n').map_or(0, |x| x + 1);
+                let source = Source::Range(start - line_start, close - line_start);
+                let context = source.get_context_from_row(&original[line_start..], splice.line);
+                format!("In the included file {:?}:\n{}", splice.path, context)
+            }
+            None => self.get_context(original),
+        }
+    }
+
+    // {first_row} is the row number of the first line of {original}
+    fn get_context_from_row(&self, original: &str, first_row: usize) -> String {
         match self {
             // This is mimicking the formatting Rust uses for compile errors
             Source::Range(start, close) => {
@@ -55,7 +89,7 @@ impl Source {
                     .unwrap_or(original.len());
                 let line = &original[line_start..line_close];
 
-                let row_number = original[0..start].lines().count() + 1;
+                let row_number = original[0..start].matches('\n').count() + first_row;
                 let row_number = row_number.to_string();
                 let arrow_count = original[start..close].len();
                 let arrow_count = std::cmp::max(arrow_count, 1);
@@ -0,0 +1,274 @@
+//run: cargo test -- --nocapture
+
+// Syntax highlighting without pygmentize
+//
+// Each language is a 'Grammar' (see "highlight/grammars.rs"), i.e. its
+// keywords, comments and strings, which drives the one tokenizer that all of
+// them share. The tokens are then written as
+// * HTML spans, with the same classes as pygmentize so that its stylesheets
+//   still apply, e.g. '<span class="k">fn</span>'
+// * LaTeX macros, also the same as pygmentize, e.g. '\PY{k}{fn}' in a
+//   'Verbatim' environment (so the document needs the definitions of
+//   `pygmentize -f latex -S default`)
+// * ANSI escapes for plain text, i.e. for terminals
+
+mod grammars;
+
+use common::FileType;
+
+use crate::run::escape::escape;
+
+pub use grammars::{Grammar, GRAMMARS};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Text,
+    Comment,
+    Keyword,
+    Builtin, // e.g. types and functions that come with the language
+    String,
+    Number,
+    Variable, // e.g. "$HOME" in sh
+}
+
+impl Kind {
+    // The short names of the pygments token types
+    fn class(self) -> &'static str {
+        match self {
+            Kind::Text => "",
+            Kind::Comment => "c",
+            Kind::Keyword => "k",
+            Kind::Builtin => "nb",
+            Kind::String => "s",
+            Kind::Number => "m",
+            Kind::Variable => "nv",
+        }
+    }
+
+    fn ansi(self) -> &'static str {
+        match self {
+            Kind::Text => "",
+            Kind::Comment => "\x1b[90m",
+            Kind::Keyword => "\x1b[1;34m",
+            Kind::Builtin => "\x1b[36m",
+            Kind::String => "\x1b[32m",
+            Kind::Number => "\x1b[35m",
+            Kind::Variable => "\x1b[33m",
+        }
+    }
+}
+
+// By any of its names, e.g. "py" or "python"
+pub fn grammar(lang: &str) -> Option<&'static Grammar> {
+    let lang = lang.to_lowercase();
+    GRAMMARS.iter().find(|grammar| grammar.names.contains(&lang.as_str()))
+}
+
+// {code} as {lang} in the syntax of {filetype}
+pub fn highlight(lang: &str, code: &str, filetype: FileType) -> Result<String, String> {
+    let grammar = grammar(lang).ok_or_else(|| {
+        let names = GRAMMARS.iter().map(|grammar| grammar.names[0]).collect::<Vec<_>>();
+        format!("There is no grammar for {:?}. Expected one of: {}", lang, names.join(", "))
+    })?;
+    let tokens = tokenize(grammar, code);
+    Ok(match filetype {
+        FileType::LaTeX | FileType::Pdf => to_latex(&tokens),
+        FileType::Default => to_ansi(&tokens),
+        FileType::AsciiDoctor
+        | FileType::CommonMark
+        | FileType::Markdown
+        | FileType::RMarkdown
+        | FileType::Html => to_html(&tokens),
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tokenizer
+
+// Consecutive text is one token, so the tokens cover all of {code}
+pub fn tokenize<'c>(grammar: &Grammar, code: &'c str) -> Vec<(Kind, &'c str)> {
+    let mut tokens = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+    let mut prev = None;
+    while let Some(c) = code[i..].chars().next() {
+        let rest = &code[i..];
+        let is_word_start = !prev.is_some_and(is_ident);
+        let (kind, len) = match match_token(grammar, rest, prev) {
+            Some(token) => token,
+            None if is_word_start && (c.is_alphabetic() || c == '_') => {
+                let len = rest.find(|c| !is_ident(c)).unwrap_or(rest.len());
+                let word = &rest[..len];
+                if grammar.keywords.contains(&word) {
+                    (Kind::Keyword, len)
+                } else if grammar.builtins.contains(&word) {
+                    (Kind::Builtin, len)
+                } else {
+                    (Kind::Text, len)
+                }
+            }
+            None => (Kind::Text, c.len_utf8()),
+        };
+
+        if kind != Kind::Text {
+            if text_start < i {
+                tokens.push((Kind::Text, &code[text_start..i]));
+            }
+            tokens.push((kind, &rest[..len]));
+            text_start = i + len;
+        }
+        prev = rest[..len].chars().next_back();
+        i += len;
+    }
+    if text_start < code.len() {
+        tokens.push((Kind::Text, &code[text_start..]));
+    }
+    tokens
+}
+
+// Everything but words, as those need the previous character too
+fn match_token(grammar: &Grammar, rest: &str, prev: Option<char>) -> Option<(Kind, usize)> {
+    let is_after_space = prev.is_none_or(char::is_whitespace);
+    for prefix in grammar.line_comments {
+        if rest.starts_with(prefix) && (is_after_space || !grammar.comments_after_space) {
+            return Some((Kind::Comment, rest.find('\n').unwrap_or(rest.len())));
+        }
+    }
+    if let Some((open, close)) = grammar.block_comment {
+        if let Some(inside) = rest.strip_prefix(open) {
+            let len = inside
+                .find(close)
+                .map_or(rest.len(), |end| open.len() + end + close.len());
+            return Some((Kind::Comment, len));
+        }
+    }
+    for (open, close, has_escapes) in grammar.strings {
+        if rest.starts_with(open) {
+            return Some((Kind::String, string_len(rest, open, close, *has_escapes)));
+        }
+    }
+    if grammar.has_char_literals && rest.starts_with('\'') {
+        // e.g. 'a' or '\n', but not the lifetime 'a
+        let end = match rest[1..].chars().next() {
+            Some('\\') => rest.get(3..).and_then(|s| s.find('\'')).filter(|end| *end < 10).map(|end| end + 4),
+            Some(c) => rest[1 + c.len_utf8()..].starts_with('\'').then(|| 2 + c.len_utf8()),
+            None => None,
+        };
+        if let Some(len) = end {
+            return Some((Kind::String, len));
+        }
+    }
+
+    let first = rest.chars().next()?;
+    if Some(first) == grammar.variable_prefix {
+        let name = &rest[1..];
+        let len = if name.starts_with('{') {
+            name.find('}').map_or(name.len(), |end| end + 1)
+        } else if name.starts_with(|c: char| c.is_ascii_digit() || "@#?$!*-".contains(c)) {
+            1
+        } else {
+            name.find(|c| !is_ident(c)).unwrap_or(name.len())
+        };
+        return (len > 0).then_some((Kind::Variable, 1 + len));
+    }
+    if first.is_ascii_digit() && !prev.is_some_and(is_ident) {
+        let mut len = 0;
+        let mut chars = rest.char_indices().peekable();
+        while let Some((j, c)) = chars.next() {
+            let is_decimal = c == '.' && chars.peek().is_some_and(|(_, c)| c.is_ascii_digit());
+            if !(is_ident(c) || is_decimal) {
+                break;
+            }
+            len = j + c.len_utf8();
+        }
+        return Some((Kind::Number, len));
+    }
+    None
+}
+
+// Unterminated strings run until the end of the code
+fn string_len(rest: &str, open: &str, close: &str, has_escapes: bool) -> usize {
+    let mut chars = rest[open.len()..].char_indices();
+    while let Some((j, c)) = chars.next() {
+        if has_escapes && c == '\\' {
+            chars.next();
+        } else if rest[open.len() + j..].starts_with(close) {
+            return open.len() + j + close.len();
+        }
+    }
+    rest.len()
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Output
+
+fn to_html(tokens: &[(Kind, &str)]) -> String {
+    let mut buffer = String::from("<div class=\"highlight\"><pre><span></span>");
+    for (kind, text) in tokens {
+        let escaped = escape(text, FileType::Html);
+        match kind {
+            Kind::Text => buffer.push_str(&escaped),
+            _ => buffer.push_str(&format!("<span class=\"{}\">{}</span>", kind.class(), escaped)),
+        }
+    }
+    push_final_newline(&mut buffer);
+    buffer.push_str("</pre></div>\n");
+    buffer
+}
+
+fn to_latex(tokens: &[(Kind, &str)]) -> String {
+    let mut buffer = String::from("\\begin{Verbatim}[commandchars=\\\\\\{\\}]\n");
+    for (kind, text) in tokens {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '\\' => escaped.push_str("\\PYZbs{}"),
+                '{' => escaped.push_str("\\PYZob{}"),
+                '}' => escaped.push_str("\\PYZcb{}"),
+                _ => escaped.push(c),
+            }
+        }
+        match kind {
+            Kind::Text => buffer.push_str(&escaped),
+            // Macros cannot span lines in 'Verbatim'
+            _ => {
+                let lines = escaped
+                    .split('\n')
+                    .map(|line| match line {
+                        "" => String::new(),
+                        _ => format!("\\PY{{{}}}{{{}}}", kind.class(), line),
+                    })
+                    .collect::<Vec<_>>();
+                buffer.push_str(&lines.join("\n"));
+            }
+        }
+    }
+    push_final_newline(&mut buffer);
+    buffer.push_str("\\end{Verbatim}\n");
+    buffer
+}
+
+fn to_ansi(tokens: &[(Kind, &str)]) -> String {
+    let mut buffer = String::new();
+    for (kind, text) in tokens {
+        match kind {
+            Kind::Text => buffer.push_str(text),
+            _ => {
+                buffer.push_str(kind.ansi());
+                buffer.push_str(text);
+                buffer.push_str("\x1b[0m");
+            }
+        }
+    }
+    buffer
+}
+
+fn push_final_newline(buffer: &mut String) {
+    if !buffer.ends_with('\n') {
+        buffer.push('\n');
+    }
+}
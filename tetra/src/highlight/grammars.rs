@@ -0,0 +1,153 @@
+//run: cargo test -- --nocapture
+
+// The languages that 'crate::highlight' knows. Adding one is only a matter
+// of describing it here.
+
+pub struct Grammar {
+    pub names: &'static [&'static str], // The first is the one shown in errors
+    pub keywords: &'static [&'static str],
+    pub builtins: &'static [&'static str],
+    pub line_comments: &'static [&'static str],
+    // e.g. "#" in sh only starts a comment after whitespace, unlike in "$#"
+    pub comments_after_space: bool,
+    pub block_comment: Option<(&'static str, &'static str)>,
+    // Opening and closing delimiters, and whether backslash escapes, tried in
+    // order so that e.g. '"""' comes before '"'
+    pub strings: &'static [(&'static str, &'static str, bool)],
+    // e.g. 'a' in Rust, where a lone ' is a lifetime
+    pub has_char_literals: bool,
+    pub variable_prefix: Option<char>,
+}
+
+const PLAIN: Grammar = Grammar {
+    names: &[],
+    keywords: &[],
+    builtins: &[],
+    line_comments: &[],
+    comments_after_space: false,
+    block_comment: None,
+    strings: &[],
+    has_char_literals: false,
+    variable_prefix: None,
+};
+
+const C_COMMENT: Option<(&str, &str)> = Some(("/*", "*/"));
+
+pub const GRAMMARS: &[Grammar] = &[
+    Grammar {
+        names: &["sh", "bash", "shell", "zsh"],
+        keywords: &[
+            "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done",
+            "case", "esac", "in", "function", "return", "exit", "local", "export",
+            "readonly", "select",
+        ],
+        builtins: &[
+            "echo", "printf", "cd", "pwd", "read", "test", "set", "unset", "shift",
+            "source", "eval", "exec", "trap", "alias", "type", "wait", "kill", "true",
+            "false",
+        ],
+        line_comments: &["#"],
+        comments_after_space: true,
+        strings: &[("'", "'", false), ("\"", "\"", true), ("`", "`", true)],
+        variable_prefix: Some('$'),
+        ..PLAIN
+    },
+    Grammar {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+            "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop",
+            "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self",
+            "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+            "where", "while",
+        ],
+        builtins: &[
+            "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8",
+            "i16", "i32", "i64", "i128", "isize", "f32", "f64", "String", "Vec",
+            "Option", "Result", "Box", "Some", "None", "Ok", "Err",
+        ],
+        line_comments: &["//"],
+        block_comment: C_COMMENT,
+        strings: &[("\"", "\"", true)],
+        has_char_literals: true,
+        ..PLAIN
+    },
+    Grammar {
+        names: &["python", "py", "python3"],
+        keywords: &[
+            "False", "None", "True", "and", "as", "assert", "async", "await", "break",
+            "class", "continue", "def", "del", "elif", "else", "except", "finally",
+            "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal",
+            "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+        ],
+        builtins: &[
+            "print", "len", "range", "int", "str", "float", "list", "dict", "set",
+            "tuple", "bool", "open", "type", "isinstance", "enumerate", "zip", "map",
+            "filter", "sorted", "super", "self", "object", "Exception",
+        ],
+        line_comments: &["#"],
+        strings: &[
+            ("\"\"\"", "\"\"\"", true),
+            ("'''", "'''", true),
+            ("\"", "\"", true),
+            ("'", "'", true),
+        ],
+        ..PLAIN
+    },
+    Grammar {
+        names: &["dot", "graphviz", "gv"],
+        keywords: &["graph", "digraph", "subgraph", "node", "edge", "strict"],
+        builtins: &[
+            "label", "shape", "color", "style", "fillcolor", "rankdir", "rank",
+            "fontname", "fontsize", "width", "height", "penwidth", "arrowhead",
+            "arrowtail", "dir",
+        ],
+        line_comments: &["//", "#"],
+        block_comment: C_COMMENT,
+        strings: &[("\"", "\"", true)],
+        ..PLAIN
+    },
+    // Preprocessor directives count as comments
+    Grammar {
+        names: &["c", "h"],
+        keywords: &[
+            "auto", "break", "case", "const", "continue", "default", "do", "else",
+            "enum", "extern", "for", "goto", "if", "inline", "register", "restrict",
+            "return", "sizeof", "static", "struct", "switch", "typedef", "union",
+            "volatile", "while",
+        ],
+        builtins: &[
+            "char", "double", "float", "int", "long", "short", "signed", "unsigned",
+            "void", "size_t", "bool", "NULL",
+        ],
+        line_comments: &["//", "#"],
+        block_comment: C_COMMENT,
+        strings: &[("\"", "\"", true)],
+        has_char_literals: true,
+        ..PLAIN
+    },
+    Grammar {
+        names: &["javascript", "js", "typescript", "ts"],
+        keywords: &[
+            "async", "await", "break", "case", "catch", "class", "const", "continue",
+            "default", "delete", "do", "else", "export", "extends", "finally", "for",
+            "function", "if", "import", "in", "instanceof", "let", "new", "of",
+            "return", "static", "super", "switch", "this", "throw", "try", "typeof",
+            "var", "void", "while", "yield",
+        ],
+        builtins: &[
+            "true", "false", "null", "undefined", "console", "Math", "JSON", "Object",
+            "Array", "String", "Number", "Promise",
+        ],
+        line_comments: &["//"],
+        block_comment: C_COMMENT,
+        strings: &[("\"", "\"", true), ("'", "'", true), ("`", "`", true)],
+        ..PLAIN
+    },
+    Grammar {
+        names: &["json"],
+        keywords: &["true", "false", "null"],
+        strings: &[("\"", "\"", true)],
+        ..PLAIN
+    },
+];
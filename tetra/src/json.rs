@@ -0,0 +1,168 @@
+//run: cargo test -- --nocapture
+
+// Just enough of a JSON parser for the data that documents read, i.e.
+// CSL-JSON bibliographies (see "bibliography/csl_json.rs") and the data of
+// tables (see "default_markup/tables.rs"), so that the dependency on serde
+// can be avoided
+
+pub fn parse(source: &str) -> Result<Json, String> {
+    let mut parser = Parser { source, cursor: 0 };
+    let json = parser.value()?;
+    parser.skip_whitespace();
+    if parser.cursor < source.len() {
+        return Err(parser.error("Expected the end of the file"));
+    }
+    Ok(json)
+}
+
+// Numbers are kept as written, as they are only ever output
+#[derive(Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, field: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == field).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    cursor: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => {
+                self.cursor += 1;
+                let mut fields = Vec::new();
+                while !self.close('}', fields.is_empty())? {
+                    self.skip_whitespace();
+                    let name = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(':')?;
+                    fields.push((name, self.value()?));
+                }
+                Ok(Json::Object(fields))
+            }
+            Some('[') => {
+                self.cursor += 1;
+                let mut items = Vec::new();
+                while !self.close(']', items.is_empty())? {
+                    items.push(self.value()?);
+                }
+                Ok(Json::Array(items))
+            }
+            Some('"') => self.string().map(Json::String),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let rest = &self.source[self.cursor..];
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                    .unwrap_or(rest.len());
+                self.cursor += len;
+                Ok(Json::Number(rest[..len].to_string()))
+            }
+            _ => {
+                for (word, json) in [("true", Json::Bool(true)), ("false", Json::Bool(false)), ("null", Json::Null)] {
+                    if self.source[self.cursor..].starts_with(word) {
+                        self.cursor += word.len();
+                        return Ok(json);
+                    }
+                }
+                Err(self.error("Expected a value"))
+            }
+        }
+    }
+
+    // Whether the object or array ends here, otherwise consumes the comma
+    // before the next value
+    fn close(&mut self, close: char, is_first: bool) -> Result<bool, String> {
+        self.skip_whitespace();
+        if self.peek() == Some(close) {
+            self.cursor += 1;
+            Ok(true)
+        } else if is_first {
+            Ok(false)
+        } else {
+            self.expect(',').map(|_| false)
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut output = String::new();
+        let mut chars = self.source[self.cursor..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.cursor += i + 1;
+                    return Ok(output);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => output.push('\n'),
+                    Some('t') => output.push('\t'),
+                    Some('r') => output.push('\r'),
+                    Some('b') => output.push('\u{8}'),
+                    Some('f') => output.push('\u{C}'),
+                    Some('u') => {
+                        let mut code = hex(&mut chars).ok_or_else(|| self.error("Invalid \\u escape"))?;
+                        // A surrogate pair, e.g. "\uD83D\uDE00"
+                        if (0xD800..0xDC00).contains(&code) {
+                            let low = match (chars.next(), chars.next()) {
+                                (Some((_, '\\')), Some((_, 'u'))) => hex(&mut chars),
+                                _ => None,
+                            };
+                            code = match low {
+                                Some(low) if (0xDC00..0xE000).contains(&low) => 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00),
+                                _ => return Err(self.error("Invalid \\u escape")),
+                            };
+                        }
+                        output.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                    Some(c) => output.push(c),
+                    None => break,
+                },
+                c => output.push(c),
+            }
+        }
+        Err(self.error("A string is never closed"))
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.cursor += c.len_utf8();
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected {:?}", c)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.cursor..];
+        self.cursor += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.cursor..].chars().next()
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.source[..self.cursor].matches('\n').count() + 1;
+        format!("line {}: {}", line, message)
+    }
+}
+
+fn hex<I: Iterator<Item = (usize, char)>>(chars: &mut I) -> Option<u32> {
+    (0..4).try_fold(0, |code, _| Some(code * 16 + chars.next()?.1.to_digit(16)?))
+}
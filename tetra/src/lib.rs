@@ -11,10 +11,13 @@ pub mod parser;
 #[macro_use]
 pub mod run;
 pub mod api;
+pub mod bibliography;
+pub mod highlight;
+mod json;
 mod default_markup;
 
 pub use default_markup::default_context;
-pub use framework::Token;
+pub use framework::{Source, Token};
 
 //use std::fmt::Debug;
 //
@@ -7,6 +7,8 @@ pub use sexpr::SexprOutput;
 pub use ast::{AstOutput, Command};
 
 pub use lexer::process as step1_lex;
+pub(crate) use lexer::ident_len;
+pub use lexer::LexType;
 pub use sexpr::process as step2_to_sexpr;
 pub use ast::process as step3_to_ast;
 
@@ -4,9 +4,11 @@
 // Removes the superfluous redirections, resolves what Stdin references,
 // and removes gaps.
 
+use std::fmt::Write as _; // clippy: import without risk of name clashing
+
 use super::sexpr::Sexpr;
 use super::{Item, Label, Param, SexprOutput};
-use crate::framework::Token;
+use crate::framework::{Source, Token};
 
 pub struct AstOutput(pub Vec<Command>, pub Vec<Token<Param>>, pub Vec<usize>);
 pub type ParseError = Token<&'static str>;
@@ -168,7 +170,7 @@ fn resolve_stdin_and_optimise(
                 Item::Str => item.remap(Param::Str),
                 Item::Literal(s) => item.remap(Param::Literal(s)),
                 Item::Ident => item.remap(Param::Ident),
-                Item::Key => todo!("Optional arguments are WIP"),
+                Item::Key => item.remap(Param::Key),
 
                 // These branches made impossible by sexpr.rs parse step
                 Item::Func
@@ -348,3 +350,90 @@ impl Command {
         display
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+// Graphviz export of the command graph, i.e. a visual version of the
+// commented-out debug printing in 'process()'
+
+// Max chars of source displayed per node
+const DOT_SNIPPET_LEN: usize = 40;
+
+impl AstOutput {
+    // Data flows along the edges, i.e. 'a -> b' means {b} waits on {a} and
+    // the edge label is which argument of {b} receives the output of {a}
+    pub fn to_dot(&self, original: &str) -> String {
+        let AstOutput(cmds, args, _) = self;
+        let mut buffer = String::new();
+        buffer.push_str("digraph tetra {\n");
+        buffer.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+
+        for (i, cmd) in cmds.iter().enumerate() {
+            write!(buffer, "  {} [label=\"", i).unwrap();
+            let mut label = format!("{}: {:?}", i, cmd.label.me);
+            match cmd.label.me {
+                Label::Ident | Label::Func => {
+                    write!(label, " {}", cmd.label.to_str(original)).unwrap()
+                }
+                Label::Assign | Label::Concat => {}
+            }
+            if let Some(source) = cmd.span(args) {
+                let snippet = source.to_str(original).lines().next().unwrap_or("").trim();
+                let snippet_close = snippet
+                    .char_indices()
+                    .nth(DOT_SNIPPET_LEN)
+                    .map(|(i, _)| i)
+                    .unwrap_or(snippet.len());
+                write!(label, "\nline {}: {}", source.line_number(original), &snippet[..snippet_close])
+                    .unwrap();
+                if snippet_close < snippet.len() {
+                    label.push('…');
+                }
+            }
+            dot_push_escaped(&mut buffer, &label);
+            buffer.push('"');
+            // The knit of the final document
+            if i + 1 == cmds.len() {
+                buffer.push_str(", peripheries=2");
+            }
+            buffer.push_str("];\n");
+        }
+
+        for (i, cmd) in cmds.iter().enumerate() {
+            for (arg_index, arg) in args[cmd.args.0..cmd.args.1].iter().enumerate() {
+                if let Param::Reference(j) = arg.me {
+                    writeln!(buffer, "  {} -> {} [label=\"{}\"];", j, i, arg_index).unwrap();
+                }
+            }
+        }
+        buffer.push_str("}\n");
+        buffer
+    }
+}
+
+impl Command {
+    // Spans the label and all its arguments. 'Label::Concat' has no source
+    // of its own. Empty ranges are the invisible/generated tokens.
+    pub(crate) fn span(&self, args: &[Token<Param>]) -> Option<Source> {
+        std::iter::once(&self.label.source)
+            .chain(args[self.args.0..self.args.1].iter().map(|a| &a.source))
+            .filter_map(|source| match source {
+                Source::Range(start, close) if start != close => {
+                    Some((*std::cmp::min(start, close), *std::cmp::max(start, close)))
+                }
+                _ => None,
+            })
+            .reduce(|(a, b), (c, d)| (std::cmp::min(a, c), std::cmp::max(b, d)))
+            .map(|(start, close)| Source::Range(start, close))
+    }
+}
+
+fn dot_push_escaped(buffer: &mut String, to_push: &str) {
+    for c in to_push.chars() {
+        match c {
+            '"' => buffer.push_str("\\\""),
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            c => buffer.push(c),
+        }
+    }
+}
@@ -257,6 +257,28 @@ fn is_invalid_second_ident_char(c: char) -> bool {
     c != '_' && (c.is_ascii_punctuation() || c.is_whitespace())
 }
 
+// Byte length of the ident at the start of {s}. Idents may be dotted for
+// namespaced functions (e.g. "bib.cite"), but only if a letter follows the
+// '.' so that "a.", "a .", etc. are still an ident then 'LexType::Stdin'
+pub fn ident_len(s: &str) -> usize {
+    let mut iter = s.char_indices().peekable();
+    match iter.next() {
+        Some((_, c)) if c.is_ascii_alphabetic() => {}
+        _ => return 0,
+    }
+    while let Some((i, c)) = iter.next() {
+        if c == '.' {
+            match iter.peek() {
+                Some((_, next)) if next.is_ascii_alphabetic() => {}
+                _ => return i,
+            }
+        } else if is_invalid_second_ident_char(c) {
+            return i;
+        }
+    }
+    s.len()
+}
+
 fn lex_code_body(
     mode: &mut CodeMode,
     walker: &mut Walker,
@@ -289,9 +311,7 @@ fn lex_code_body(
                 !is_invalid_second_ident_char(ch),
                 "First char of idents should also satisfy second+ char requirements"
             );
-            let ident_post = walker.original[post..]
-                .find(is_invalid_second_ident_char)
-                .unwrap_or(0);
+            let ident_post = ident_len(&walker.original[curr..]) - (post - curr);
             let peek_post = walker.post + ident_post;
             if walker.original[peek_post..].starts_with('(') {
                 walker.increment_post_by(ident_post + len_utf8!('(' => 1));
@@ -439,14 +459,12 @@ fn reconstruct_string(original: &str, lexemes: &[Lexeme]) -> String {
             }
 
             LexType::Ident => {
-                assert!(text.find(is_invalid_second_ident_char).is_none());
+                assert_eq!(ident_len(text), text.len());
                 buffer.push_str(text);
             }
             LexType::IdentParen => {
                 let penultimate_post = text.len() - len_utf8!('(' => 1);
-                assert!(text[..penultimate_post]
-                    .find(is_invalid_second_ident_char)
-                    .is_none());
+                assert_eq!(ident_len(&text[..penultimate_post]), penultimate_post);
                 assert_eq!("(", &text[penultimate_post..]);
                 buffer.push_str(text);
             }
@@ -14,18 +14,36 @@ macro_rules! unwrap {
     };
 }
 
+mod arena;
+mod compose;
+mod documentation;
+pub mod escape;
 mod executor;
 //pub mod exec_async;
 mod function;
+mod hooks;
+mod parameters;
+mod render;
+mod trace;
+mod typed;
 pub mod utility;
 
-use function::{Func};
+use arena::Arena;
+use function::{Batcher, Func};
 pub use function::{PureFunction, PureResult, StatefulFunction, StatefulResult};
+pub use function::{boxed_pure, boxed_stateful};
+pub use function::{Batch, Collect};
 pub use function::{Dirty, DirtyValue, LIMITED, UNLIMITED};
+pub use hooks::{PostKnitHook, PreLexHook};
+pub use documentation::{Documentation, FunctionInfo};
+pub use parameters::{Keywords, ParamDef, ParameterKind, Types};
+pub use render::Render;
+pub use trace::{Trace, TraceEvent, Tracer};
+pub use typed::{FromValue, Signature, ValueType};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-use crate::api::Config;
+use crate::api::{Config, Splice};
 use crate::framework::Source;
 use crate::parser::{self, AstOutput, Param};
 use crate::Token;
@@ -45,11 +63,11 @@ pub enum Error {
 
 impl Error {
     // @TODO: consider whether to output Cow<str> or not
-    fn to_display(&self, original: &str, label: &Source, args: &[Token<Param>]) -> String {
+    fn to_display(&self, original: &str, label: &Source, args: &[Token<Param>], splices: &[Splice]) -> String {
         println!("{:?} {:?}", args, self);
         match self {
-            Error::Arg(i, s) => format!("{} {}", args[*i].source.get_context(original), s),
-            Error::Generic(s) => format!("{} {}", label.get_context(original), s),
+            Error::Arg(i, s) => format!("{} {}", args[*i].source.get_context_in(original, splices), s),
+            Error::Generic(s) => format!("{} {}", label.get_context_in(original, splices), s),
             Error::Contextless(s) => s.to_string(),
         }
     }
@@ -110,16 +128,27 @@ define_value! { const VALUE_AS_STR = enum Value: u8 {
     BOOL   = Bool(bool),
     LIST   = List(Vec<Value<'source, CustomValue>>),
     CUSTOM = Custom(CustomValue),
+    // Last so that the constants above keep their values
+    BYTES  = Bytes(Cow<'source, [u8]>), // e.g. images output by 'run'
 }}
 
 ////////////////////////////////////////////////////////////////////////////////
 
 // {Variables} is used by user-defined functions and by the executor internally
 //
-// We are wrapping the HashMap because we plan on using an arena allocator
-// later on and hiding that implementation detail from user-defined functions
+// We are wrapping the HashMap to hide that owned text can be moved into the
+// arena (see "run/arena.rs") from user-defined functions
 pub struct Variables<'source, K, V> {
     bindings: HashMap<K, Value<'source, V>>,
+    arena: &'source Arena,
+}
+impl<'a, K, V> Variables<'a, K, V> {
+    fn new(arena: &'a Arena) -> Self {
+        Self {
+            bindings: HashMap::new(),
+            arena,
+        }
+    }
 }
 impl<'a, K: Eq + Hash, V> Variables<'a, K, V> {
     pub fn get(&self, key: &K) -> Option<&Value<'a, V>> {
@@ -132,13 +161,24 @@ impl<'a, K: Eq + Hash, V> Variables<'a, K, V> {
     pub fn insert(&mut self, key: K, value: Value<'a, V>) -> Option<Value<'a, V>> {
         self.bindings.insert(key, value)
     }
+
+    // Same as 'insert()' but makes cloning the text in {value} free, use this
+    // for values that are fetched and returned several times.
+    // Do not use this if you plan on mutating the text via 'get_mut()'.
+    pub fn insert_shared(&mut self, key: K, mut value: Value<'a, V>) -> Option<Value<'a, V>> {
+        self.arena.share(&mut value);
+        self.bindings.insert(key, value)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 // Main context
 pub struct Bindings<'a, K, V> {
-    functions: HashMap<&'a str, Func<'a, K, V>>,
-    parameters: Vec<ValueRepr>,
+    functions: HashMap<Cow<'a, str>, Func<'a, K, V>>,
+    documentation: HashMap<Cow<'a, str>, Documentation<'a>>,
+    pre_lex_hooks: Vec<PreLexHook<'a>>,
+    post_knit_hooks: Vec<hooks::TriggeredHook<'a>>,
+    batchers: HashMap<K, Batcher<'a, V>>, // See 'register_collector()'
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::new_without_default))]
@@ -146,28 +186,62 @@ impl<'a, K, V> Bindings<'a, K, V> {
     pub fn new() -> Self {
         Self {
             functions: HashMap::new(),
-            parameters: Vec::new(),
+            documentation: HashMap::new(),
+            pre_lex_hooks: Vec::new(),
+            post_knit_hooks: Vec::new(),
+            batchers: HashMap::new(),
         }
     }
 
     pub fn build(original: &str) -> Result<AstOutput, String> {
+        Self::build_spliced(original, &[])
+    }
+
+    // Same as 'build()' but errors in {splices} name their file
+    pub fn build_spliced(original: &str, splices: &[Splice]) -> Result<AstOutput, String> {
         parser::step1_lex(original, true)
             .and_then(|lexemes| parser::step2_to_sexpr(&lexemes, original))
             .and_then(|sexprs| parser::step3_to_ast(&sexprs, original))
-            .map_err(|token| format!("{} {}", token.get_context(original), token.me))
+            .map_err(|token| format!("{} {}", token.source.get_context_in(original, splices), token.me))
     }
 
     // in "run/function.rs"
     //pub fn register_pure_function;
     //pub fn register_stateful_function
+
+    // in "run/compose.rs"
+    //pub fn extend();
+    //pub fn mount();
+    //pub fn remove();
+
+    // in "run/documentation.rs"
+    //pub fn document();
+    //pub fn functions();
+
+    // in "run/hooks.rs"
+    //pub fn register_pre_lex_hook();
+    //pub fn register_post_knit_hook();
+    //pub fn register_post_knit_hook_for();
+    //pub fn pre_lex();
 }
 
-impl<'a, K, V: Clone> Bindings<'a, K, V> {
+impl<'a, K, V: Clone + Render> Bindings<'a, K, V> {
     // Defined in the "run/executor.rs"
     //pub fn run();
 
-    pub fn compile(&self, original: &str, config: Config) -> Result<String, String> {
-        self.run(&Self::build(original)?, config, original)
+    pub fn compile(&self, original: &str, mut config: Config) -> Result<String, String> {
+        let source = self.pre_lex(original, &mut config)?;
+        self.run(&Self::build_spliced(&source, &config.splices)?, config, &source)
+    }
+
+    pub fn compile_with_tracer<T: Tracer>(
+        &self,
+        original: &str,
+        mut config: Config,
+        tracer: &mut T,
+    ) -> Result<String, String> {
+        let source = self.pre_lex(original, &mut config)?;
+        self.run_with_tracer(&Self::build_spliced(&source, &config.splices)?, config, &source, tracer)
     }
 
 }
@@ -0,0 +1,83 @@
+//run: cargo test -- --nocapture
+
+// Append-only storage for the owned text that the executor passes around.
+//
+// Outputs with several dependents (e.g. a multi-megabyte SVG bound to a
+// variable used by several functions) would otherwise be deep-copied for
+// each dependent. Instead, we move the 'Cow::Owned' string into the arena
+// and swap it out for a 'Cow::Borrowed' pointing into the arena, so every
+// clone afterwards is just copying a pointer.
+//
+// Binary outputs (e.g. PNG plots) are shared the same way.
+//
+// Moving a 'String' into the arena does not copy its contents, so this is
+// always cheap. Nothing is freed until the arena itself is dropped at the
+// end of 'run()'.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use super::Value;
+
+#[derive(Default)]
+pub struct Arena {
+    strings: RefCell<Vec<String>>,
+    bytes: RefCell<Vec<Vec<u8>>>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self {
+            strings: RefCell::new(Vec::new()),
+            bytes: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn alloc_str(&self, s: String) -> &str {
+        let mut strings = self.strings.borrow_mut();
+        let ptr = s.as_str() as *const str;
+        strings.push(s);
+        // SAFETY: The heap buffer of a 'String' does not move when {strings}
+        //         reallocates, and we never mutate or remove any 'String'
+        //         until {self} is dropped, which the borrow on {self} forbids
+        //         while the returned reference is alive.
+        unsafe { &*ptr }
+    }
+
+    // Same as 'alloc_str()' but for binary data
+    pub fn alloc_bytes(&self, b: Vec<u8>) -> &[u8] {
+        let mut bytes = self.bytes.borrow_mut();
+        let ptr = b.as_slice() as *const [u8];
+        bytes.push(b);
+        // SAFETY: Same as for 'alloc_str()'
+        unsafe { &*ptr }
+    }
+
+    // Converts all owned text in {value} to text borrowed from the arena
+    pub fn share<'a, V>(&'a self, value: &mut Value<'a, V>) {
+        match value {
+            Value::Text(cow @ Cow::Owned(_)) => {
+                let owned = match std::mem::replace(cow, Cow::Borrowed("")) {
+                    Cow::Owned(s) => s,
+                    Cow::Borrowed(_) => unreachable!(),
+                };
+                *cow = Cow::Borrowed(self.alloc_str(owned));
+            }
+            Value::Bytes(cow @ Cow::Owned(_)) => {
+                let owned = match std::mem::replace(cow, Cow::Borrowed(&[])) {
+                    Cow::Owned(b) => b,
+                    Cow::Borrowed(_) => unreachable!(),
+                };
+                *cow = Cow::Borrowed(self.alloc_bytes(owned));
+            }
+            Value::List(list) => list.iter_mut().for_each(|v| self.share(v)),
+            Value::Null
+            | Value::Text(Cow::Borrowed(_))
+            | Value::Bytes(Cow::Borrowed(_))
+            | Value::Usize(_)
+            | Value::Char(_)
+            | Value::Bool(_)
+            | Value::Custom(_) => {}
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//run: cargo test -- --nocapture
+
+// Building a flavour out of other flavours instead of copying
+// "default_markup.rs" to add a single function, e.g.
+//
+//     let mut ctx = tetra::default_context();
+//     ctx.mount("bib", bibliography_flavour())?; // 'bib.cite', 'bib.references'
+//     ctx.remove("run");                         // No shell access
+//     ctx.register_pure_function("include", &sandboxed_include, LIMITED, &[v::TEXT]);
+//
+// Registering a function with a name that is already taken replaces it, so
+// overriding is just registering again. 'extend()' and 'mount()' instead
+// refuse to replace anything so that conflicts are not silently resolved.
+// The hooks of {other} (see "run/hooks.rs") run after those of {self}.
+//
+// All flavours must share the same custom key {K} and value {V} types.
+
+use std::borrow::Cow;
+use std::hash::Hash;
+
+use super::Bindings;
+use crate::parser::ident_len;
+
+impl<'a, K: Eq + Hash, V> Bindings<'a, K, V> {
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    // Returns whether there was a function named {name} to remove
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.documentation.remove(name);
+        self.functions.remove(name).is_some()
+    }
+
+    // Adds all the functions of {other}. If any of the names are already
+    // taken, nothing is added and the conflicting names are returned sorted
+    pub fn extend(&mut self, other: Bindings<'a, K, V>) -> Result<(), Vec<String>> {
+        self.merge(other, None, false)
+    }
+
+    // Same as 'extend()' but functions in {other} replace those in {self}
+    pub fn extend_overriding(&mut self, other: Bindings<'a, K, V>) {
+        self.merge(other, None, true).unwrap();
+    }
+
+    // Same as 'extend()' but {other}'s functions are called with the prefix
+    // "{namespace}." in markup, e.g. `{$ bib.cite "a" $}`. If {namespace} is
+    // not an identifier (or is empty), nothing is added and it is returned
+    // as the only name
+    //
+    // Only the functions are namespaced. Hooks work on the whole document,
+    // so those of {other} run on all of it, not just on the calls of its
+    // functions. The exception is post-knit hooks registered for a function
+    // (e.g. that of 'toc'), which run when "{namespace}.{function}" is called.
+    // Hooks that look for calls by name (e.g. that of 'include') still look
+    // for the name without the prefix.
+    pub fn mount(&mut self, namespace: &str, other: Bindings<'a, K, V>) -> Result<(), Vec<String>> {
+        if namespace.is_empty() || ident_len(namespace) != namespace.len() {
+            return Err(vec![namespace.to_string()]);
+        }
+        self.merge(other, Some(namespace), false)
+    }
+
+    fn merge(
+        &mut self,
+        other: Bindings<'a, K, V>,
+        namespace: Option<&str>,
+        is_override: bool,
+    ) -> Result<(), Vec<String>> {
+        let rename = |name: Cow<'a, str>| match namespace {
+            Some(namespace) => Cow::Owned(format!("{}.{}", namespace, name)),
+            None => name,
+        };
+
+        if !is_override {
+            let mut conflicts = other
+                .functions
+                .keys()
+                .map(|name| rename(name.clone()))
+                .filter(|name| self.functions.contains_key(name))
+                .map(Cow::into_owned)
+                .collect::<Vec<_>>();
+            if !conflicts.is_empty() {
+                conflicts.sort_unstable();
+                return Err(conflicts);
+            }
+        }
+
+        let Bindings { functions, mut documentation, pre_lex_hooks, post_knit_hooks, batchers } = other;
+        self.pre_lex_hooks.extend(pre_lex_hooks);
+        let post_knit_hooks = post_knit_hooks.into_iter().map(|(name, hook)| (name.map(rename), hook));
+        self.post_knit_hooks.extend(post_knit_hooks);
+        // The collectors of {self} keep batching for their key (see
+        // 'register_collector()'), those of {other} keep their own batch
+        for (key, batcher) in batchers {
+            self.batchers.entry(key).or_insert(batcher);
+        }
+        for (name, func) in functions {
+            let doc = documentation.remove(&name);
+            let name = rename(name);
+            match doc {
+                Some(doc) => self.documentation.insert(name.clone(), doc),
+                None => self.documentation.remove(&name),
+            };
+            self.functions.insert(name, func);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//run: cargo test -- --nocapture
+
+// Human-readable descriptions of the registered functions, so that a flavour
+// can be listed with `tetra-cli functions` and its reference documentation
+// can be generated as Markdown (which tetra can then compile) or JSON.
+//
+// Documentation is optional and kept separate from 'Func' so that the
+// executor never has to look at it. Functions defined with 'typed_function!'
+// get their parameter names for free.
+
+use std::borrow::Cow;
+use std::fmt::Write as _; // clippy: import without risk of name clashing
+
+use common::{json_push_str, FileType};
+
+use super::function::Func;
+use super::{Bindings, ParameterKind, Render, Types, Value};
+
+#[derive(Clone, Debug, Default)]
+pub struct Documentation<'a> {
+    pub summary: Cow<'a, str>,
+    pub parameters: Vec<Cow<'a, str>>, // Names, in the same order as the types
+    pub examples: Vec<Cow<'a, str>>,   // Markup snippets, e.g. `{$ cite "a" $}`
+}
+
+// A view into a registered function for listing
+pub struct FunctionInfo<'b> {
+    pub name: &'b str,
+    pub is_stateful: bool,
+    pub parameters: Vec<ParameterInfo<'b>>,
+    pub documentation: Option<&'b Documentation<'b>>,
+}
+
+pub struct ParameterInfo<'b> {
+    pub name: Option<&'b str>,
+    pub types: Types,
+    pub kind: ParameterKind,
+    pub default: Option<String>, // As it would be written in markup
+}
+
+impl<'a, K, V> Bindings<'a, K, V> {
+    // Attach documentation to {name}. If {parameters} is empty, keep the names
+    // that 'register_typed_*_function()' already filled in.
+    pub fn document<S: Into<Cow<'a, str>>>(
+        &mut self,
+        name: &str,
+        summary: S,
+        parameters: &[&'a str],
+        examples: &[&'a str],
+    ) {
+        let key = match self.functions.get_key_value(name) {
+            Some((key, _)) => key.clone(),
+            None => panic!("Documenting {:?} before it is registered", name),
+        };
+        let doc = self.documentation.entry(key).or_default();
+        doc.summary = summary.into();
+        if !parameters.is_empty() {
+            doc.parameters = parameters.iter().map(|s| Cow::Borrowed(*s)).collect();
+        }
+        doc.examples = examples.iter().map(|s| Cow::Borrowed(*s)).collect();
+    }
+}
+
+impl<'a, K, V: Render> Bindings<'a, K, V> {
+    // Sorted by name
+    pub fn functions(&self) -> impl Iterator<Item = FunctionInfo<'_>> {
+        let mut names = self.functions.keys().collect::<Vec<_>>();
+        names.sort_unstable();
+        names.into_iter().map(|name| self.function_info(name).unwrap())
+    }
+
+    pub fn function_info(&self, name: &str) -> Option<FunctionInfo<'_>> {
+        let (name, func) = self.functions.get_key_value(name)?;
+        let (is_stateful, params) = match func {
+            Func::Pure(_, params) => (false, params),
+            Func::Stateful(_, params) => (true, params),
+        };
+        let documentation = self.documentation.get(name.as_ref());
+        let parameters = params
+            .iter()
+            .enumerate()
+            .map(|(i, (types, kind, default))| ParameterInfo {
+                name: documentation
+                    .and_then(|doc| doc.parameters.get(i))
+                    .map(|s| s.as_ref()),
+                types,
+                kind,
+                default: default.map(display_default),
+            })
+            .chain(params.keywords().map(|(name, types)| ParameterInfo {
+                name: Some(name),
+                types,
+                kind: ParameterKind::Keyword,
+                default: None,
+            }))
+            .collect();
+        Some(FunctionInfo {
+            name,
+            is_stateful,
+            parameters,
+            documentation,
+        })
+    }
+}
+
+impl<'b> FunctionInfo<'b> {
+    pub fn is_variadic(&self) -> bool {
+        self.parameters.iter().any(|p| p.kind == ParameterKind::Rest)
+    }
+
+    // e.g. "run(cmd: Text, args: Text..., body: Text)", optional parameters
+    // are in square brackets and keyword parameters after a semicolon, e.g.
+    // "cite(citekey: Text...; page: Text)"
+    pub fn signature(&self) -> String {
+        let mut buffer = String::with_capacity(self.name.len() + 2);
+        buffer.push_str(self.name);
+        buffer.push('(');
+        let keywords_start = self
+            .parameters
+            .iter()
+            .position(|param| param.kind == ParameterKind::Keyword);
+        for (i, param) in self.parameters.iter().enumerate() {
+            if Some(i) == keywords_start {
+                buffer.push_str("; ");
+            } else if i > 0 {
+                buffer.push_str(", ");
+            }
+            param.push_signature(&mut buffer);
+        }
+        buffer.push(')');
+        buffer
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut buffer = self.signature();
+        if self.is_stateful {
+            buffer.push_str("  [stateful]");
+        }
+        buffer.push('\n');
+        if let Some(doc) = self.documentation {
+            for line in doc.summary.lines() {
+                writeln!(buffer, "    {}", line).unwrap();
+            }
+            for example in &doc.examples {
+                writeln!(buffer, "    e.g. {}", example).unwrap();
+            }
+        }
+        buffer
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut buffer = format!("## `{}`\n\n", self.signature());
+        if self.is_stateful {
+            buffer.push_str("*Stateful*, may run over several passes.\n\n");
+        }
+        if let Some(doc) = self.documentation {
+            if !doc.summary.is_empty() {
+                buffer.push_str(&doc.summary);
+                buffer.push_str("\n\n");
+            }
+            for example in &doc.examples {
+                // Text mode has no escapes, so compiling this output with
+                // tetra will run the examples (which is a test of sorts)
+                writeln!(buffer, "```\n{}\n```\n", example).unwrap();
+            }
+        }
+        buffer
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut buffer = String::from("{\"name\":");
+        json_push_str(&mut buffer, self.name);
+        buffer.push_str(",\"signature\":");
+        json_push_str(&mut buffer, &self.signature());
+        write!(
+            buffer,
+            ",\"stateful\":{},\"variadic\":{},\"parameters\":[",
+            self.is_stateful,
+            self.is_variadic(),
+        )
+        .unwrap();
+        for (i, param) in self.parameters.iter().enumerate() {
+            if i > 0 {
+                buffer.push(',');
+            }
+            buffer.push_str("{\"name\":");
+            match param.name {
+                Some(name) => json_push_str(&mut buffer, name),
+                None => buffer.push_str("null"),
+            }
+            buffer.push_str(",\"type\":");
+            json_push_str(&mut buffer, &param.types.to_display());
+            buffer.push_str(",\"kind\":");
+            json_push_str(&mut buffer, match param.kind {
+                ParameterKind::Required => "required",
+                ParameterKind::Optional => "optional",
+                ParameterKind::Rest => "rest",
+                ParameterKind::Keyword => "keyword",
+            });
+            buffer.push_str(",\"default\":");
+            match &param.default {
+                Some(default) => json_push_str(&mut buffer, default),
+                None => buffer.push_str("null"),
+            }
+            buffer.push('}');
+        }
+        buffer.push_str("],\"summary\":");
+        match self.documentation {
+            Some(doc) => json_push_str(&mut buffer, &doc.summary),
+            None => buffer.push_str("null"),
+        }
+        buffer.push_str(",\"examples\":[");
+        if let Some(doc) = self.documentation {
+            for (i, example) in doc.examples.iter().enumerate() {
+                if i > 0 {
+                    buffer.push(',');
+                }
+                json_push_str(&mut buffer, example);
+            }
+        }
+        buffer.push_str("]}");
+        buffer
+    }
+}
+
+impl<'b> ParameterInfo<'b> {
+    // e.g. "lang: Text", "[lines: Usize = 10]", "args: Text..."
+    fn push_signature(&self, buffer: &mut String) {
+        let types = self.types.to_display();
+        if self.kind == ParameterKind::Optional {
+            buffer.push('[');
+        }
+        match self.name {
+            Some(name) => write!(buffer, "{}: {}", name, types).unwrap(),
+            None => buffer.push_str(&types),
+        }
+        if let Some(default) = &self.default {
+            write!(buffer, " = {}", default).unwrap();
+        }
+        match self.kind {
+            ParameterKind::Required | ParameterKind::Keyword => {}
+            ParameterKind::Optional => buffer.push(']'),
+            ParameterKind::Rest => buffer.push_str("..."),
+        }
+    }
+}
+
+// Custom values cannot be written in markup, so show how they render instead
+fn display_default<V: Render>(value: &Value<V>) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Text(s) => format!("{:?}", s),
+        Value::Bytes(b) => format!("{} bytes", b.len()),
+        Value::Usize(x) => x.to_string(),
+        Value::Char(c) => format!("{:?}", c),
+        Value::Bool(b) => b.to_string(),
+        Value::List(l) => {
+            let items = l.iter().map(display_default).collect::<Vec<_>>();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Custom(c) => c.render(FileType::Default).into_owned(),
+    }
+}
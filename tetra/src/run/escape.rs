@@ -0,0 +1,44 @@
+//run: cargo test -- --nocapture
+
+// Escaping text (e.g. from a file or a program) so that the output filetype
+// shows it as is instead of reading it as markup. Only what would otherwise
+// be markup is escaped: '&', '<', '>' and '"' for HTML (so the text can also
+// go in attributes), the special characters of LaTeX and the inline syntax
+// of the Markdowns. AsciiDoctor and plain text are left as they are.
+//
+// This is for prose, so code that needs more (e.g. LaTeX 'Verbatim') and
+// places with their own syntax (e.g. table cells) build on top of it.
+
+use crate::api::FileType;
+
+pub fn escape(text: &str, filetype: FileType) -> String {
+    let mut buffer = String::with_capacity(text.len());
+    push_escaped(&mut buffer, text, filetype);
+    buffer
+}
+
+pub fn push_escaped(buffer: &mut String, text: &str, filetype: FileType) {
+    for c in text.chars() {
+        match (filetype, c) {
+            (FileType::Html, '&') => buffer.push_str("&amp;"),
+            (FileType::Html, '<') => buffer.push_str("&lt;"),
+            (FileType::Html, '>') => buffer.push_str("&gt;"),
+            (FileType::Html, '"') => buffer.push_str("&quot;"),
+            (FileType::LaTeX | FileType::Pdf, '\\') => buffer.push_str("\\textbackslash{}"),
+            (FileType::LaTeX | FileType::Pdf, '~') => buffer.push_str("\\textasciitilde{}"),
+            (FileType::LaTeX | FileType::Pdf, '^') => buffer.push_str("\\textasciicircum{}"),
+            (FileType::LaTeX | FileType::Pdf, '&' | '%' | '$' | '#' | '_' | '{' | '}') => {
+                buffer.push('\\');
+                buffer.push(c);
+            }
+            (
+                FileType::CommonMark | FileType::Markdown | FileType::RMarkdown,
+                '\\' | '*' | '_' | '[' | ']' | '`' | '<',
+            ) => {
+                buffer.push('\\');
+                buffer.push(c);
+            }
+            _ => buffer.push(c),
+        }
+    }
+}
@@ -1,13 +1,18 @@
 //run: cargo test -- --nocapture
 
 use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::collections::HashMap;
+use std::io::Write;
 use std::mem;
+use std::rc::Rc;
+use std::time::Instant;
 
-use super::utility::concat;
-use super::{Bindings, Dirty, DirtyValue, Func, Value, Variables};
+use super::trace::{output_len, summarise, TraceEvent, Tracer};
+use super::utility::{concat, write_value};
+use super::{Arena, Bindings, Dirty, DirtyValue, Error, Func, Render, Value, Variables};
 
-use crate::api::{Api, Config};
+use crate::api::{Api, Config, FileType};
 use crate::framework::Token;
 use crate::parser::{AstOutput, Command, Label, Param};
 
@@ -20,27 +25,65 @@ use crate::parser::{AstOutput, Command, Label, Param};
 
 const ITERATION_LIMIT: usize = 1000;
 
-impl<'a, K, V: Clone> Bindings<'a, K, V> {
+impl<'a, K, V: Clone + Render> Bindings<'a, K, V> {
     pub fn run(
         &self,
         ast: &AstOutput,
         config: Config,
         original: &str,
     ) -> Result<String, String> {
-        run(self, ast, config, original)
+        run(self, ast, config, original, &mut (), None)
+    }
+
+    // Same as 'run()' but writes each text cell of the knit to {writer} as
+    // soon as it and every cell before it are 'Dirty::Ready', instead of
+    // building the entire document in memory. Useful for progressive preview.
+    pub fn run_to_writer<W: Write>(
+        &self,
+        ast: &AstOutput,
+        config: Config,
+        original: &str,
+        mut writer: W,
+    ) -> Result<(), String> {
+        if self.has_post_knit_hooks(&ast.0, original) {
+            let output = run(self, ast, config, original, &mut (), None)?;
+            return writer
+                .write_all(output.as_bytes())
+                .map_err(|err| format!("Could not write output: {}", err));
+        }
+        run(self, ast, config, original, &mut (), Some(&mut writer)).map(|_| ())
+    }
+
+    // Same as 'run()' but reports every command executed to {tracer}
+    pub fn run_with_tracer<T: Tracer>(
+        &self,
+        ast: &AstOutput,
+        config: Config,
+        original: &str,
+        tracer: &mut T,
+    ) -> Result<String, String> {
+        run(self, ast, config, original, tracer, None)
     }
 }
 
-pub fn run<'a, K, V: Clone>(
+pub fn run<'a, K, V: Clone + Render, T: Tracer>(
     ctx: &Bindings<'a, K, V>,
     AstOutput(ast, args, _): &AstOutput,
     config: Config,
     original: &str,
+    tracer: &mut T,
+    // If provided, we stream the knit into {stream} and return an empty string
+    mut stream: Option<&mut dyn Write>,
 ) -> Result<String, String> {
-    let mut internal: HashMap<&str, Value<V>> = HashMap::new();
-    let mut external = Variables {
-        bindings: HashMap::new(),
+    // Declared first so that they outlive all the values borrowing from them
+    let metadata = Rc::new(OnceCell::new());
+    let api_for = |i: usize| {
+        let span = ast[i].span(args).unwrap_or_else(|| ast[i].label.source.clone());
+        Api::for_command(original, i, span, &config, &metadata)
     };
+    let arena = Arena::new();
+    let mut internal: HashMap<&str, Value<V>> = HashMap::new();
+    let mut external = Variables::new(&arena);
     let mut outputs: Vec<DirtyValue<V>> = Vec::with_capacity(ast.len());
     let mut binded_args = Vec::with_capacity(args.len());
 
@@ -57,16 +100,52 @@ pub fn run<'a, K, V: Clone>(
 
     let last_index = outputs.len() - 1;
     let mut iter_count = 0;
-    while let Dirty::Waiting = outputs[last_index].0 {
+    let run_start = Instant::now();
+
+    // When streaming, we never run the knit (the final concat), instead we
+    // write its arguments as they become ready. {knit_cursor} is the index
+    // of the next argument of the knit to write.
+    let knit = &ast[last_index];
+    let mut knit_cursor = 0;
+    let is_streaming = stream.is_some();
+    let is_finished = |outputs: &[DirtyValue<V>], knit_cursor: usize| {
+        if is_streaming {
+            knit_cursor == knit.args.1 - knit.args.0
+        } else {
+            matches!(outputs[last_index].0, Dirty::Ready)
+        }
+    };
+
+    macro_rules! flush_knit {
+        () => {
+            if let Some(writer) = stream.as_mut() {
+                knit_cursor = knit
+                    .write_ready_prefix(knit_cursor, ast, args, &binded_args, &mut outputs, config.output_filetype, writer)
+                    .map_err(|err| {
+                        err.to_display(original, &knit.label.source, &args[knit.args.0..knit.args.1], &config.splices)
+                    })?;
+            }
+        };
+    }
+
+    while !is_finished(&outputs, knit_cursor) {
         for (i, cmd) in ast.iter().enumerate() {
-            if cmd.are_args_ready(args, &outputs) {
-                cmd.load_args(ast, args, &mut binded_args, &mut outputs);
+            // Pure functions and finished stateful functions are never re-run
+            if let Dirty::Ready = outputs[i].0 {
+                continue;
+            } else if is_streaming && i == last_index {
+                continue;
+            } else if cmd.are_args_ready(args, &outputs) {
+                cmd.load_args(args, &mut binded_args, &outputs);
             } else {
                 //panic!("\n    {}\n", cmd.to_display(args, original));
                 continue;
             }
+            let before = outputs[i].0.clone();
+            let cmd_start = Instant::now();
 
             let bindings = &binded_args[cmd.args.0..cmd.args.1];
+            let (bindings, keywords) = bindings.split_at(cmd.keywords_start(args));
             match cmd.label.me {
                 Label::Assign => {
                     let lvalue = &args[cmd.args.0];
@@ -76,12 +155,12 @@ pub fn run<'a, K, V: Clone>(
 
                     if ctx.functions.get(name).is_some() {
                         return Err(format!("{} {}",
-                                lvalue.source.get_context(original),
+                                lvalue.source.get_context_in(original, &config.splices),
                                 "A function with this name already exists. Choose a different name for this variable."
                                 ));
                     }
 
-                    // @TODO: Should this be cloned?
+                    // Cheap since finished outputs with dependents are in {arena}
                     internal.insert(name, bindings[1].clone());
                     outputs[i] = (Dirty::Ready, bindings[1].clone());
                 }
@@ -100,26 +179,25 @@ pub fn run<'a, K, V: Clone>(
                                 Func::Pure(f, params) => (
                                     Dirty::Ready,
                                     params
-                                        .check_args(&ctx.parameters, bindings)
-                                        .and_then(|_| {
-                                            f.call(bindings, Api::new(original, i, &config))
+                                        .call_with_keywords(bindings, keywords, |bindings| {
+                                            f.call(bindings, api_for(i))
                                         })
                                         .map_err(|err| {
                                             err.to_display(
                                                 original,
                                                 &cmd.label.source,
                                                 &args[cmd.args.0..cmd.args.1],
+                                                &config.splices,
                                             )
                                         })?,
                                 ),
                                 Func::Stateful(f, params) => {
                                     let old_output = mem::replace(&mut outputs[i].1, Value::Null);
                                     params
-                                        .check_args(&ctx.parameters, bindings)
-                                        .and_then(|_| {
+                                        .call_with_keywords(bindings, keywords, |bindings| {
                                             f.call(
                                                 bindings,
-                                                Api::new(original, i, &config),
+                                                api_for(i),
                                                 old_output,
                                                 &mut external,
                                             )
@@ -129,6 +207,7 @@ pub fn run<'a, K, V: Clone>(
                                                 original,
                                                 &cmd.label.source,
                                                 &args[cmd.args.0..cmd.args.1],
+                                                &config.splices,
                                             )
                                         })?
                                 }
@@ -144,7 +223,7 @@ pub fn run<'a, K, V: Clone>(
                         _ => {
                             return Err(format!(
                                 "{} {}",
-                                cmd.label.get_context(original),
+                                cmd.label.source.get_context_in(original, &config.splices),
                                 "No function or variable named this.",
                             ))
                         }
@@ -153,19 +232,59 @@ pub fn run<'a, K, V: Clone>(
                 Label::Concat => {
                     // @TODO: have errors return which argument is bad
                     let output =
-                        concat(bindings, Api::new(original, i, &config)).map_err(|e| {
-                            e.to_display(original, &cmd.label.source, &args[cmd.args.0..cmd.args.1])
+                        concat(bindings, api_for(i)).map_err(|e| {
+                            e.to_display(original, &cmd.label.source, &args[cmd.args.0..cmd.args.1], &config.splices)
                         })?;
                     outputs[i] = (Dirty::Ready, output);
                 }
             }
+            // Once finished, its output never changes, so move it into
+            // {arena} for its dependents (reloaded every iteration if they
+            // are stateful) to copy pointers instead of the text
+            if matches!(outputs[i].0, Dirty::Ready) && cmd.reverse_dependant_count() > 0 {
+                arena.share(&mut outputs[i].1);
+            }
+            let duration = cmd_start.elapsed();
+
+            if tracer.is_enabled() {
+                tracer.record(TraceEvent {
+                    iteration: iter_count,
+                    index: i,
+                    label: match cmd.label.me {
+                        Label::Assign => "=".to_string(),
+                        Label::Concat => "#Concat".to_string(),
+                        Label::Ident | Label::Func => cmd.label.to_str(original).to_string(),
+                    },
+                    command: cmd.to_display(args, original),
+                    args: binded_args[cmd.args.0..cmd.args.1].iter().map(summarise).collect(),
+                    before,
+                    after: outputs[i].0.clone(),
+                    start: cmd_start.duration_since(run_start),
+                    duration,
+                    output_len: output_len(&outputs[i].1, config.output_filetype),
+                });
+            }
+
+            flush_knit!();
         }
+        // In case nothing ran this iteration
+        flush_knit!();
 
         iter_count += 1;
         if iter_count > ITERATION_LIMIT {
             break;
         }
     }
+    if is_streaming {
+        return if is_finished(&outputs, knit_cursor) {
+            Ok(String::new())
+        } else {
+            Err(format!(
+                "Stopped after {} iterations, some stateful functions never finished",
+                ITERATION_LIMIT
+            ))
+        };
+    }
     //println!("====");
     ////binded_args.iter().for_each(|p| println!("{:?}", p));
     //outputs.iter().for_each(|p| println!("{:?}", p));
@@ -173,7 +292,7 @@ pub fn run<'a, K, V: Clone>(
     //println!("It took {} iteration(s) to parse", iter_count);
     //println!("====start====");
     match outputs.pop() {
-        Some((_, Value::Text(s))) => Ok(s.to_string()),
+        Some((_, Value::Text(s))) => ctx.post_knit(s.into_owned(), api_for(last_index), ast),
         _ => unreachable!(),
     }
 }
@@ -201,12 +320,57 @@ impl Command {
                 Param::Literal(s) => Value::Text(Cow::Borrowed(s)),
                 Param::Ident => Value::Null, // First arg of assign is the only place
                 Param::Reference(_) => Value::Null,
-                Param::Key => todo!("Not sure how to deal with optional arguments yet."),
-                //Param::Reference
+                // The name of the keyword argument that follows
+                Param::Key => Value::Text(Cow::Borrowed(arg.to_str(original))),
             });
         }
     }
 
+    // For the knit when streaming. Writes all consecutive arguments from
+    // {cursor} that are ready and returns the index of the first that is not
+    #[allow(clippy::too_many_arguments)]
+    fn write_ready_prefix<'a, V: Render>(
+        &self,
+        mut cursor: usize,
+        ast: &[Command],
+        args: &[Token<Param>],
+        bindings: &[Value<'a, V>],
+        outputs: &mut [DirtyValue<'a, V>],
+        filetype: FileType,
+        writer: &mut dyn Write,
+    ) -> Result<usize, Error> {
+        let knit_args = &args[self.args.0..self.args.1];
+        while let Some(arg) = knit_args.get(cursor) {
+            let value = match arg.me {
+                Param::Reference(j) if matches!(outputs[j].0, Dirty::Waiting) => break,
+                Param::Reference(j) => &outputs[j].1,
+                _ => &bindings[self.args.0 + cursor],
+            };
+            write_value(value, filetype, writer).map_err(|err| match err {
+                Error::Arg(_, s) | Error::Generic(s) => Error::Arg(cursor, s),
+                err => err,
+            })?;
+
+            // If the knit is the only dependent, free it once written
+            if let Param::Reference(j) = arg.me {
+                if ast[j].reverse_dependant_count() == 1 {
+                    outputs[j].1 = Value::Null;
+                }
+            }
+            cursor += 1;
+        }
+        Ok(cursor)
+    }
+
+    // The parser puts all the keyword arguments, each a 'Param::Key' followed
+    // by its value, after the positional arguments
+    fn keywords_start(&self, args: &[Token<Param>]) -> usize {
+        args[self.args.0..self.args.1]
+            .iter()
+            .position(|arg| matches!(arg.me, Param::Key))
+            .unwrap_or(self.args.1 - self.args.0)
+    }
+
     fn are_args_ready<V>(&self, args: &[Token<Param>], outputs: &[DirtyValue<V>]) -> bool {
         let mut is_ready = true;
         for arg in &args[self.args.0..self.args.1] {
@@ -217,24 +381,21 @@ impl Command {
         is_ready
     }
 
+    // We cannot steal {outputs[j]} even if we are its only dependent since
+    // stateful functions reload their arguments every iteration. Instead
+    // finished outputs are moved into the arena (see 'run()') so that the
+    // clone is just a pointer copy, e.g. a multi-megabyte SVG passed to
+    // several functions.
     fn load_args<'a, V: Clone>(
         &self,
-        ast: &[Command],
         args: &[Token<Param>],
         bindings: &mut [Value<'a, V>],
-        outputs: &mut [DirtyValue<'a, V>],
+        outputs: &[DirtyValue<'a, V>],
     ) {
         let start = self.args.0;
         for (i, arg) in args[start..self.args.1].iter().enumerate() {
             if let Param::Reference(j) = arg.me {
-                // If {outputs[j]} has no dependents, we can just steal it
-                bindings[start + i] = if ast[j].reverse_dependant_count() == 0 {
-                    mem::replace(&mut outputs[j].1, Value::Null)
-
-                // Otherwise we have to clone
-                } else {
-                    outputs[j].1.clone()
-                }
+                bindings[start + i] = outputs[j].1.clone();
             }
         }
     }
@@ -1,8 +1,12 @@
 //run: cargo test -- --nocapture
 
 use std::borrow::Cow;
+use std::hash::Hash;
+use std::mem;
+use std::ops::Deref;
+use std::sync::Arc;
 
-use super::{Bindings, Error, Value, ValueRepr, Variables, VALUE_AS_STR};
+use super::{Bindings, Error, ParamDef, Signature, Types, Value, ValueRepr, Variables};
 use crate::api::Api;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -26,23 +30,8 @@ impl<'a, K, V> Bindings<'a, K, V> {
         limit_args: bool,
         parameters: &[ValueRepr],
     ) {
-        let len = parameters.len();
-        self.parameters.extend(parameters);
-
-        self.functions.insert(
-            name,
-            Func::Pure(
-                f,
-                ParamDef {
-                    parameters: (len, self.parameters.len()),
-                    arg_count: if limit_args == LIMITED {
-                        (len, len)
-                    } else {
-                        (0, usize::MAX)
-                    },
-                },
-            ),
-        );
+        let params = ParamDef::from_list(limit_args, parameters);
+        self.insert_function(Cow::Borrowed(name), Func::Pure(Handle::Borrowed(f), params));
     }
 
     pub fn register_stateful_function<F: StatefulFunction<K, V> + 'static>(
@@ -52,24 +41,159 @@ impl<'a, K, V> Bindings<'a, K, V> {
         limit_args: bool,
         parameters: &[ValueRepr],
     ) {
-        let len = parameters.len();
-        self.parameters.extend(parameters);
-
-        self.functions.insert(
-            name,
-            Func::Stateful(
-                f,
-                ParamDef {
-                    parameters: (len, self.parameters.len()),
-                    arg_count: if limit_args == LIMITED {
-                        (len, len)
-                    } else {
-                        (0, usize::MAX)
-                    },
-                },
-            ),
-        );
+        let params = ParamDef::from_list(limit_args, parameters);
+        self.insert_function(Cow::Borrowed(name), Func::Stateful(Handle::Borrowed(f), params));
     }
+
+    // Same as 'register_pure_function()' but for parameters that cannot be
+    // expressed by a plain list of types, see "run/parameters.rs"
+    pub fn register_pure_function_with<F: PureFunction<V> + 'static>(
+        &mut self,
+        name: &'a str,
+        f: &'a F,
+        params: ParamDef<V>,
+    ) {
+        self.insert_function(Cow::Borrowed(name), Func::Pure(Handle::Borrowed(f), params));
+    }
+
+    pub fn register_stateful_function_with<F: StatefulFunction<K, V> + 'static>(
+        &mut self,
+        name: &'a str,
+        f: &'a F,
+        params: ParamDef<V>,
+    ) {
+        self.insert_function(Cow::Borrowed(name), Func::Stateful(Handle::Borrowed(f), params));
+    }
+
+    // Same as 'register_pure_function()' but {self} takes ownership of both
+    // the name and the function, so neither have to outlive {self}. This is
+    // for building flavours at runtime, e.g. from a config file, or with
+    // closures that capture data. See 'boxed_pure()' for closures.
+    pub fn register_owned_pure_function<S: Into<Cow<'a, str>>>(
+        &mut self,
+        name: S,
+        f: Box<dyn PureFunction<V> + 'a>,
+        limit_args: bool,
+        parameters: &[ValueRepr],
+    ) {
+        let params = ParamDef::from_list(limit_args, parameters);
+        self.insert_function(name.into(), Func::Pure(Handle::Owned(f), params));
+    }
+
+    // Ditto 'register_owned_pure_function()'. See 'boxed_stateful()'
+    pub fn register_owned_stateful_function<S: Into<Cow<'a, str>>>(
+        &mut self,
+        name: S,
+        f: Box<dyn StatefulFunction<K, V> + 'a>,
+        limit_args: bool,
+        parameters: &[ValueRepr],
+    ) {
+        let params = ParamDef::from_list(limit_args, parameters);
+        self.insert_function(name.into(), Func::Stateful(Handle::Owned(f), params));
+    }
+
+    // For functions defined with 'typed_function!', the parameter types and
+    // the arity come from the declaration instead. These are empty structs so
+    // taking ownership does not allocate.
+    pub fn register_typed_pure_function<F: PureFunction<V> + Signature + 'a>(
+        &mut self,
+        name: &'a str,
+        f: F,
+    ) {
+        let func = Func::Pure(Handle::Owned(Box::new(f)), signature_to_param_def::<F, V>());
+        self.insert_function(Cow::Borrowed(name), func);
+        self.document_signature::<F>(name);
+    }
+
+    pub fn register_typed_stateful_function<F: StatefulFunction<K, V> + Signature + 'a>(
+        &mut self,
+        name: &'a str,
+        f: F,
+    ) {
+        let func = Func::Stateful(Handle::Owned(Box::new(f)), signature_to_param_def::<F, V>());
+        self.insert_function(Cow::Borrowed(name), func);
+        self.document_signature::<F>(name);
+    }
+
+    // Replacing a function also drops the documentation of the old one
+    fn insert_function(&mut self, name: Cow<'a, str>, func: Func<'a, K, V>) {
+        self.documentation.remove(&name);
+        self.functions.insert(name, func);
+    }
+
+    fn document_signature<F: Signature>(&mut self, name: &'a str) {
+        let doc = self.documentation.entry(Cow::Borrowed(name)).or_default();
+        doc.parameters = F::NAMES.iter().map(|s| Cow::Borrowed(*s)).collect();
+    }
+}
+
+impl<'a, K, V> Bindings<'a, K, V>
+where
+    K: Clone + Eq + Hash + Sync + Send + 'a,
+    V: Clone + 'a,
+{
+    // Registers {name} as the calls of {collect} (see "Collectors" below) and
+    // {aggregate}, if given, as a function without parameters that outputs
+    // 'Batch::aggregate'. The state is kept in {storage} under {key}, and the
+    // first collector registered with {key} batches for all of them.
+    pub fn register_collector<C: Collect<V> + 'a>(
+        &mut self,
+        name: &'a str,
+        aggregate: Option<&'a str>,
+        key: K,
+        collect: C,
+        params: ParamDef<V>,
+    ) {
+        let collect = Arc::new(collect);
+        let batcher = Arc::clone(self.batchers.entry(key.clone()).or_insert_with(|| {
+            let collect = Arc::clone(&collect);
+            Arc::new(move |items, api| collect.batch(items, api))
+        }));
+        if let Some(aggregate) = aggregate {
+            let batcher = Arc::clone(&batcher);
+            let f = CollectorAggregate { name: aggregate, key: key.clone(), batcher };
+            let func = Func::Stateful(Handle::Owned(Box::new(f)), ParamDef::new());
+            self.insert_function(Cow::Borrowed(aggregate), func);
+        }
+        let f = CollectorCall { name, key, collect, batcher };
+        self.insert_function(Cow::Borrowed(name), Func::Stateful(Handle::Owned(Box::new(f)), params));
+    }
+}
+
+fn signature_to_param_def<F: Signature, V>() -> ParamDef<V> {
+    // 'typed_function!' checks that the optional parameters are the last ones
+    let (required, optional) = F::PARAMETERS.split_at(F::REQUIRED);
+    let params = required.iter().fold(ParamDef::new(), |def, repr| def.required(*repr));
+    let params = optional.iter().fold(params, |def, repr| def.optional(*repr));
+    let params = match F::HAS_REST {
+        true => params.rest(Types::ANY),
+        false => params,
+    };
+    F::KEYWORDS.iter().fold(params, |def, (name, repr)| def.keyword(name, *repr))
+}
+
+// Closures only infer their higher-ranked signatures from 'Fn' bounds, so
+// these are needed to box closures for the 'register_owned_*()' functions
+pub fn boxed_pure<'a, V, F>(f: F) -> Box<dyn PureFunction<V> + 'a>
+where
+    F: for<'b> Fn(&[Value<'b, V>], Api<'b>) -> PureResult<'b, V> + Sync + Send + 'a,
+{
+    Box::new(f)
+}
+
+pub fn boxed_stateful<'a, K, V, F>(f: F) -> Box<dyn StatefulFunction<K, V> + 'a>
+where
+    F: for<'b> Fn(
+            &[Value<'b, V>],
+            Api<'b>,
+            Value<'b, V>,
+            &mut Variables<'b, K, V>,
+        ) -> StatefulResult<'b, V>
+        + Sync
+        + Send
+        + 'a,
+{
+    Box::new(f)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -77,63 +201,23 @@ impl<'a, K, V> Bindings<'a, K, V> {
 // {K} is a custom key enum, {V} is a custom value enum
 
 pub enum Func<'a, K, V> {
-    Pure(&'a dyn PureFunction<V>, ParamDef),
-    Stateful(&'a dyn StatefulFunction<K, V>, ParamDef),
-}
-
-pub struct ParamDef {
-    parameters: (usize, usize),
-    arg_count: (usize, usize),
-}
-
-// @TODO: check is excuted on every iteration, it might be possible to check
-//        only once if the arguments have not changed
-impl ParamDef {
-    pub fn check_args<V>(&self, all_params: &[ValueRepr], args: &[Value<V>]) -> Result<(), Error> {
-        let parameters = &all_params[self.parameters.0..self.parameters.1];
-        //println!("{:?} {:?} {:?}", all_params, self.arg_count, self.parameters);
-
-        if args.len() < self.arg_count.0 {
-            return Err(args
-                .len()
-                .checked_sub(1)
-                .map(|i| Error::Arg(i, Cow::Borrowed("Need an argument after this")))
-                // Give label context if no args
-                .unwrap_or(Error::Generic(Cow::Borrowed("Missing an argument"))));
-        } else if args.len() > self.arg_count.1 {
-            //match &args[1] {
-            //    Value::Null => println!("Missing: Null"),
-            //    Value::Str(s) => println!("Missing: {:?}", s),
-            //    Value::String(s) => println!("Missing: {:?}", s),
-            //    Value::String(s) => println!("Missing: {:?}", s),
-            //    Value::Usize(s) => println!("Missing: {:?}", s),
-            //    Value::Char(s) => println!("Missing: {:?}", s),
-            //    Value::String(s) => println!("Missing: {:?}", s),
-            //    Value::Bool(s) => println!("Missing: {:?}", s),
-            //    Value::List(_) => todo!(),
-            //    Value::Custom(_) => todo!(),
-            //}
-            return Err(args
-                .len()
-                .checked_sub(1)
-                .map(|i| Error::Arg(i, Cow::Borrowed("Unexpected argument")))
-                // Give to label context if no args
-                .unwrap_or(Error::Generic(Cow::Borrowed("Unexpected argument"))));
-        } else {
-            for (i, (a1, a2)) in parameters.iter().zip(args.iter()).enumerate() {
-                if *a1 != a2.tag() {
-                    return Err(Error::Arg(
-                        i,
-                        Cow::Owned(format!(
-                            "is a value of type {}. Expected a {}",
-                            VALUE_AS_STR[*a1 as usize],
-                            VALUE_AS_STR[a2.tag() as usize],
-                        )),
-                    ));
-                }
-            }
+    Pure(Handle<'a, dyn PureFunction<V> + 'a>, ParamDef<V>),
+    Stateful(Handle<'a, dyn StatefulFunction<K, V> + 'a>, ParamDef<V>),
+}
+
+// Like 'Cow' but for trait objects, which cannot implement 'ToOwned'
+pub enum Handle<'a, T: ?Sized> {
+    Borrowed(&'a T),
+    Owned(Box<T>),
+}
+
+impl<'a, T: ?Sized> Deref for Handle<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        match self {
+            Handle::Borrowed(f) => f,
+            Handle::Owned(f) => f,
         }
-        Ok(())
     }
 }
 
@@ -188,3 +272,233 @@ where
         self(args, api, old_output, storage)
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+// Collectors
+//
+// For stateful functions that need every call in the document before any
+// call can output, e.g. 'cite' runs pandoc once on all the citekeys. Instead
+// of hand-rolling a state machine over {old_output} and {storage}:
+//
+// 1. every call registers an item, see 'Collect::item()'
+// 2. once a whole pass of the executor goes by without any new items,
+//    'Collect::batch()' is run once on all of them in document order
+// 3. every call outputs the slot 'batch()' returned for its item, and the
+//    aggregate function (e.g. 'references') outputs 'Batch::aggregate'
+//
+// A call that only registers after the batch ran (i.e. its arguments took
+// more than a pass to be ready) is an error.
+//
+// Collectors registered with the same key share their items and the batch,
+// e.g. 'number' and 'ref' in the default flavour. Only the 'batch()' of the
+// first one registered is ever run, so it must handle the items of all of
+// them. Bindings merged by 'extend()' or 'mount()' keep their own batch.
+
+pub struct Batch<'a, V> {
+    pub slots: Vec<Value<'a, V>>, // One for each item, in the same order
+    pub aggregate: Value<'a, V>,
+}
+
+pub trait Collect<V: Clone>: Sync + Send {
+    // What a call contributes, by default its first argument
+    fn item<'a>(&self, args: &[Value<'a, V>], _api: Api<'a>) -> Result<Value<'a, V>, Error> {
+        Ok(args.first().cloned().unwrap_or(Value::Null))
+    }
+
+    // Called with the {api} of whichever call happens to trigger it
+    fn batch<'a>(&self, items: Vec<Value<'a, V>>, api: Api<'a>) -> Result<Batch<'a, V>, Error>;
+}
+
+// The 'Collect::batch()' of the first collector registered with a key
+pub(super) type Batcher<'a, V> =
+    Arc<dyn for<'b> Fn(Vec<Value<'b, V>>, Api<'b>) -> Result<Batch<'b, V>, Error> + Sync + Send + 'a>;
+
+struct CollectorCall<'n, K, V, C> {
+    name: &'n str, // For errors
+    key: K,
+    collect: Arc<C>,
+    batcher: Batcher<'n, V>,
+}
+
+struct CollectorAggregate<'n, K, V> {
+    name: &'n str,
+    key: K,
+    batcher: Batcher<'n, V>,
+}
+
+impl<K, V, C> StatefulFunction<K, V> for CollectorCall<'_, K, V, C>
+where
+    K: Clone + Eq + Hash + Sync + Send,
+    V: Clone,
+    C: Collect<V>,
+{
+    fn call<'a>(
+        &self,
+        args: &[Value<'a, V>],
+        api: Api<'a>,
+        old_output: Value<'a, V>,
+        storage: &mut Variables<'a, K, V>,
+    ) -> StatefulResult<'a, V> {
+        let mut state = CollectorState::load(storage, &self.key, api.id(), self.name)?;
+        let result = match old_output {
+            // First pass of this call
+            Value::Null if state.is_batched => Err(Error::Generic(Cow::Borrowed(
+                "Called after all the other calls were processed together. Do the arguments depend on a stateful function?",
+            ))),
+            Value::Null => self.collect.item(args, api).map(|item| {
+                state.items.push(item);
+                state.registered_on = Some(state.pass);
+                (Dirty::Waiting, Value::Usize(state.items.len() - 1))
+            }),
+
+            Value::Usize(slot) => state.try_batch(&self.batcher, api, self.name).map(|is_batched| {
+                if is_batched {
+                    (Dirty::Ready, mem::replace(&mut state.items[slot], Value::Null))
+                } else {
+                    (Dirty::Waiting, Value::Usize(slot))
+                }
+            }),
+            _ => unreachable!(),
+        };
+        state.store(storage, &self.key);
+        result
+    }
+}
+
+impl<K, V> StatefulFunction<K, V> for CollectorAggregate<'_, K, V>
+where
+    K: Clone + Eq + Hash + Sync + Send,
+    V: Clone,
+{
+    fn call<'a>(
+        &self,
+        _: &[Value<'a, V>],
+        api: Api<'a>,
+        _: Value<'a, V>,
+        storage: &mut Variables<'a, K, V>,
+    ) -> StatefulResult<'a, V> {
+        let mut state = CollectorState::load(storage, &self.key, api.id(), self.name)?;
+        let result = state.try_batch(&self.batcher, api, self.name).map(|is_batched| {
+            if is_batched {
+                (Dirty::Ready, state.aggregate.clone())
+            } else {
+                (Dirty::Waiting, Value::Null)
+            }
+        });
+        state.store(storage, &self.key);
+        result
+    }
+}
+
+// {storage} can only hold values, so this is packed into a 'Value::List'
+struct CollectorState<'a, V> {
+    pass: usize,
+    last_id: Option<usize>,
+    registered_on: Option<usize>, // The last pass that an item was registered
+    is_batched: bool,
+    items: Vec<Value<'a, V>>, // Replaced by the slots once batched
+    aggregate: Value<'a, V>,
+}
+
+impl<'a, V> CollectorState<'a, V> {
+    fn load<K: Clone + Eq + Hash>(
+        storage: &mut Variables<'a, K, V>,
+        key: &K,
+        id: usize,
+        name: &str,
+    ) -> Result<Self, Error> {
+        let state = match storage.insert(key.clone(), Value::Null) {
+            None => Some(Self {
+                pass: 0,
+                last_id: None,
+                registered_on: None,
+                is_batched: false,
+                items: Vec::new(),
+                aggregate: Value::Null,
+            }),
+            Some(Value::List(list)) => Self::unpack(list),
+            Some(_) => None,
+        };
+        // Another function stored something else under the same key
+        let mut state = state.ok_or_else(|| {
+            Error::Generic(Cow::Owned(format!(
+                "The state of the collector '{}' was overwritten. Is its key used by another function?",
+                name
+            )))
+        })?;
+
+        // Commands are run in increasing order, so this starts a new pass
+        if state.last_id.is_some_and(|last_id| id <= last_id) {
+            state.pass += 1;
+        }
+        state.last_id = Some(id);
+        Ok(state)
+    }
+
+    // The reverse of 'store()', checking every field
+    fn unpack(list: Vec<Value<'a, V>>) -> Option<Self> {
+        let to_option = |value: Value<'a, V>| match value {
+            Value::Null => Some(None),
+            Value::Usize(x) => Some(Some(x)),
+            _ => None,
+        };
+
+        let [pass, last_id, registered_on, is_batched, items, aggregate] = <[_; 6]>::try_from(list).ok()?;
+        Some(Self {
+            pass: match pass {
+                Value::Usize(x) => x,
+                _ => return None,
+            },
+            last_id: to_option(last_id)?,
+            registered_on: to_option(registered_on)?,
+            is_batched: match is_batched {
+                Value::Bool(b) => b,
+                _ => return None,
+            },
+            items: match items {
+                Value::List(l) => l,
+                _ => return None,
+            },
+            aggregate,
+        })
+    }
+
+    fn store<K: Clone + Eq + Hash>(self, storage: &mut Variables<'a, K, V>, key: &K) {
+        let from_option = |x: Option<usize>| x.map_or(Value::Null, Value::Usize);
+        let list = vec![
+            Value::Usize(self.pass),
+            from_option(self.last_id),
+            from_option(self.registered_on),
+            Value::Bool(self.is_batched),
+            Value::List(self.items),
+            self.aggregate,
+        ];
+        storage.insert(key.clone(), Value::List(list));
+    }
+
+    // Batch only once an entire pass has gone by without new items, i.e. all
+    // the calls have registered. Returns whether the batch has been run
+    fn try_batch(&mut self, batcher: &Batcher<'_, V>, api: Api<'a>, name: &str) -> Result<bool, Error> {
+        if self.is_batched {
+            return Ok(true);
+        } else if self.pass == 0 || self.registered_on.is_some_and(|pass| pass + 1 >= self.pass) {
+            return Ok(false);
+        }
+
+        let items = mem::take(&mut self.items);
+        let count = items.len();
+        let Batch { slots, aggregate } = batcher(items, api)?;
+        if slots.len() != count {
+            return Err(Error::Generic(Cow::Owned(format!(
+                "The collector '{}' returned {} results for {} calls",
+                name,
+                slots.len(),
+                count
+            ))));
+        }
+        self.items = slots;
+        self.aggregate = aggregate;
+        self.is_batched = true;
+        Ok(true)
+    }
+}
@@ -0,0 +1,101 @@
+//run: cargo test -- --nocapture
+
+// Transformations of the entire document, for what cannot be done by a
+// function in the markup itself
+//
+// * pre-lex hooks run on the source before it is parsed, e.g. normalising
+//   line endings or expanding includes. Those that splice in other files
+//   record where in 'Config::splices'
+// * post-knit hooks run on the output after all the commands are done, e.g.
+//   wrapping it in a layout, injecting a table of contents or minifying
+//
+// Hooks run in the order they are registered, each on the output of the last.
+// Since the whole output is needed, streaming with 'run_to_writer()' only
+// starts writing once all the post-knit hooks are done. Post-knit hooks that
+// are only needed by a function (e.g. 'toc' needs all the headings) can be
+// registered to run only for documents that call it, so that other documents
+// still stream.
+
+use std::borrow::Cow;
+
+use super::{Bindings, Error};
+use crate::api::{Api, Config};
+use crate::parser::{Command, Label};
+
+pub type PreLexHook<'a> = Box<dyn Fn(String, &mut Config) -> Result<String, Error> + Sync + Send + 'a>;
+pub type PostKnitHook<'a> =
+    Box<dyn for<'b> Fn(String, &Api<'b>) -> Result<String, Error> + Sync + Send + 'a>;
+// Named if only run for documents that call that function
+pub(super) type TriggeredHook<'a> = (Option<Cow<'a, str>>, PostKnitHook<'a>);
+
+impl<'a, K, V> Bindings<'a, K, V> {
+    pub fn register_pre_lex_hook<F>(&mut self, f: F)
+    where
+        F: Fn(String, &mut Config) -> Result<String, Error> + Sync + Send + 'a,
+    {
+        self.pre_lex_hooks.push(Box::new(f));
+    }
+
+    // {api} is that of the knit
+    pub fn register_post_knit_hook<F>(&mut self, f: F)
+    where
+        F: for<'b> Fn(String, &Api<'b>) -> Result<String, Error> + Sync + Send + 'a,
+    {
+        self.post_knit_hooks.push((None, Box::new(f)));
+    }
+
+    // Same as 'register_post_knit_hook()' but only runs if the document calls
+    // the function {name}
+    pub fn register_post_knit_hook_for<F>(&mut self, name: &'a str, f: F)
+    where
+        F: for<'b> Fn(String, &Api<'b>) -> Result<String, Error> + Sync + Send + 'a,
+    {
+        self.post_knit_hooks.push((Some(Cow::Borrowed(name)), Box::new(f)));
+    }
+
+    // 'compile()' does this for you. Call this before 'build()' otherwise
+    pub fn pre_lex<'s>(&self, original: &'s str, config: &mut Config) -> Result<Cow<'s, str>, String> {
+        let mut source = Cow::Borrowed(original);
+        for hook in &self.pre_lex_hooks {
+            source = Cow::Owned(hook(source.into_owned(), config).map_err(hook_error)?);
+        }
+        Ok(source)
+    }
+
+    pub(super) fn has_post_knit_hooks(&self, ast: &[Command], original: &str) -> bool {
+        self.post_knit_hooks
+            .iter()
+            .any(|(name, _)| is_called(name.as_deref(), ast, original))
+    }
+
+    pub(super) fn post_knit(
+        &self,
+        mut output: String,
+        api: Api,
+        ast: &[Command],
+    ) -> Result<String, String> {
+        for (name, hook) in &self.post_knit_hooks {
+            if is_called(name.as_deref(), ast, api.source()) {
+                output = hook(output, &api).map_err(hook_error)?;
+            }
+        }
+        Ok(output)
+    }
+}
+
+// Hooks not registered for a function are always called
+fn is_called(name: Option<&str>, ast: &[Command], original: &str) -> bool {
+    match name {
+        Some(name) => ast.iter().any(|cmd| {
+            matches!(cmd.label.me, Label::Ident | Label::Func) && cmd.label.to_str(original) == name
+        }),
+        None => true,
+    }
+}
+
+// There is no command in the source to give as context
+fn hook_error(err: Error) -> String {
+    match err {
+        Error::Arg(_, s) | Error::Generic(s) | Error::Contextless(s) => s.into_owned(),
+    }
+}
@@ -0,0 +1,396 @@
+//run: cargo test -- --nocapture
+
+// The parameter specification of a registered function, which the executor
+// checks the arguments against before every call. In order, a function has
+//
+// * required parameters
+// * optional parameters, with or without a default value
+// * at most one rest parameter that takes any number of arguments
+// * required parameters after the rest parameter
+//
+// e.g. "run" is `ParamDef::new().required(TEXT).rest(TEXT).required(TEXT)`,
+// i.e. a program, any number of arguments to it, and then the body for STDIN.
+//
+// Keyword parameters (e.g. `page: "12"`) are given by name after all of
+// these, in any order. The function receives them after its positional
+// arguments, one for each declared keyword in order, 'Value::Null' if not
+// given. Declare them in a list shared by the registration
+// ('ParamDef::keywords_from()') and the function ('Keywords::split()') so the
+// function can look them up by name.
+//
+// Each parameter accepts a set of types ('Types') so that a function can
+// take e.g. either a Text or a List.
+
+use std::borrow::Cow;
+use std::fmt::Write as _; // clippy: import without risk of name clashing
+
+use common::FileType;
+
+use super::{Error, Render, Value, ValueRepr, VALUE_AS_STR};
+
+// Bitset of 'ValueRepr'
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Types(u16);
+
+impl Types {
+    pub const ANY: Self = Self(u16::MAX);
+
+    pub const fn one(repr: ValueRepr) -> Self {
+        Self(1 << repr)
+    }
+    pub const fn or(self, repr: ValueRepr) -> Self {
+        Self(self.0 | 1 << repr)
+    }
+    pub fn contains(&self, repr: ValueRepr) -> bool {
+        self.0 & 1 << repr != 0
+    }
+
+    // e.g. "Text | List"
+    pub fn to_display(&self) -> Cow<'static, str> {
+        if *self == Self::ANY {
+            return Cow::Borrowed("Any");
+        }
+        let mut iter = (0..VALUE_AS_STR.len()).filter(|repr| self.contains(*repr as ValueRepr));
+        let mut buffer = String::new();
+        if let Some(first) = iter.next() {
+            buffer.push_str(VALUE_AS_STR[first]);
+        }
+        iter.for_each(|repr| write!(buffer, " | {}", VALUE_AS_STR[repr]).unwrap());
+        Cow::Owned(buffer)
+    }
+}
+
+impl From<ValueRepr> for Types {
+    fn from(repr: ValueRepr) -> Self {
+        Self::one(repr)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParameterKind {
+    Required,
+    Optional,
+    Rest,
+    Keyword,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Defaults are 'static since they outlive every document that is run
+pub struct ParamDef<V> {
+    leading: Vec<Types>,
+    required: usize, // The first {required} of {leading} are required
+    defaults: Vec<Option<Value<'static, V>>>, // One for each optional
+    rest: Option<Types>,
+    trailing: Vec<Types>,
+    keywords: Vec<(&'static str, Types)>,
+}
+
+impl<V> Default for ParamDef<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> ParamDef<V> {
+    pub fn new() -> Self {
+        Self {
+            leading: Vec::new(),
+            required: 0,
+            defaults: Vec::new(),
+            rest: None,
+            trailing: Vec::new(),
+            keywords: Vec::new(),
+        }
+    }
+
+    // What 'LIMITED' and 'UNLIMITED' with a list of types mean
+    pub fn from_list(limit_args: bool, parameters: &[ValueRepr]) -> Self {
+        let mut def = Self::new();
+        for repr in parameters {
+            def = if limit_args {
+                def.required(*repr)
+            } else {
+                def.optional(*repr)
+            };
+        }
+        if !limit_args {
+            def = def.rest(Types::ANY);
+        }
+        def
+    }
+
+    pub fn required<T: Into<Types>>(mut self, types: T) -> Self {
+        if self.rest.is_some() {
+            self.trailing.push(types.into());
+        } else {
+            assert!(self.defaults.is_empty(), "A required parameter cannot follow an optional one");
+            self.leading.push(types.into());
+            self.required += 1;
+        }
+        self
+    }
+
+    // Left out of the arguments if not provided
+    pub fn optional<T: Into<Types>>(self, types: T) -> Self {
+        self.push_optional(types.into(), None)
+    }
+
+    // {default} is passed in if not provided
+    pub fn default<T: Into<Types>>(self, types: T, default: Value<'static, V>) -> Self {
+        let types = types.into();
+        assert!(types.contains(default.tag()), "The default is not one of the types of the parameter");
+        self.push_optional(types, Some(default))
+    }
+
+    pub fn rest<T: Into<Types>>(mut self, types: T) -> Self {
+        assert!(self.rest.is_none(), "There can only be one rest parameter");
+        self.rest = Some(types.into());
+        self
+    }
+
+    pub fn keyword<T: Into<Types>>(mut self, name: &'static str, types: T) -> Self {
+        assert!(self.keywords.iter().all(|(k, _)| *k != name), "Keyword parameters must have distinct names");
+        self.keywords.push((name, types.into()));
+        self
+    }
+
+    // Same as 'keyword()' for each of {keywords}, in order
+    pub fn keywords_from(self, keywords: &[(&'static str, Types)]) -> Self {
+        keywords.iter().fold(self, |def, (name, types)| def.keyword(name, *types))
+    }
+
+    fn push_optional(mut self, types: Types, default: Option<Value<'static, V>>) -> Self {
+        assert!(self.rest.is_none(), "An optional parameter cannot follow the rest parameter");
+        if default.is_some() {
+            assert!(
+                self.defaults.iter().all(Option::is_some),
+                "A parameter with a default cannot follow an optional one without"
+            );
+        }
+        self.leading.push(types);
+        self.defaults.push(default);
+        self
+    }
+
+    // In order of declaration
+    pub fn iter(&self) -> impl Iterator<Item = (Types, ParameterKind, Option<&Value<'static, V>>)> {
+        let leading = self.leading.iter().enumerate().map(move |(i, types)| {
+            if i < self.required {
+                (*types, ParameterKind::Required, None)
+            } else {
+                (*types, ParameterKind::Optional, self.defaults[i - self.required].as_ref())
+            }
+        });
+        let rest = self.rest.iter().map(|types| (*types, ParameterKind::Rest, None));
+        let trailing = self.trailing.iter().map(|types| (*types, ParameterKind::Required, None));
+        leading.chain(rest).chain(trailing)
+    }
+
+    // In order of declaration, which is the order the function receives them
+    pub fn keywords(&self) -> impl Iterator<Item = (&'static str, Types)> + '_ {
+        self.keywords.iter().copied()
+    }
+
+    // Minimum number of arguments
+    fn min_args(&self) -> usize {
+        self.required + self.trailing.len()
+    }
+
+    // Arity and types. Error indices are into {args}
+    pub fn check_args<U: Render>(&self, args: &[Value<U>]) -> Result<(), Error> {
+        let len = args.len();
+        if len < self.min_args() {
+            let missing = self
+                .leading
+                .iter()
+                .take(self.required)
+                .chain(self.trailing.iter())
+                .nth(len)
+                .unwrap();
+            let msg = format!("Missing an argument of type {}", missing.to_display());
+            return Err(match len.checked_sub(1) {
+                Some(i) => Error::Arg(i, Cow::Owned(format!("{} after this", msg))),
+                // Give label context if no args
+                None => Error::Generic(Cow::Owned(msg)),
+            });
+        } else if self.rest.is_none() && len > self.leading.len() {
+            return Err(Error::Arg(self.leading.len(), Cow::Borrowed("Unexpected argument")));
+        }
+
+        let rest_close = len - self.trailing.len();
+        let leading_close = rest_close.min(self.leading.len());
+        let expected = self.leading[..leading_close]
+            .iter()
+            .chain(std::iter::repeat_n(self.rest.as_ref().unwrap_or(&Types::ANY), rest_close - leading_close))
+            .chain(self.trailing.iter());
+        for (i, (types, arg)) in expected.zip(args.iter()).enumerate() {
+            if !types.contains(arg.tag()) {
+                return Err(Error::Arg(
+                    i,
+                    Cow::Owned(format!(
+                        "is a value of type {}. Expected a {}",
+                        display_type(arg),
+                        types.to_display(),
+                    )),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Checks {args} then calls {f} with the defaults filled in. Errors that
+    // {f} returns are remapped to index into {args} instead
+    pub fn call_with<'x, T, F>(&self, args: &[Value<'x, V>], f: F) -> Result<T, Error>
+    where
+        V: Clone + Render,
+        F: FnOnce(&[Value<'x, V>]) -> Result<T, Error>,
+    {
+        self.call_with_keywords(args, &[], f)
+    }
+
+    // Same as 'call_with()' but also with the keyword arguments of the call,
+    // which alternate between the name (a Text) and the value. Errors index
+    // into {args} then {keywords}
+    pub fn call_with_keywords<'x, T, F>(
+        &self,
+        args: &[Value<'x, V>],
+        keywords: &[Value<'x, V>],
+        f: F,
+    ) -> Result<T, Error>
+    where
+        V: Clone + Render,
+        F: FnOnce(&[Value<'x, V>]) -> Result<T, Error>,
+    {
+        self.check_args(args)?;
+        let (keywords, keyword_indices) = self.match_keywords(args.len(), keywords)?;
+
+        let rest_close = args.len() - self.trailing.len();
+        let at = rest_close.min(self.leading.len());
+        let defaults = self.defaults[at - self.required..]
+            .iter()
+            .map_while(|default| default.clone())
+            .collect::<Vec<_>>();
+        if defaults.is_empty() && keywords.is_empty() {
+            return f(args);
+        }
+
+        let count = defaults.len();
+        let mut filled = Vec::with_capacity(args.len() + count + keywords.len());
+        filled.extend_from_slice(&args[..at]);
+        filled.extend(defaults);
+        filled.extend_from_slice(&args[at..]);
+        let positional = filled.len();
+        filled.extend(keywords);
+        f(&filled).map_err(|err| match err {
+            Error::Arg(i, msg) if i >= positional => match keyword_indices[i - positional] {
+                Some(j) => Error::Arg(j, msg),
+                None => Error::Generic(msg),
+            },
+            Error::Arg(i, msg) if i >= at + count => Error::Arg(i - count, msg),
+            Error::Arg(i, msg) if i >= at => Error::Generic(msg),
+            err => err,
+        })
+    }
+
+    // {keywords} start at {offset} in the arguments of the call. Returns the
+    // value of each declared keyword and its index in the arguments, if given
+    #[allow(clippy::type_complexity)]
+    fn match_keywords<'x>(
+        &self,
+        offset: usize,
+        keywords: &[Value<'x, V>],
+    ) -> Result<(Vec<Value<'x, V>>, Vec<Option<usize>>), Error>
+    where
+        V: Clone + Render,
+    {
+        let mut values = vec![Value::Null; self.keywords.len()];
+        let mut indices = vec![None; self.keywords.len()];
+        for (k, pair) in keywords.chunks(2).enumerate() {
+            let index = offset + 2 * k;
+            let name = unwrap!(unreachable &pair[0] => Value::Text(s) => s.as_ref());
+            let j = match self.keywords.iter().position(|(key, _)| *key == name) {
+                Some(j) => j,
+                None if self.keywords.is_empty() => {
+                    return Err(Error::Arg(index, Cow::Borrowed("This function takes no keyword arguments")))
+                }
+                None => {
+                    let names = self.keywords.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+                    return Err(Error::Arg(index, Cow::Owned(format!(
+                        "is not a keyword parameter. Expected one of: {}",
+                        names.join(", "),
+                    ))));
+                }
+            };
+            if indices[j].is_some() {
+                return Err(Error::Arg(index, Cow::Borrowed("This keyword argument is given more than once")));
+            }
+
+            let types = self.keywords[j].1;
+            if !types.contains(pair[1].tag()) {
+                return Err(Error::Arg(
+                    index + 1,
+                    Cow::Owned(format!(
+                        "is a value of type {}. Expected a {}",
+                        display_type(&pair[1]),
+                        types.to_display(),
+                    )),
+                ));
+            }
+            values[j] = pair[1].clone();
+            indices[j] = Some(index + 1);
+        }
+        Ok((values, indices))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// The keyword arguments of a call by name. {names} must be the keyword
+// parameters that the function is registered with, in the same order
+pub struct Keywords<'v, 'a, V> {
+    names: &'static [(&'static str, Types)],
+    values: &'v [Value<'a, V>],
+    offset: usize, // Index of the first keyword argument in the arguments
+}
+
+impl<'v, 'a, V> Keywords<'v, 'a, V> {
+    // The positional arguments in {args} and the keyword arguments after them
+    pub fn split(args: &'v [Value<'a, V>], names: &'static [(&'static str, Types)]) -> (&'v [Value<'a, V>], Self) {
+        let offset = args.len() - names.len();
+        let (positional, values) = args.split_at(offset);
+        (positional, Self { names, values, offset })
+    }
+
+    // 'Value::Null' if not given
+    pub fn get(&self, name: &str) -> &'v Value<'a, V> {
+        &self.values[self.position(name)]
+    }
+
+    pub fn text(&self, name: &str) -> Option<&'v str> {
+        match self.get(name) {
+            Value::Text(s) => Some(s.as_ref()),
+            _ => None,
+        }
+    }
+
+    // Where the argument is in the arguments of the call, e.g. for 'Error::Arg'
+    pub fn index(&self, name: &str) -> usize {
+        self.offset + self.position(name)
+    }
+
+    fn position(&self, name: &str) -> usize {
+        match self.names.iter().position(|(key, _)| *key == name) {
+            Some(i) => i,
+            None => unreachable!("{:?} is not a keyword parameter", name),
+        }
+    }
+}
+
+// Custom values are not visible in the source, so also show what they render to
+fn display_type<U: Render>(value: &Value<U>) -> Cow<'static, str> {
+    match value {
+        Value::Custom(c) => Cow::Owned(format!("Custom {:?}", c.render(FileType::Default))),
+        _ => Cow::Borrowed(VALUE_AS_STR[value.tag() as usize]),
+    }
+}
@@ -0,0 +1,32 @@
+//run: cargo test -- --nocapture
+
+// How a flavour's custom value {V} (i.e. 'Value::Custom') is turned into text.
+// This is used by the knit (see 'utility::concat()'), by the trace and by the
+// documentation of default values, e.g.
+//
+//     impl Render for CustomValue {
+//         fn render(&self, filetype: FileType) -> Cow<str> {
+//             match (self, filetype) {
+//                 (CustomValue::Ref(key), FileType::LaTeX) => format!("\\ref{{{}}}", key).into(),
+//                 (CustomValue::Ref(key), _) => format!("[{}](#{})", key, key).into(),
+//             }
+//         }
+//     }
+//
+// {filetype} is the output filetype. For the trace and the documentation, where
+// there is no document being output, it is 'FileType::Default'.
+
+use std::borrow::Cow;
+
+use common::FileType;
+
+pub trait Render {
+    fn render(&self, filetype: FileType) -> Cow<'_, str>;
+}
+
+// For flavours without custom values
+impl Render for () {
+    fn render(&self, _: FileType) -> Cow<'_, str> {
+        Cow::Borrowed("")
+    }
+}
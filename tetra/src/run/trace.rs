@@ -0,0 +1,195 @@
+//run: cargo test -- --nocapture
+
+// Structured record of what the executor does on each iteration. This
+// replaces uncommenting the `println!()`s in "executor.rs" when debugging
+// multi-pass stateful functions like 'cite' and 'references'.
+//
+// The executor builds a 'TraceEvent' for every command it runs and hands it
+// off to a 'Tracer'. 'Trace' is the default tracer that just accumulates all
+// the events so that they can be dumped afterwards as a readable log or as
+// Chrome trace-event JSON (open with "chrome://tracing" or Perfetto).
+
+use std::fmt::Write as _; // clippy: import without risk of name clashing
+use std::time::Duration;
+
+use common::{json_push_str, FileType};
+
+use super::{Dirty, Render, Value, VALUE_AS_STR};
+
+// How many chars of a 'Value::Text' to show in the argument summaries
+const PREVIEW_LEN: usize = 24;
+
+pub trait Tracer {
+    // The executor skips building 'TraceEvent' entirely if this is false
+    fn is_enabled(&self) -> bool {
+        true
+    }
+    fn record(&mut self, event: TraceEvent);
+}
+
+// Tracing disabled, this is what 'Bindings::run()' uses
+impl Tracer for () {
+    fn is_enabled(&self) -> bool {
+        false
+    }
+    fn record(&mut self, _: TraceEvent) {}
+}
+
+#[derive(Debug)]
+pub struct TraceEvent {
+    pub iteration: usize,
+    pub index: usize,      // Index into {AstOutput.0}
+    pub label: String,     // Function name, or '=' and '#Concat'
+    pub command: String,   // 'Command::to_display()'
+    pub args: Vec<String>, // Summary of each argument, see 'summarise()'
+    pub before: Dirty,
+    pub after: Dirty,
+    pub start: Duration, // Since the start of 'run()'
+    pub duration: Duration,
+    pub output_len: usize, // Byte length were the output knit as text
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Default)]
+pub struct Trace {
+    pub events: Vec<TraceEvent>,
+}
+
+impl Tracer for Trace {
+    fn record(&mut self, event: TraceEvent) {
+        self.events.push(event);
+    }
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self { events: Vec::new() }
+    }
+
+    pub fn to_log(&self) -> String {
+        let mut buffer = String::new();
+        let mut iteration = None;
+        let mut total = Duration::ZERO;
+        for e in &self.events {
+            if iteration != Some(e.iteration) {
+                iteration = Some(e.iteration);
+                writeln!(buffer, "== Iteration {}", e.iteration).unwrap();
+            }
+            total += e.duration;
+            writeln!(
+                buffer,
+                "  {:>4} {:<32} {:?} -> {:?}  {:>10.3?}  {} bytes",
+                e.index,
+                e.command,
+                e.before,
+                e.after,
+                e.duration,
+                e.output_len,
+            )
+            .unwrap();
+            for (i, arg) in e.args.iter().enumerate() {
+                writeln!(buffer, "         {}: {}", i, arg).unwrap();
+            }
+        }
+        writeln!(
+            buffer,
+            "== {} command(s) over {} iteration(s) in {:.3?}",
+            self.events.len(),
+            iteration.map(|i| i + 1).unwrap_or(0),
+            total,
+        )
+        .unwrap();
+        buffer
+    }
+
+    // https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+    // Each iteration is displayed as its own thread so they stack as lanes
+    pub fn to_chrome_json(&self) -> String {
+        let mut buffer = String::new();
+        buffer.push_str("{\"traceEvents\":[");
+
+        let mut iter = self.events.iter().peekable();
+        while let Some(e) = iter.next() {
+            buffer.push_str("{\"name\":");
+            json_push_str(&mut buffer, &e.label);
+            write!(
+                buffer,
+                ",\"cat\":\"command\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":{}",
+                e.start.as_micros(),
+                e.duration.as_micros(),
+                e.iteration,
+            )
+            .unwrap();
+
+            buffer.push_str(",\"args\":{\"command\":");
+            json_push_str(&mut buffer, &e.command);
+            write!(buffer, ",\"index\":{},\"before\":", e.index).unwrap();
+            json_push_str(&mut buffer, &format!("{:?}", e.before));
+            buffer.push_str(",\"after\":");
+            json_push_str(&mut buffer, &format!("{:?}", e.after));
+            write!(buffer, ",\"output_len\":{},\"args\":[", e.output_len).unwrap();
+            let mut arg_iter = e.args.iter().peekable();
+            while let Some(arg) = arg_iter.next() {
+                json_push_str(&mut buffer, arg);
+                if arg_iter.peek().is_some() {
+                    buffer.push(',');
+                }
+            }
+            buffer.push_str("]}}");
+
+            if iter.peek().is_some() {
+                buffer.push(',');
+            }
+        }
+        buffer.push_str("]}");
+        buffer
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Helpers for the executor
+
+// e.g. 'Text(11) "@capper2012"', long text is elided with '…'
+// Custom values are previewed as they render for 'FileType::Default'
+pub fn summarise<V: Render>(value: &Value<V>) -> String {
+    let mut buffer = String::from(VALUE_AS_STR[value.tag() as usize]);
+    match value {
+        Value::Null => {}
+        Value::Text(s) => push_preview(&mut buffer, s),
+        Value::Bytes(b) => write!(buffer, "({})", b.len()).unwrap(),
+        Value::Custom(c) => push_preview(&mut buffer, &c.render(FileType::Default)),
+        Value::Usize(x) => write!(buffer, " {}", x).unwrap(),
+        Value::Char(c) => write!(buffer, " {:?}", c).unwrap(),
+        Value::Bool(b) => write!(buffer, " {}", b).unwrap(),
+        Value::List(l) => write!(buffer, "({})", l.len()).unwrap(),
+    }
+    buffer
+}
+
+fn push_preview(buffer: &mut String, s: &str) {
+    let preview_close = s
+        .char_indices()
+        .nth(PREVIEW_LEN)
+        .map(|(i, _)| i)
+        .unwrap_or(s.len());
+    write!(buffer, "({}) {:?}", s.len(), &s[..preview_close]).unwrap();
+    if preview_close < s.len() {
+        buffer.push('…');
+    }
+}
+
+// Like 'utility::recursive_calc_length()' but does not error on nulls
+pub fn output_len<V: Render>(value: &Value<V>, filetype: FileType) -> usize {
+    match value {
+        Value::Null => 0,
+        Value::Custom(c) => c.render(filetype).len(),
+        Value::Text(s) => s.len(),
+        // Before being embedded, see 'utility::embed_bytes()'
+        Value::Bytes(b) => b.len(),
+        Value::Char(c) => c.len_utf8(),
+        Value::Usize(x) => x.to_string().len(),
+        Value::Bool(b) => b.then(|| "true").unwrap_or("false").len(),
+        Value::List(l) => l.iter().map(|v| output_len(v, filetype)).sum(),
+    }
+}
@@ -0,0 +1,329 @@
+//run: cargo test -- --nocapture
+
+// Typed arguments for user-defined functions. Instead of unpacking {args}
+// with `unwrap!(unreachable &args[0] => Value::Text(s) => s)`, which panics
+// if the 'ParamDef' check is bypassed, define the function with
+// 'typed_function!' and declare the parameters as regular Rust parameters:
+//
+//     typed_function! {
+//         pub fn include<'a, V>(api: Api<'a>; path: &str, lines: Option<usize>) -> PureResult<'a, V> {
+//             ...
+//         }
+//     }
+//
+// The parameters before the ';' are the usual parameters of 'PureFunction'
+// (or 'StatefulFunction'), minus the {args}. Those after are extracted from
+// {args} via 'FromValue'. Trailing 'Option<T>' parameters are optional, and
+// 'Option<T>' parameters anywhere else are a compile error. They are also
+// 'None' for null arguments, e.g. keyword arguments that were not given.
+//
+// Pure functions can also take keyword parameters, after a second ';'. They
+// are all 'Option<T>', 'None' if not given:
+//
+//     typed_function! {
+//         pub fn include<'a, V>(api: Api<'a>; path: &str; section: Option<&str>) -> PureResult<'a, V> {
+//             ...
+//         }
+//     }
+//
+// Pure functions can end with `..rest`, the remaining arguments as they are,
+// i.e. '&[Value]', e.g. for the arguments of a command:
+//
+//     typed_function! {
+//         pub fn run_if_equals<'a, V>(api: Api<'a>; lvalue: &str, rvalue: &str, ..command) -> PureResult<'a, V> {
+//             ...
+//         }
+//     }
+//
+// This defines an empty struct named {include} that implements 'PureFunction'
+// and 'Signature', so register it with
+// `register_typed_pure_function("include", include {})` and the parameter
+// types and arity are derived from the declaration. It is a braced struct
+// rather than a unit struct so that it does not clash with variables that
+// happen to have the same name.
+//
+// No proc-macros since we are avoiding a dependency on 'syn'.
+
+use std::borrow::Cow;
+
+use super::{value, Value, ValueRepr, VALUE_AS_STR};
+
+// The type code for 'ParamDef' of each type that can be a typed parameter
+pub trait ValueType {
+    const REPR: ValueRepr;
+    const IS_OPTIONAL: bool = false;
+}
+
+pub trait FromValue<'v, 'a, V>: ValueType + Sized {
+    fn from_value(value: &'v Value<'a, V>) -> Result<Self, Cow<'static, str>>;
+
+    // What to use when the argument was not provided, 'None' is an error
+    fn from_missing() -> Option<Self> {
+        None
+    }
+}
+
+// Implemented by 'typed_function!' so that registration can set the
+// parameters without the user having to list them again
+pub trait Signature {
+    const PARAMETERS: &'static [ValueRepr];
+    const NAMES: &'static [&'static str]; // For 'Documentation'
+    const REQUIRED: usize; // Number of non-optional parameters
+    const HAS_REST: bool = false; // Ends with `..rest`
+    const KEYWORDS: &'static [(&'static str, ValueRepr)] = &[]; // In order
+}
+
+pub fn type_error<V>(expected: ValueRepr, value: &Value<V>) -> Cow<'static, str> {
+    Cow::Owned(format!(
+        "is a value of type {}. Expected a {}",
+        VALUE_AS_STR[value.tag() as usize],
+        VALUE_AS_STR[expected as usize],
+    ))
+}
+
+// 'ValueType' has no {V}, so it is implemented separately from 'FromValue'
+impl ValueType for &str {
+    const REPR: ValueRepr = value::TEXT;
+}
+impl ValueType for Cow<'_, str> {
+    const REPR: ValueRepr = value::TEXT;
+}
+impl ValueType for &[u8] {
+    const REPR: ValueRepr = value::BYTES;
+}
+impl ValueType for usize {
+    const REPR: ValueRepr = value::USIZE;
+}
+impl ValueType for char {
+    const REPR: ValueRepr = value::CHAR;
+}
+impl ValueType for bool {
+    const REPR: ValueRepr = value::BOOL;
+}
+impl<V> ValueType for &[Value<'_, V>] {
+    const REPR: ValueRepr = value::LIST;
+}
+
+macro_rules! impl_from_value {
+    ($( $repr:ident for $ty:ty, $value:ident => $variant:pat => $output:expr; )*) => {
+        $(
+            impl<'v, 'a, V> FromValue<'v, 'a, V> for $ty {
+                fn from_value($value: &'v Value<'a, V>) -> Result<Self, Cow<'static, str>> {
+                    match $value {
+                        $variant => Ok($output),
+                        _ => Err(type_error(value::$repr, $value)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_value! {
+    TEXT  for &'v str,             v => Value::Text(s) => s;
+    TEXT  for Cow<'a, str>,        v => Value::Text(s) => s.clone();
+    BYTES for &'v [u8],            v => Value::Bytes(b) => b;
+    USIZE for usize,               v => Value::Usize(x) => *x;
+    CHAR  for char,                v => Value::Char(c) => *c;
+    BOOL  for bool,                v => Value::Bool(b) => *b;
+    LIST  for &'v [Value<'a, V>],  v => Value::List(l) => l;
+}
+
+impl<T: ValueType> ValueType for Option<T> {
+    const REPR: ValueRepr = T::REPR;
+    const IS_OPTIONAL: bool = true;
+}
+
+impl<'v, 'a, V, T: FromValue<'v, 'a, V>> FromValue<'v, 'a, V> for Option<T> {
+    fn from_value(value: &'v Value<'a, V>) -> Result<Self, Cow<'static, str>> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+    fn from_missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+// See the top of this file for usage
+#[macro_export]
+macro_rules! typed_function {
+    // Pure function generic over the custom value {V}, optionally with
+    // keyword parameters
+    ($(#[$meta:meta])* $vis:vis fn $name:ident<$lt:lifetime, $V:ident>(
+        $api:ident: $api_ty:ty;
+        $( $param:ident: $param_ty:ty ),* $(,)?
+        $(; $( $keyword:ident: $keyword_ty:ty ),* $(,)? )?
+    ) -> $ret:ty $body:block) => {
+        $(#[$meta])*
+        #[allow(non_camel_case_types)]
+        $vis struct $name {}
+
+        impl<$V> $crate::run::PureFunction<$V> for $name {
+            fn call<$lt>(
+                &self,
+                args: &[$crate::run::Value<$lt, $V>],
+                api: $crate::api::Api<$lt>,
+            ) -> $crate::run::PureResult<$lt, $V> {
+                fn inner<$lt, $V>(
+                    $api: $api_ty,
+                    $( $param: $param_ty, )*
+                    $($( $keyword: $keyword_ty, )*)?
+                ) -> $ret $body
+                // The keyword arguments always come last, one for each
+                let (args, _keywords) = args.split_at(args.len() - (0 $($( + $crate::typed_function!(@one $keyword) )*)?));
+                $crate::typed_function!(@extract args, $V, 0; $( $param: $param_ty ),*);
+                $( $crate::typed_function!(@extract _keywords, $V, args.len(); $( $keyword: $keyword_ty ),*); )?
+                inner(api, $( $param, )* $($( $keyword, )*)?)
+            }
+        }
+        $crate::typed_function!(@signature $name, $lt; $( $param: $param_ty ),* $(; keywords $( $keyword: $keyword_ty ),*)?);
+    };
+
+    // Pure function generic over the custom value {V}, with a rest parameter
+    ($(#[$meta:meta])* $vis:vis fn $name:ident<$lt:lifetime, $V:ident>(
+        $api:ident: $api_ty:ty;
+        $( $param:ident: $param_ty:ty, )* ..$rest:ident $(,)?
+    ) -> $ret:ty $body:block) => {
+        $(#[$meta])*
+        #[allow(non_camel_case_types)]
+        $vis struct $name {}
+
+        impl<$V> $crate::run::PureFunction<$V> for $name {
+            fn call<$lt>(
+                &self,
+                args: &[$crate::run::Value<$lt, $V>],
+                api: $crate::api::Api<$lt>,
+            ) -> $crate::run::PureResult<$lt, $V> {
+                fn inner<$lt, $V>(
+                    $api: $api_ty,
+                    $( $param: $param_ty, )*
+                    $rest: &[$crate::run::Value<$lt, $V>],
+                ) -> $ret $body
+                let (args, $rest) = args.split_at(args.len().min(0 $( + $crate::typed_function!(@one $param) )*));
+                $crate::typed_function!(@extract args, $V, 0; $( $param: $param_ty ),*);
+                inner(api, $( $param, )* $rest)
+            }
+        }
+        $crate::typed_function!(@signature $name, $lt; $( $param: $param_ty ),*; rest $rest);
+    };
+
+    // Pure function for a specific custom value
+    ($(#[$meta:meta])* $vis:vis fn $name:ident<$lt:lifetime>(
+        $api:ident: $api_ty:ty;
+        $( $param:ident: $param_ty:ty ),* $(,)?
+    ) -> PureResult<$ret_lt:lifetime, $V:ty> $body:block) => {
+        $(#[$meta])*
+        #[allow(non_camel_case_types)]
+        $vis struct $name {}
+
+        impl $crate::run::PureFunction<$V> for $name {
+            fn call<$lt>(
+                &self,
+                args: &[$crate::run::Value<$lt, $V>],
+                api: $crate::api::Api<$lt>,
+            ) -> $crate::run::PureResult<$lt, $V> {
+                fn inner<$lt>(
+                    $api: $api_ty, $( $param: $param_ty ),*
+                ) -> $crate::run::PureResult<$ret_lt, $V> $body
+                $crate::typed_function!(@extract args, $V, 0; $( $param: $param_ty ),*);
+                inner(api, $( $param ),*)
+            }
+        }
+        $crate::typed_function!(@signature $name, $lt; $( $param: $param_ty ),*);
+    };
+
+    // Stateful function
+    ($(#[$meta:meta])* $vis:vis fn $name:ident<$lt:lifetime>(
+        $api:ident: $api_ty:ty,
+        $old_output:ident: $old_output_ty:ty,
+        $storage:ident: &mut Variables<$storage_lt:lifetime, $K:ty, $V:ty>;
+        $( $param:ident: $param_ty:ty ),* $(,)?
+    ) -> $ret:ty $body:block) => {
+        $(#[$meta])*
+        #[allow(non_camel_case_types)]
+        $vis struct $name {}
+
+        impl $crate::run::StatefulFunction<$K, $V> for $name {
+            fn call<$lt>(
+                &self,
+                args: &[$crate::run::Value<$lt, $V>],
+                api: $crate::api::Api<$lt>,
+                old_output: $crate::run::Value<$lt, $V>,
+                storage: &mut $crate::run::Variables<$lt, $K, $V>,
+            ) -> $crate::run::StatefulResult<$lt, $V> {
+                fn inner<$lt>(
+                    $api: $api_ty,
+                    $old_output: $old_output_ty,
+                    $storage: &mut $crate::run::Variables<$storage_lt, $K, $V>,
+                    $( $param: $param_ty ),*
+                ) -> $ret $body
+                $crate::typed_function!(@extract args, $V, 0; $( $param: $param_ty ),*);
+                inner(api, old_output, storage, $( $param ),*)
+            }
+        }
+        $crate::typed_function!(@signature $name, $lt; $( $param: $param_ty ),*);
+    };
+
+    // {offset} is the index of {args} in the arguments, for errors
+    (@extract $args:ident, $V:ty, $offset:expr; $( $param:ident: $param_ty:ty ),*) => {
+        let _offset = $offset;
+        let _count = 0 $( + $crate::typed_function!(@one $param) )*;
+        if $args.len() > _count {
+            return Err($crate::run::Error::Arg(_offset + _count, "Unexpected argument".into()));
+        }
+        let mut _index = 0;
+        $(
+            let $param: $param_ty = match $args.get(_index) {
+                Some(value) => $crate::run::FromValue::from_value(value)
+                    .map_err(|msg| $crate::run::Error::Arg(_offset + _index, msg))?,
+                None => <$param_ty as $crate::run::FromValue<'_, '_, $V>>::from_missing()
+                    .ok_or_else(|| $crate::run::Error::Generic(
+                        concat!("Missing the argument '", stringify!($param), "'").into()
+                    ))?,
+            };
+            _index += 1;
+        )*
+    };
+
+    (@signature $name:ident, $lt:lifetime; $( $param:ident: $param_ty:ty ),*
+        $(; rest $rest:ident)?
+        $(; keywords $( $keyword:ident: $keyword_ty:ty ),*)?
+    ) => {
+        impl<$lt> $crate::run::Signature for $name {
+            const PARAMETERS: &'static [u8] = &[
+                $( <$param_ty as $crate::run::ValueType>::REPR, )*
+            ];
+            const NAMES: &'static [&'static str] = &[$( stringify!($param), )* $( stringify!($rest) )?];
+            $( const HAS_REST: bool = $crate::typed_function!(@one $rest) == 1; )?
+            // Keyword arguments that are not given are null
+            $( const KEYWORDS: &'static [(&'static str, u8)] = {
+                $( assert!(
+                    <$keyword_ty as $crate::run::ValueType>::IS_OPTIONAL,
+                    concat!("The keyword parameter '", stringify!($keyword), "' must be an 'Option'"),
+                ); )*
+                &[$( (stringify!($keyword), <$keyword_ty as $crate::run::ValueType>::REPR), )*]
+            }; )?
+            // Arguments are matched to parameters by position, so an optional
+            // parameter before a required one would shift the rest. Evaluated
+            // when registering, so this fails to compile rather than run
+            const REQUIRED: usize = {
+                let optional = [$( <$param_ty as $crate::run::ValueType>::IS_OPTIONAL ),*];
+                let mut required = 0;
+                let mut i = 0;
+                while i < optional.len() {
+                    assert!(
+                        !optional[i] || i + 1 == optional.len() || optional[i + 1],
+                        concat!("The 'Option' parameters of '", stringify!($name), "' must come after the others"),
+                    );
+                    required += !optional[i] as usize;
+                    i += 1;
+                }
+                required
+            };
+        }
+    };
+
+    (@one $_:tt) => { 1 };
+}
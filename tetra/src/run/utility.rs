@@ -5,12 +5,14 @@
 //run: cargo test -- --nocapture
 
 use std::borrow::{Borrow, Cow};
+use std::fs;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::process::Stdio;
 
-use super::{Error, PureResult, Value};
-use crate::api::Api;
+use super::{Error, PureResult, Render, Value};
+use crate::api::{Api, FileType};
 
 /******************************************************************************
  * In-built Commands
@@ -21,12 +23,14 @@ use crate::api::Api;
 
 // Just joins its arguments into a string
 // Also doubles as the default push to the final knit
-pub fn concat<'a, V>(args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V> {
+pub fn concat<'a, V: Render>(args: &[Value<'a, V>], api: Api<'a>) -> PureResult<'a, V> {
     let mut buffer = String::with_capacity(recursive_calc_length(args)?);
-    recursive_concat::<V>(args, &mut buffer);
+    recursive_concat::<V>(args, api.meta.output_filetype, &mut buffer)?;
     Ok(Value::Text(Cow::Owned(buffer)))
 }
 
+// Custom values and bytes are only rendered once, in 'recursive_concat()', so
+// they are not counted and {buffer} grows as needed instead
 fn recursive_calc_length<V>(args: &[Value<V>]) -> Result<usize, Error> {
     let mut sum = 0;
     for (i, a) in args.iter().enumerate() {
@@ -37,24 +41,52 @@ fn recursive_calc_length<V>(args: &[Value<V>]) -> Result<usize, Error> {
             Value::Usize(x) => x.to_string().len(),
             Value::Bool(b) => b.then(|| "true").unwrap_or("false").len(),
             Value::List(l) => recursive_calc_length(l)?,
-            Value::Custom(_) => todo!(),
+            Value::Bytes(_) | Value::Custom(_) => 0,
         };
     }
     Ok(sum)
 }
 
-fn recursive_concat<'a, V>(args: &[Value<'a, V>], buffer: &mut String) {
-    for arg in args {
+fn recursive_concat<'a, V: Render>(
+    args: &[Value<'a, V>],
+    filetype: FileType,
+    buffer: &mut String,
+) -> Result<(), Error> {
+    for (i, arg) in args.iter().enumerate() {
         match arg {
             Value::Null => unreachable!(),
             Value::Text(s) => buffer.push_str(s),
+            Value::Bytes(b) => buffer.push_str(&embed_bytes(b, filetype).map_err(|err| Error::Arg(i, err))?),
             Value::Char(c) => buffer.push(*c),
             Value::Usize(x) => buffer.push_str(&x.to_string()),
             Value::Bool(b) => buffer.push_str(b.then(|| "true").unwrap_or("false")),
-            Value::List(l) => recursive_concat(l, buffer),
-            Value::Custom(_) => todo!(),
+            Value::List(l) => recursive_concat(l, filetype, buffer)?,
+            Value::Custom(c) => buffer.push_str(&c.render(filetype)),
         };
     }
+    Ok(())
+}
+
+// Streaming counterpart to 'concat()', for writing the knit piece by piece
+pub fn write_value<V: Render>(
+    value: &Value<V>,
+    filetype: FileType,
+    writer: &mut dyn Write,
+) -> Result<(), Error> {
+    let result = match value {
+        Value::Null => return Err(Error::Generic("You left a null unprocessed".into())),
+        Value::Text(s) => writer.write_all(s.as_bytes()),
+        Value::Bytes(b) => match embed_bytes(b, filetype) {
+            Ok(embedded) => writer.write_all(embedded.as_bytes()),
+            Err(err) => return Err(Error::Generic(err)),
+        },
+        Value::Char(c) => write!(writer, "{}", c),
+        Value::Usize(x) => write!(writer, "{}", x),
+        Value::Bool(b) => write!(writer, "{}", b),
+        Value::List(l) => return l.iter().try_for_each(|v| write_value(v, filetype, writer)),
+        Value::Custom(c) => writer.write_all(c.render(filetype).as_bytes()),
+    };
+    result.map_err(|err| Error::Contextless(Cow::Owned(format!("Could not write output: {}", err))))
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -81,9 +113,12 @@ pub fn shell<'a, V>(args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V> {
                 _ => return Err(Error::Arg(i, "Invalid type. Expected text.".into())),
             })
             .collect::<Result<Vec<&str>, Error>>()?;
-        run_command(cmd, Some(cell_body), &args, None)
-            .map(Cow::Owned)
-            .map(Value::Text)
+        // Programs can output e.g. images, which are kept as bytes
+        let stdout = run_command_for_bytes(cmd, Some(cell_body), &args, None)?;
+        Ok(match String::from_utf8(stdout) {
+            Ok(s) => Value::Text(Cow::Owned(s)),
+            Err(err) => Value::Bytes(Cow::Owned(err.into_bytes())),
+        })
     }
 }
 
@@ -97,6 +132,16 @@ pub fn env<'a, V>(args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V> {
     fetch_env_var(name).map(Cow::Owned).map(Value::Text)
 }
 
+// There are no boolean literals, so 'true' and 'false' are functions, e.g.
+// for `cite "a", narrative: true`
+pub fn yes<'a, V>(_args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V> {
+    Ok(Value::Bool(true))
+}
+
+pub fn no<'a, V>(_args: &[Value<'a, V>], _api: Api<'a>) -> PureResult<'a, V> {
+    Ok(Value::Bool(false))
+}
+
 /******************************************************************************
  * Helpers
  ******************************************************************************/
@@ -107,6 +152,22 @@ pub fn run_command(
     args: &[&str],
     env: Option<Vec<(&str, &str)>>,
 ) -> Result<String, Error> {
+    let stdout = run_command_for_bytes(program, stdin, args, env)?;
+    String::from_utf8(stdout).map_err(|_| {
+        Error::Generic(Cow::Owned(format!(
+            "The output of {:?} is not UTF-8 text. Binary outputs, e.g. images, can be written to a file with 'figure'",
+            program
+        )))
+    })
+}
+
+// Same as 'run_command' but for outputs that are not text, e.g. images
+pub fn run_command_for_bytes(
+    program: &str,
+    stdin: Option<&str>,
+    args: &[&str],
+    env: Option<Vec<(&str, &str)>>,
+) -> Result<Vec<u8>, Error> {
     let mut process = process::Command::new(program);
     process.args(args);
     if let Some(e) = env {
@@ -135,7 +196,7 @@ pub fn run_command(
 
     if output.status.success() {
         println!("{:?}", String::from_utf8(output.stderr));
-        Ok(String::from_utf8(output.stdout).unwrap())
+        Ok(output.stdout)
     } else {
         Err(Error::Generic(Cow::Owned(format!("Non zero status: {}\n{}", "?", String::from_utf8(output.stderr).unwrap()))))
         //panic!("NonZeroStatus {}", )
@@ -146,6 +207,94 @@ pub fn run_command(
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// Bytes
+
+// Binary outputs can only be part of the text of the document as images in
+// HTML, which are embedded as data URIs
+pub fn embed_bytes(bytes: &[u8], filetype: FileType) -> Result<String, Cow<'static, str>> {
+    match (filetype, sniff_extension(bytes).and_then(mime_type)) {
+        (FileType::Html, Some(mime)) if mime.starts_with("image/") => {
+            Ok(format!("<img src=\"{}\">", data_uri(bytes, mime)))
+        }
+        _ => Err(Cow::Owned(format!(
+            "is {} bytes of binary output, which can only be shown as an image in HTML. Use 'figure' to write it to a file instead",
+            bytes.len()
+        ))),
+    }
+}
+
+// e.g. "data:image/png;base64,iVBORw0KGgo="
+pub fn data_uri(bytes: &[u8], mime: &str) -> String {
+    format!("data:{};base64,{}", mime, base64(bytes))
+}
+
+// The standard alphabet, with padding
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0, |n, (i, byte)| n | u32::from(*byte) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => output.push('='),
+            }
+        }
+    }
+    output
+}
+
+// Writes {bytes} to {directory}, named by a hash of them so that writing the
+// same bytes again reuses the file, e.g. "assets/1f0e3dad99908345.png"
+pub fn write_bytes(bytes: &[u8], directory: &Path, extension: &str) -> Result<PathBuf, Error> {
+    let path = directory.join(format!("{:016x}.{}", fnv1a(bytes), extension));
+    if !path.exists() {
+        fs::create_dir_all(directory)
+            .and_then(|_| fs::write(&path, bytes))
+            .map_err(|err| Error::Generic(Cow::Owned(format!("Could not write {:?}: {}", path, err))))?;
+    }
+    Ok(path)
+}
+
+// The extension of {bytes} going by how the format starts
+pub fn sniff_extension(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "png"),
+        (b"\xff\xd8\xff", "jpg"),
+        (b"GIF87a", "gif"),
+        (b"GIF89a", "gif"),
+        (b"%PDF-", "pdf"),
+    ];
+    if let Some((_, extension)) = SIGNATURES.iter().find(|(magic, _)| bytes.starts_with(magic)) {
+        return Some(extension);
+    }
+    // SVGs can start with an XML declaration, a doctype or comments
+    let start = &bytes[..bytes.len().min(1024)];
+    start.windows(4).any(|window| window == b"<svg").then_some("svg")
+}
+
+pub fn mime_type(extension: &str) -> Option<&'static str> {
+    match extension {
+        "svg" => Some("image/svg+xml"),
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "pdf" => Some("application/pdf"),
+        _ => None,
+    }
+}
+
+// 64-bit FNV-1a, which is stable across versions of Rust unlike 'DefaultHasher'
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub fn fetch_env_var(key: &str) -> Result<String, Error> {
     Ok(std::env::vars()
         .find(|(k, _)| k == key)
@@ -0,0 +1,179 @@
+//run: cargo test -- --nocapture
+
+// Citations without pandoc
+
+#[cfg(test)]
+mod tests {
+    use tetra::api::FileType;
+    use tetra::bibliography::{Bibliography, Citation, Name, Style};
+
+    const BIBTEX: &str = r#"
+        This is a comment, as is anything@outside.org of entries
+        @string{ jcs = "Journal of " # {Citation Studies} }
+        @article{capper2012,
+          author = {Capper, Daniel and Ann van der Berg and {Barnes and Noble}},
+          title = {Reading {\LaTeX} \& Citations},
+          journal = jcs,
+          year = 2012, volume = 3, number = {1}, pages = {1--10},
+          doi = {10.1000/xyz},
+        }
+        @book(smith2001a, author = "M{\"u}ller, J{\'e}r{\^o}me", title = {Books},
+              publisher = {Press}, year = {2001})
+        @book{smith2001b, author = {J{\'e}r{\^o}me M{\"u}ller}, title = {More Books}, year = 2001}
+        @comment{ @book{ignored, title = {Ignored}} }
+    "#;
+
+    const CSL_JSON: &str = r#"[
+        { "id": "who2020", "type": "report", "title": "Report \"\u00e9\"",
+          "author": [{ "literal": "World Health Organization" }],
+          "issued": { "date-parts": [[2020, 5]] }, "URL": "https://who.int" },
+        { "id": 42, "title": "Untitled", "issued": { "raw": "1999-01-01" },
+          "author": [{ "family": "Beethoven", "given": "Ludwig", "non-dropping-particle": "van" }] }
+    ]"#;
+
+    fn bibliography() -> Bibliography {
+        let mut bibliography = Bibliography::new();
+        bibliography.add_bibtex(BIBTEX).unwrap();
+        bibliography.add_csl_json(CSL_JSON).unwrap();
+        bibliography
+    }
+
+    fn cite(citations: &[&str], style: Style, filetype: FileType) -> (Vec<String>, String) {
+        let citations = citations.iter().map(|s| Citation::parse(s).unwrap()).collect::<Vec<_>>();
+        bibliography().format(&citations, style, filetype).unwrap()
+    }
+
+    #[test]
+    fn parsing() {
+        let bibliography = bibliography();
+        assert_eq!(bibliography.len(), 5);
+        assert!(bibliography.get("ignored").is_none());
+
+        let article = bibliography.get("capper2012").unwrap();
+        assert_eq!(article.kind, "article");
+        assert_eq!(article.authors, vec![
+            Name { family: "Capper".into(), given: Some("Daniel".into()) },
+            Name { family: "van der Berg".into(), given: Some("Ann".into()) },
+            Name { family: "Barnes and Noble".into(), given: None },
+        ]);
+        assert_eq!(article.title.as_deref(), Some("Reading LaTeX & Citations"));
+        assert_eq!(article.container.as_deref(), Some("Journal of Citation Studies"));
+        assert_eq!(article.pages.as_deref(), Some("1–10"));
+        assert_eq!(article.issue.as_deref(), Some("1"));
+
+        let book = bibliography.get("smith2001a").unwrap();
+        assert_eq!(book.authors[0], Name { family: "Müller".into(), given: Some("Jérôme".into()) });
+
+        let report = bibliography.get("who2020").unwrap();
+        assert_eq!(report.title.as_deref(), Some("Report \"é\""));
+        assert_eq!(report.year.as_deref(), Some("2020"));
+        let untitled = bibliography.get("42").unwrap();
+        assert_eq!(untitled.authors[0].family, "van Beethoven");
+        assert_eq!(untitled.year.as_deref(), Some("1999"));
+
+        let mut broken = Bibliography::new();
+        assert_eq!(broken.add_bibtex("@book{a,\n title = {A}\n year = 1}"), Err("line 3: Expected ','".into()));
+        assert!(broken.add_csl_json("[{\"id\": \"a\"},]").is_err());
+        assert!(broken.add_bibtex("@book{a, publisher = undefined}").unwrap_err().contains("undefined"));
+    }
+
+    #[test]
+    fn citation_syntax() {
+        let parse = |s| Citation::parse(s).map(|c| (c.keys, c.is_narrative));
+        assert_eq!(parse("[@a; @b]"), Ok((vec!["a".into(), "b".into()], false)));
+        assert_eq!(parse("@a"), Ok((vec!["a".into()], true)));
+        assert_eq!(parse(" a, b "), Ok((vec!["a".into(), "b".into()], false)));
+        assert!(parse("[]").is_err());
+        assert!(parse("a b").is_err());
+    }
+
+    #[test]
+    fn pandoc_syntax() {
+        let citation = |keys: &[&str]| Citation {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            ..Citation::default()
+        };
+        assert_eq!(citation(&["a", "b"]).to_pandoc(), "[@a; @b]");
+        let located = Citation { prefix: Some("see".into()), page: Some("3, 5".into()), ..citation(&["a"]) };
+        assert_eq!(located.to_pandoc(), "[see @a, pp. 3, 5]");
+        assert_eq!(Citation { suppress_author: true, ..citation(&["a", "b"]) }.to_pandoc(), "[-@a; -@b]");
+        let narrative = Citation { is_narrative: true, page: Some("12".into()), ..citation(&["a"]) };
+        assert_eq!(narrative.to_pandoc(), "@a [p. 12]");
+        assert_eq!(Citation::parse("@a").unwrap().to_pandoc(), "@a");
+    }
+
+    #[test]
+    fn author_date() {
+        let (inline, references) = cite(
+            &["[@smith2001b; @capper2012]", "@smith2001a", "who2020"],
+            Style::AuthorDate,
+            FileType::Default,
+        );
+        assert_eq!(inline, vec![
+            "(Müller 2001b; Capper et al. 2012)",
+            "Müller (2001a)",
+            "(World Health Organization 2020)",
+        ]);
+        assert_eq!(references, concat!(
+            "Capper, Daniel, Ann van der Berg, and Barnes and Noble. 2012. ",
+            "“Reading LaTeX & Citations.” Journal of Citation Studies 3 (1): 1–10. https://doi.org/10.1000/xyz.\n\n",
+            "Müller, Jérôme. 2001a. Books. Press.\n\n",
+            "Müller, Jérôme. 2001b. More Books.\n\n",
+            "World Health Organization. 2020. Report \"é\". https://who.int.",
+        ));
+    }
+
+    #[test]
+    fn numeric() {
+        let (inline, references) = cite(&["[@42; @who2020]", "@42"], Style::Numeric, FileType::Default);
+        assert_eq!(inline, vec!["[1, 2]", "van Beethoven [1]"]);
+        assert_eq!(references, concat!(
+            "[1] van Beethoven, Ludwig. Untitled. 1999.\n\n",
+            "[2] World Health Organization. Report \"é\". 2020. https://who.int.",
+        ));
+    }
+
+    #[test]
+    fn locators() {
+        let citation = |text: &str, prefix: Option<&str>, page: Option<&str>| Citation {
+            prefix: prefix.map(String::from),
+            page: page.map(String::from),
+            ..Citation::parse(text).unwrap()
+        };
+        let citations = [
+            citation("[@42; @who2020]", Some("see"), Some("12")),
+            citation("@42", Some("as in"), Some("1–3")),
+            Citation { suppress_author: true, ..citation("42", None, None) },
+        ];
+        let format = |style| bibliography().format(&citations, style, FileType::Default).unwrap().0;
+        assert_eq!(format(Style::AuthorDate), vec![
+            "(see van Beethoven 1999; World Health Organization 2020, p. 12)",
+            "as in van Beethoven (1999, pp. 1–3)",
+            "(1999)",
+        ]);
+        assert_eq!(format(Style::Numeric), vec!["[see 1, 2, p. 12]", "as in van Beethoven [1, pp. 1–3]", "[1]"]);
+    }
+
+    #[test]
+    fn filetypes() {
+        let (inline, references) = cite(&["capper2012"], Style::Numeric, FileType::Html);
+        assert_eq!(inline, vec!["[<a href=\"#ref-capper2012\">1</a>]"]);
+        assert!(references.starts_with("<div class=\"references\"><p id=\"ref-capper2012\">[1] Capper"));
+        assert!(references.contains("“Reading LaTeX &amp; Citations.” <i>Journal of Citation Studies</i>"));
+
+        let (inline, references) = cite(&["smith2001a"], Style::AuthorDate, FileType::CommonMark);
+        assert_eq!(inline, vec!["([Müller 2001](#ref-smith2001a))"]);
+        assert_eq!(references, "<span id=\"ref-smith2001a\"></span>Müller, Jérôme. 2001. *Books*. Press.");
+
+        let (_, references) = cite(&["capper2012"], Style::AuthorDate, FileType::LaTeX);
+        assert!(references.starts_with("\\hypertarget{ref-capper2012}{}Capper"));
+        assert!(references.contains("Reading LaTeX \\& Citations"));
+        assert!(references.ends_with("\\url{https://doi.org/10.1000/xyz}."));
+
+        let (inline, _) = cite(&["@who2020"], Style::AuthorDate, FileType::AsciiDoctor);
+        assert_eq!(inline, vec!["<<ref-who2020,World Health Organization (2020)>>"]);
+
+        let missing = bibliography().format(&[Citation::parse("nowhere").unwrap()], Style::Numeric, FileType::Html);
+        assert!(missing.unwrap_err().contains("nowhere"));
+    }
+}
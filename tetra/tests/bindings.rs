@@ -0,0 +1,619 @@
+//run: cargo test -- --nocapture
+
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    use tetra::api::{Api, FileType, Config, OutlineEntry};
+    use tetra::run::{boxed_pure, boxed_stateful, value as v, Batch, Collect};
+    use tetra::run::{Bindings, Dirty, Error, Keywords, ParamDef, PureFunction, PureResult, Render, Signature, Types, Value, LIMITED, UNLIMITED};
+    use tetra::typed_function;
+
+    #[test]
+    fn owned_registration() {
+        // As if read from a config file at runtime
+        let name = String::from("lookup");
+        let table: HashMap<String, String> = [("a", "alpha"), ("b", "beta")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_owned_pure_function(
+            name,
+            boxed_pure(move |args, _api| match &args[0] {
+                Value::Text(key) => table
+                    .get(key.as_ref())
+                    .map(|s| Value::Text(Cow::Owned(s.clone())))
+                    .ok_or_else(|| Error::Arg(0, "Not in the table".into())),
+                _ => unreachable!(),
+            }),
+            LIMITED,
+            &[v::TEXT],
+        );
+        let suffix = String::from("!");
+        ctx.register_owned_stateful_function(
+            "shout",
+            boxed_stateful(move |args, _api, _old, _storage| match &args[0] {
+                Value::Text(s) => Ok((Dirty::Ready, Value::Text(Cow::Owned(format!("{}{}", s, suffix))))),
+                _ => unreachable!(),
+            }),
+            LIMITED,
+            &[v::TEXT],
+        );
+
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        assert_eq!(
+            ctx.compile("{$ lookup \"a\" $} {$ shout(lookup(\"b\")) $}", config.clone()),
+            Ok("alpha beta!".to_string()),
+        );
+        assert!(ctx.compile("{$ lookup \"c\" $}", config).is_err());
+    }
+
+    typed_function! {
+        fn greet<'a, V>(_api: Api<'a>; name: &str, greeting: Option<&str>) -> PureResult<'a, V> {
+            let greeting = greeting.unwrap_or("Hello");
+            Ok(Value::Text(Cow::Owned(format!("{}, {}", greeting, name))))
+        }
+    }
+
+    #[test]
+    fn typed_registration() {
+        assert_eq!(<greet as Signature>::PARAMETERS, &[v::TEXT, v::TEXT]);
+        assert_eq!(<greet as Signature>::REQUIRED, 1);
+
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_typed_pure_function("greet", greet {});
+
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        assert_eq!(
+            ctx.compile("{$ greet \"Ann\" $}. {$ greet \"Bob\", \"Bye\" $}", config.clone()),
+            Ok("Hello, Ann. Bye, Bob".to_string()),
+        );
+        assert!(ctx.compile("{$ greet $}", config.clone()).is_err());
+        assert!(ctx.compile("{$ greet \"a\", \"b\", \"c\" $}", config.clone()).is_err());
+
+        // Called directly, bypassing the 'ParamDef' checks of the executor
+        let api = || Api::new("", 0, &config);
+        match (greet {}).call(&[Value::<()>::Usize(1)], api()) {
+            Err(Error::Arg(0, msg)) => assert_eq!(msg, "is a value of type Usize. Expected a Text"),
+            x => panic!("{:?}", x),
+        }
+        match (greet {}).call(&[] as &[Value<()>], api()) {
+            Err(Error::Generic(msg)) => assert_eq!(msg, "Missing the argument 'name'"),
+            x => panic!("{:?}", x),
+        }
+        match (greet {}).call(&[Value::<()>::Null, Value::Null, Value::Null], api()) {
+            Err(Error::Arg(2, _)) => {}
+            x => panic!("{:?}", x),
+        }
+    }
+
+    typed_function! {
+        fn sign<'a, V>(_api: Api<'a>; text: &str; by: Option<&str>, on: Option<&str>) -> PureResult<'a, V> {
+            let mut buffer = text.to_string();
+            by.into_iter().for_each(|by| buffer.push_str(&format!(" - {}", by)));
+            on.into_iter().for_each(|on| buffer.push_str(&format!(", {}", on)));
+            Ok(Value::Text(Cow::Owned(buffer)))
+        }
+    }
+
+    #[test]
+    fn typed_keywords() {
+        assert_eq!(<sign as Signature>::PARAMETERS, &[v::TEXT]);
+        assert_eq!(<sign as Signature>::KEYWORDS, &[("by", v::TEXT), ("on", v::TEXT)]);
+
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_typed_pure_function("sign", sign {});
+        assert_eq!(ctx.function_info("sign").unwrap().signature(), "sign(text: Text; by: Text, on: Text)");
+
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        let compile = |src: &str| ctx.compile(src, config.clone());
+        assert_eq!(compile("{$ sign \"a\" $}"), Ok("a".to_string()));
+        assert_eq!(compile("{$ sign \"a\", on: \"Monday\", by: \"b\" $}"), Ok("a - b, Monday".to_string()));
+        assert!(compile("{$ sign \"a\", at: \"b\" $}").unwrap_err().ends_with("^^ is not a keyword parameter. Expected one of: by, on"));
+        assert_eq!(
+            compile("{$ sign \"a\", \"b\" $}"),
+            Err("   |\n 1 | {$ sign \"a\", \"b\" $}\n   |               ^ Unexpected argument".to_string()),
+        );
+    }
+
+    #[test]
+    fn documentation() {
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_typed_pure_function("greet", greet {});
+        ctx.register_owned_pure_function("shout", boxed_pure(|_, _| Ok(Value::Null)), LIMITED, &[v::TEXT]);
+        ctx.document("greet", "Says hello to {name}", &[], &["{$ greet \"Ann\" $}"]);
+
+        let names = ctx.functions().map(|f| f.name).collect::<Vec<_>>();
+        assert_eq!(names, ["greet", "shout"]);
+
+        let greet = ctx.function_info("greet").unwrap();
+        assert_eq!(greet.signature(), "greet(name: Text, [greeting: Text])");
+        assert_eq!(
+            greet.to_json(),
+            concat!(
+                r#"{"name":"greet","signature":"greet(name: Text, [greeting: Text])","stateful":false,"variadic":false,"#,
+                r#""parameters":[{"name":"name","type":"Text","kind":"required","default":null},"#,
+                r#"{"name":"greeting","type":"Text","kind":"optional","default":null}],"#,
+                r#""summary":"Says hello to {name}","examples":["{$ greet \"Ann\" $}"]}"#,
+            ),
+        );
+
+        let shout = ctx.function_info("shout").unwrap();
+        assert_eq!(shout.signature(), "shout(Text)");
+        assert!(shout.documentation.is_none());
+        assert!(ctx.function_info("whisper").is_none());
+    }
+
+    #[test]
+    fn parameter_specs() {
+        // Joins all the arguments with {left} and {right} around each
+        fn wrap<'a>(args: &[Value<'a, ()>], _: Api<'a>) -> PureResult<'a, ()> {
+            let text = |v: &Value<'a, ()>| match v {
+                Value::Text(s) => s.to_string(),
+                Value::List(l) => l.len().to_string(),
+                _ => unreachable!(),
+            };
+            let (left, right) = (text(&args[0]), text(&args[args.len() - 1]));
+            let middle = args[1..args.len() - 1].iter().map(text).collect::<Vec<_>>();
+            Ok(Value::Text(Cow::Owned(format!("{}{}{}", left, middle.join(right.as_str()), right))))
+        }
+        fn surround<'a>(args: &[Value<'a, ()>], _: Api<'a>) -> PureResult<'a, ()> {
+            match args {
+                [Value::Text(s), Value::Text(l), Value::Text(r)] => {
+                    Ok(Value::Text(Cow::Owned(format!("{}{}{}", l, s, r))))
+                }
+                [_, Value::Text(_), _] => Err(Error::Arg(2, "Bad right".into())),
+                _ => Err(Error::Arg(1, "Bad left".into())),
+            }
+        }
+
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_pure_function_with(
+            "wrap",
+            &wrap,
+            ParamDef::new().required(v::TEXT).rest(Types::one(v::TEXT).or(v::LIST)).required(v::TEXT),
+        );
+        ctx.register_pure_function_with(
+            "surround",
+            &surround,
+            ParamDef::new()
+                .required(v::TEXT)
+                .default(v::TEXT, Value::Text(Cow::Borrowed("(")))
+                .default(v::TEXT, Value::Text(Cow::Borrowed(")"))),
+        );
+        assert_eq!(ctx.function_info("wrap").unwrap().signature(), "wrap(Text, Text | List..., Text)");
+        assert_eq!(
+            ctx.function_info("surround").unwrap().signature(),
+            r#"surround(Text, [Text = "("], [Text = ")"])"#,
+        );
+
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        let compile = |src: &str| ctx.compile(src, config.clone());
+        assert_eq!(compile("{$ wrap \"<\", \">\" $}"), Ok("<>".to_string()));
+        assert_eq!(compile("{$ wrap \"<\", \"a\", \"b\", \">\" $}"), Ok("<a>b>".to_string()));
+        assert_eq!(compile("{$ surround \"a\" $}"), Ok("(a)".to_string()));
+        assert_eq!(compile("{$ surround \"a\", \"[\" $}"), Ok("[a)".to_string()));
+        assert_eq!(compile("{$ surround \"a\", \"[\", \"]\" $}"), Ok("[a]".to_string()));
+
+        // The error messages point at the offending argument
+        let error = |src: &str| compile(src).unwrap_err();
+        assert!(error("{$ wrap \"<\" $}").ends_with("| {$ wrap \"<\" $}\n   |          ^ Missing an argument of type Text after this"));
+        assert!(error("{$ surround $}").ends_with("Missing an argument of type Text"));
+        assert!(error("{$ surround \"a\", \"b\", \"c\", \"d\" $}").ends_with("^ Unexpected argument"));
+
+        // An error for an argument that was filled in by a default has no
+        // source to point at, so it becomes an error for the whole function
+        let spec = ParamDef::<()>::new()
+            .required(v::TEXT)
+            .default(v::TEXT, Value::Text(Cow::Borrowed("(")))
+            .default(Types::one(v::TEXT).or(v::USIZE), Value::Usize(0));
+        let api = || Api::new("", 0, &config);
+        let args = [Value::Text(Cow::Borrowed("a")), Value::Text(Cow::Borrowed("["))];
+        match spec.call_with(&args, |args| surround(args, api())) {
+            Err(Error::Generic(msg)) => assert_eq!(msg, "Bad right"),
+            x => panic!("{:?}", x),
+        }
+        let args = [Value::Text(Cow::Borrowed("a")), Value::Usize(1)];
+        match spec.call_with(&args, |args| surround(args, api())) {
+            Err(Error::Arg(1, msg)) => assert_eq!(msg, "is a value of type Usize. Expected a Text"),
+            x => panic!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn keyword_arguments() {
+        // The keywords come after the positional arguments, looked up by
+        // name in the list that the function is registered with
+        const KEYWORDS: &[(&str, Types)] = &[("by", Types::one(v::TEXT)), ("year", Types::one(v::TEXT))];
+        fn quote<'a>(args: &[Value<'a, ()>], _: Api<'a>) -> PureResult<'a, ()> {
+            let (words, keywords) = Keywords::split(args, KEYWORDS);
+            let text = words
+                .iter()
+                .map(|arg| match arg {
+                    Value::Text(s) => s.as_ref(),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            match (keywords.text("by"), keywords.text("year")) {
+                (Some(by), None) => Ok(Value::Text(Cow::Owned(format!("\"{}\" - {}", text, by)))),
+                (Some(by), Some(year)) => Ok(Value::Text(Cow::Owned(format!("\"{}\" - {}, {}", text, by, year)))),
+                (None, None) => Ok(Value::Text(Cow::Owned(format!("\"{}\"", text)))),
+                (None, Some(_)) => Err(Error::Arg(keywords.index("year"), "Year without an author".into())),
+            }
+        }
+
+        fn upper<'a>(args: &[Value<'a, ()>], _: Api<'a>) -> PureResult<'a, ()> {
+            match &args[0] {
+                Value::Text(s) => Ok(Value::Text(Cow::Owned(s.to_uppercase()))),
+                _ => unreachable!(),
+            }
+        }
+
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        let params = ParamDef::new().rest(v::TEXT).keywords_from(KEYWORDS);
+        ctx.register_pure_function_with("quote", &quote, params);
+        ctx.register_pure_function("upper", &upper, LIMITED, &[v::TEXT]);
+        assert_eq!(ctx.function_info("quote").unwrap().signature(), "quote(Text...; by: Text, year: Text)");
+
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        let compile = |src: &str| ctx.compile(src, config.clone());
+        assert_eq!(compile("{$ quote \"a\", \"b\" $}"), Ok("\"a b\"".to_string()));
+        assert_eq!(compile("{$ quote \"a\", year: \"1\", by: \"c\" $}"), Ok("\"a\" - c, 1".to_string()));
+        // Values can be calls, keywords can come before positional arguments, and
+        // the body of a block is still the last positional argument
+        assert_eq!(compile("{$ quote by: upper(\"c\"), \"a\" $}"), Ok("\"a\" - C".to_string()));
+        assert_eq!(compile("{| quote by: \"b\" |}a{| |}"), Ok("\"a\" - b".to_string()));
+
+        let error = |src: &str| compile(src).unwrap_err();
+        assert!(error("{$ quote \"a\", at: \"b\" $}").ends_with("^^ is not a keyword parameter. Expected one of: by, year"));
+        assert!(error("{$ quote by: \"a\", by: \"b\" $}").ends_with("^^ This keyword argument is given more than once"));
+        assert!(error("{$ upper \"a\", by: \"b\" $}").ends_with("^^ This function takes no keyword arguments"));
+        // Errors from the function point at the keyword argument
+        assert!(error("{$ quote \"a\", year: \"1\" $}").ends_with(" ^ Year without an author"));
+    }
+
+    #[test]
+    fn composition() {
+        fn upper<'a>(args: &[Value<'a, ()>], _: Api<'a>) -> PureResult<'a, ()> {
+            match &args[0] {
+                Value::Text(s) => Ok(Value::Text(Cow::Owned(s.to_uppercase()))),
+                _ => unreachable!(),
+            }
+        }
+        fn lower<'a>(args: &[Value<'a, ()>], _: Api<'a>) -> PureResult<'a, ()> {
+            match &args[0] {
+                Value::Text(s) => Ok(Value::Text(Cow::Owned(s.to_lowercase()))),
+                _ => unreachable!(),
+            }
+        }
+        let text = || {
+            let mut ctx: Bindings<(), ()> = Bindings::new();
+            ctx.register_pure_function("upper", &upper, LIMITED, &[v::TEXT]);
+            ctx.register_pure_function("lower", &lower, LIMITED, &[v::TEXT]);
+            ctx.document("upper", "Uppercase", &["text"], &[]);
+            ctx
+        };
+
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_typed_pure_function("greet", greet {});
+        ctx.mount("text", text()).unwrap();
+        ctx.mount("text.ascii", text()).unwrap();
+        assert_eq!(
+            ctx.functions().map(|f| f.name).collect::<Vec<_>>(),
+            ["greet", "text.ascii.lower", "text.ascii.upper", "text.lower", "text.upper"],
+        );
+        assert_eq!(ctx.function_info("text.upper").unwrap().signature(), "text.upper(text: Text)");
+
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        let compile = |ctx: &Bindings<(), ()>, src: &str| ctx.compile(src, config.clone());
+        assert_eq!(
+            compile(&ctx, "{$ text.upper \"a\" $} {$ text.ascii.lower(\"B\") $} {| text.upper |}c"),
+            Ok("A b C".to_string()),
+        );
+        // A '.' is only part of an ident if a letter follows, so this is
+        // 'greet' given STDIN explicitly and then implicitly
+        assert_eq!(compile(&ctx, "{| greet. |}d"), Ok("d, d".to_string()));
+        assert!(compile(&ctx, "{$ text.title \"a\" $}").is_err());
+
+        // Conflicts are reported and nothing is added
+        assert_eq!(ctx.mount("text", text()), Err(vec!["text.lower".to_string(), "text.upper".to_string()]));
+        assert_eq!(ctx.mount("bad name", text()), Err(vec!["bad name".to_string()]));
+        assert_eq!(ctx.mount("", text()), Err(vec!["".to_string()]));
+        let mut more = text();
+        more.register_typed_pure_function("greet", greet {});
+        assert_eq!(ctx.extend(more), Err(vec!["greet".to_string()]));
+        assert!(!ctx.contains("upper"));
+
+        // Overriding replaces the function and its documentation
+        ctx.extend_overriding(text());
+        ctx.register_pure_function("text.upper", &lower, LIMITED, &[v::TEXT]);
+        assert_eq!(compile(&ctx, "{$ upper \"a\" $}{$ text.upper \"A\" $}"), Ok("Aa".to_string()));
+        assert!(ctx.function_info("text.upper").unwrap().documentation.is_none());
+
+        assert!(ctx.remove("text.upper"));
+        assert!(!ctx.remove("text.upper"));
+        assert!(compile(&ctx, "{$ text.upper \"a\" $}").is_err());
+    }
+
+    #[derive(Clone, Debug)]
+    struct Anchor(String);
+
+    impl Render for Anchor {
+        fn render(&self, filetype: FileType) -> Cow<'_, str> {
+            match filetype {
+                FileType::Html => Cow::Owned(format!("<a href=\"#{0}\">{0}</a>", self.0)),
+                _ => Cow::Owned(format!("[{0}](#{0})", self.0)),
+            }
+        }
+    }
+
+    fn anchor<'a>(args: &[Value<'a, Anchor>], _: Api<'a>) -> PureResult<'a, Anchor> {
+        match &args[0] {
+            Value::Text(s) => Ok(Value::Custom(Anchor(s.to_string()))),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn custom_values() {
+        let mut ctx: Bindings<(), Anchor> = Bindings::new();
+        ctx.register_pure_function("anchor", &anchor, LIMITED, &[v::TEXT]);
+        ctx.register_typed_pure_function("greet", greet {});
+        let src = "See {$ anchor \"intro\" $}.";
+
+        let html = Config::new(FileType::Markdown, FileType::Html);
+        let markdown = Config::new(FileType::Markdown, FileType::Markdown);
+        assert_eq!(ctx.compile(src, html.clone()), Ok("See <a href=\"#intro\">intro</a>.".to_string()));
+        assert_eq!(ctx.compile(src, markdown.clone()), Ok("See [intro](#intro).".to_string()));
+
+        let ast = Bindings::<(), Anchor>::build(src).unwrap();
+        let mut buffer = Vec::new();
+        ctx.run_to_writer(&ast, markdown, src, &mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), "See [intro](#intro).");
+
+        let err = ctx.compile("{$ greet(anchor(\"a\")) $}", html).unwrap_err();
+        assert!(err.ends_with("is a value of type Custom \"[a](#a)\". Expected a Text"), "{}", err);
+    }
+
+    typed_function! {
+        fn size<'a, V>(_api: Api<'a>; data: &[u8]) -> PureResult<'a, V> {
+            Ok(Value::Usize(data.len()))
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        use tetra::run::utility::{base64, concat, data_uri, shell, sniff_extension};
+
+        // Added after the others so that their type codes do not change
+        assert_eq!([v::NULL, v::TEXT, v::USIZE, v::CHAR, v::BOOL, v::LIST, v::CUSTOM, v::BYTES], [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        assert_eq!(["", "f", "fo", "foo", "foobar"].map(|s| base64(s.as_bytes())), ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYmFy"]);
+        assert_eq!(data_uri(b"\xff\xfe", "image/png"), "data:image/png;base64,//4=");
+        assert_eq!(sniff_extension(b"<?xml version=\"1.0\"?>\n<svg>"), Some("svg"));
+        assert_eq!(sniff_extension(b"\x89PNG\r\n\x1a\n"), Some("png"));
+
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_pure_function("run", &shell, LIMITED, &[v::TEXT, v::TEXT]);
+        ctx.register_pure_function("concat", &concat, UNLIMITED, &[]);
+        ctx.register_typed_pure_function("size", size {});
+        let png = "{| run \"sh\" |}printf '\\211PNG\\r\\n\\032\\n'";
+        let html = Config::new(FileType::Markdown, FileType::Html);
+        let markdown = Config::new(FileType::Markdown, FileType::Markdown);
+
+        // Outputs that are not UTF-8 are kept as they are
+        let size_of_png = "{$ size(run(\"sh\", \"echo iVBORw0KGgo= | base64 -d\")) $}";
+        assert_eq!(ctx.compile(size_of_png, html.clone()), Ok("8".to_string()));
+        // and are embedded as images in HTML, also through 'concat'
+        let embedded = "<img src=\"data:image/png;base64,iVBORw0KGgo=\">";
+        assert_eq!(ctx.compile(png, html.clone()), Ok(embedded.to_string()));
+        assert_eq!(
+            ctx.compile("{$ concat(run(\"sh\", \"echo iVBORw0KGgo= | base64 -d\")) $}", html.clone()),
+            Ok(embedded.to_string()),
+        );
+
+        let err = ctx.compile(png, markdown).unwrap_err();
+        assert!(err.contains("is 8 bytes of binary output"), "{}", err);
+        let err = ctx.compile("{| run \"sh\" |}printf '\\377'", html.clone()).unwrap_err();
+        assert!(err.contains("is 1 bytes of binary output"), "{}", err);
+        let err = ctx.compile("{$ size \"text\" $}", html).unwrap_err();
+        assert!(err.ends_with("is a value of type Text. Expected a Bytes"), "{}", err);
+    }
+
+    // Numbers the terms by the order they are first used, like footnotes
+    struct Glossary;
+
+    impl Collect<()> for Glossary {
+        fn batch<'a>(&self, items: Vec<Value<'a, ()>>, _: Api<'a>) -> Result<Batch<'a, ()>, Error> {
+            let mut terms: Vec<String> = Vec::new();
+            let slots = items
+                .iter()
+                .map(|item| {
+                    let term = match item {
+                        Value::Text(s) => s.to_string(),
+                        _ => unreachable!(),
+                    };
+                    let number = match terms.iter().position(|t| *t == term) {
+                        Some(i) => i + 1,
+                        None => {
+                            terms.push(term);
+                            terms.len()
+                        }
+                    };
+                    Value::Usize(number)
+                })
+                .collect();
+            let listing = terms.iter().enumerate().map(|(i, t)| format!("{}. {}", i + 1, t));
+            let aggregate = Value::Text(Cow::Owned(listing.collect::<Vec<_>>().join(" ")));
+            Ok(Batch { slots, aggregate })
+        }
+    }
+
+    #[test]
+    fn collector() {
+        let mut ctx: Bindings<&str, ()> = Bindings::new();
+        let params = ParamDef::new().required(v::TEXT);
+        ctx.register_collector("term", Some("glossary"), "glossary", Glossary, params);
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        let compile = |src: &str| ctx.compile(src, config.clone());
+
+        assert_eq!(
+            compile("{$ term \"b\" $} {$ term \"a\" $} {$ term \"b\" $} [{$ glossary $}]"),
+            Ok("1 2 1 [1. b 2. a]".to_string()),
+        );
+        // The aggregate can come before the calls and there need not be any
+        assert_eq!(compile("[{$ glossary $}] {$ term \"c\" $}"), Ok("[1. c] 1".to_string()));
+        assert_eq!(compile("[{$ glossary $}]"), Ok("[]".to_string()));
+
+        let src = "{$ term \"x\" $}{$ term \"y\" $}{$ glossary $}";
+        let ast = Bindings::<&str, ()>::build(src).unwrap();
+        let mut buffer = Vec::new();
+        ctx.run_to_writer(&ast, config.clone(), src, &mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), "121. x 2. y");
+
+        // A 'Collect::batch()' that loses items is an error, not a panic
+        ctx.register_collector("lossy", None, "lossy", Lossy, ParamDef::new().required(v::TEXT));
+        let err = ctx.compile("{$ lossy \"a\" $} {$ lossy \"b\" $}", config).unwrap_err();
+        assert!(err.contains("The collector 'lossy' returned 0 results for 2 calls"), "{}", err);
+    }
+
+    struct Lossy;
+
+    impl Collect<()> for Lossy {
+        fn batch<'a>(&self, _: Vec<Value<'a, ()>>, _: Api<'a>) -> Result<Batch<'a, ()>, Error> {
+            Ok(Batch { slots: Vec::new(), aggregate: Value::Null })
+        }
+    }
+
+    // Would list the terms in reverse if its batch were ever run
+    struct Reversed;
+
+    impl Collect<()> for Reversed {
+        fn batch<'a>(&self, items: Vec<Value<'a, ()>>, api: Api<'a>) -> Result<Batch<'a, ()>, Error> {
+            let Batch { slots, aggregate } = Glossary.batch(items.into_iter().rev().collect(), api)?;
+            Ok(Batch { slots: slots.into_iter().rev().collect(), aggregate })
+        }
+    }
+
+    #[test]
+    fn shared_collector_key() {
+        let mut ctx: Bindings<&str, ()> = Bindings::new();
+        let params = || ParamDef::new().required(v::TEXT);
+        ctx.register_collector("term", Some("glossary"), "glossary", Glossary, params());
+        ctx.register_collector("see", Some("index"), "glossary", Reversed, params());
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        let compile = |src: &str| ctx.compile(src, config.clone());
+
+        // Whichever call or aggregate comes first, the batch of 'term' runs
+        assert_eq!(
+            compile("{$ term \"a\" $} {$ see \"b\" $} [{$ index $}]"),
+            Ok("1 2 [1. a 2. b]".to_string()),
+        );
+        assert_eq!(
+            compile("[{$ index $}] {$ see \"b\" $} {$ term \"a\" $} [{$ glossary $}]"),
+            Ok("[1. b 2. a] 1 2 [1. b 2. a]".to_string()),
+        );
+
+        // A function that stores something else under the key is an error
+        let overwrite = boxed_stateful(|_, _, _, storage| {
+            storage.insert("glossary", Value::List(vec![Value::Null]));
+            Ok((Dirty::Ready, Value::Null))
+        });
+        ctx.register_owned_stateful_function("overwrite", overwrite, LIMITED, &[]);
+        let err = ctx.compile("{$ term \"a\" $}{$ overwrite $}", config).unwrap_err();
+        assert!(err.contains("The state of the collector 'term' was overwritten"), "{}", err);
+    }
+
+    #[test]
+    fn hooks() {
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_typed_pure_function("greet", greet {});
+        ctx.register_pre_lex_hook(|source, _config| Ok(source.replace("\r\n", "\n")));
+        ctx.register_post_knit_hook(|output, api| match api.meta.output_filetype {
+            FileType::Html => Ok(format!("<body>{}</body>", output)),
+            _ => Ok(output),
+        });
+        let mut layout: Bindings<(), ()> = Bindings::new();
+        layout.register_post_knit_hook(|output, _api| Ok(format!("<html>{}</html>", output)));
+        ctx.extend(layout).unwrap();
+
+        let src = "{$ greet \"Ann\" $}\r\n";
+        let html = Config::new(FileType::Markdown, FileType::Html);
+        assert_eq!(ctx.compile(src, html.clone()), Ok("<html><body>Hello, Ann\n</body></html>".to_string()));
+        let markdown = Config::new(FileType::Markdown, FileType::Markdown);
+        assert_eq!(ctx.compile(src, markdown), Ok("<html>Hello, Ann\n</html>".to_string()));
+
+        let source = ctx.pre_lex(src, &mut html.clone()).unwrap();
+        let ast = Bindings::<(), ()>::build(&source).unwrap();
+        let mut buffer = Vec::new();
+        ctx.run_to_writer(&ast, html.clone(), &source, &mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), "<html><body>Hello, Ann\n</body></html>");
+
+        ctx.register_pre_lex_hook(|_, _| Err(Error::Generic("No source today".into())));
+        assert_eq!(ctx.compile(src, html), Err("No source today".to_string()));
+    }
+
+    #[test]
+    fn mounted_hooks() {
+        let mut plugin: Bindings<(), ()> = Bindings::new();
+        plugin.register_typed_pure_function("greet", greet {});
+        plugin.register_pre_lex_hook(|source, _config| Ok(source.replace("Hi", "{$ greet \"Hi\" $}")));
+        plugin.register_post_knit_hook(|output, _api| Ok(format!("[{}]", output)));
+        plugin.register_post_knit_hook_for("greet", |output, _api| Ok(output.to_uppercase()));
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.mount("plugin", plugin).unwrap();
+
+        // The pre-lex hook and the plain post-knit hook run on the whole document
+        let config = Config::new(FileType::Markdown, FileType::Markdown);
+        let compile = |src: &str| ctx.compile(src, config.clone());
+        assert_eq!(compile("a"), Ok("[a]".to_string()));
+        // The hook outputs a call of the name without the prefix
+        assert_eq!(compile("Hi").unwrap_err(), "   |\n 1 | {$ greet \"Hi\" $}\n   |    ^^^^^ No function or variable named this.");
+        // The hook for 'greet' runs for calls of 'plugin.greet' only
+        assert_eq!(compile("{$ plugin.greet \"b\" $}"), Ok("[HELLO, B]".to_string()));
+    }
+
+    fn title<'a>(_: &[Value<'a, ()>], api: Api<'a>) -> PureResult<'a, ()> {
+        let title = api.metadata().attributes.get("title").copied().unwrap_or("Untitled");
+        Ok(Value::Text(Cow::Borrowed(title)))
+    }
+
+    fn headings<'a>(_: &[Value<'a, ()>], api: Api<'a>) -> PureResult<'a, ()> {
+        let outline = api.outline().into_iter().map(|OutlineEntry(level, s)| format!("{}{}", level, s));
+        Ok(Value::Text(Cow::Owned(outline.collect::<Vec<_>>().join(","))))
+    }
+
+    fn location<'a>(_: &[Value<'a, ()>], api: Api<'a>) -> PureResult<'a, ()> {
+        let path = api.meta.input_path.as_ref().map(|p| p.display().to_string()).unwrap_or_default();
+        let name = api.span().to_str(api.source());
+        Ok(Value::Text(Cow::Owned(format!("{}:{} {}", path, api.line_number(), name))))
+    }
+
+    #[test]
+    fn document_metadata() {
+        let mut ctx: Bindings<(), ()> = Bindings::new();
+        ctx.register_pure_function("title", &title, LIMITED, &[]);
+        ctx.register_pure_function("headings", &headings, LIMITED, &[]);
+        ctx.register_pure_function("location", &location, UNLIMITED, &[v::TEXT]);
+
+        let mut config = Config::new(FileType::CommonMark, FileType::Html);
+        config.input_path = Some("notes.md".into());
+        let src = "---\ntitle: Notes\n---\n# Intro\n## Two\n{$ title $}: {$ headings $}\n{$ location $}";
+        assert_eq!(
+            ctx.compile(src, config.clone()),
+            Ok("---\ntitle: Notes\n---\n# Intro\n## Two\nNotes: 1Intro,2Two\nnotes.md:7 location".to_string()),
+        );
+        // The span covers the arguments, here the body
+        let src = "\n{| location \"a\" |}body";
+        assert_eq!(ctx.compile(src, config.clone()), Ok("\nnotes.md:2 location \"a\" |}body".to_string()));
+
+        // Filetypes without an analyser just have no metadata
+        let config = Config::new(FileType::Default, FileType::Html);
+        assert_eq!(ctx.compile("{$ title $}", config), Ok("Untitled".to_string()));
+    }
+}
@@ -0,0 +1,456 @@
+//run: cargo test -- --nocapture
+
+// The functions of the default flavour
+
+#[cfg(test)]
+mod tests {
+    use tetra::api::{FileType, Config};
+
+    fn compile(source: &str, input: FileType, output: FileType) -> Result<String, String> {
+        tetra::default_context().compile(source, Config::new(input, output))
+    }
+
+    // A directory of its own in the temporary directory (canonical, as the
+    // paths in errors are) with the {files}, as (path, contents) pairs
+    struct Fixture(std::path::PathBuf);
+
+    impl Fixture {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let directory = std::fs::canonicalize(std::env::temp_dir()).unwrap().join(name);
+            let _ = std::fs::remove_dir_all(&directory);
+            std::fs::create_dir_all(&directory).unwrap();
+            for (path, contents) in files {
+                let path = directory.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, contents).unwrap();
+            }
+            Fixture(directory)
+        }
+
+        fn path(&self, path: &str) -> std::path::PathBuf {
+            self.0.join(path)
+        }
+
+        // Compiles {source} as the file 'document.md' of the directory,
+        // written to 'output' next to it
+        fn compile(&self, source: &str, output: FileType) -> Result<String, String> {
+            let mut config = Config::new(FileType::CommonMark, output);
+            config.input_path = Some(self.path("document.md"));
+            config.output_path = Some(self.path("output"));
+            tetra::default_context().compile(source, config)
+        }
+    }
+
+    #[test]
+    fn toc() {
+        // The third heading is output by a cell
+        let source = "{$ toc $}\n\n# A & B\n## Two\n{$ concat \"#### Four\" $}\n### Two\n";
+        let markdown = compile(source, FileType::CommonMark, FileType::CommonMark).unwrap();
+        assert_eq!(markdown, concat!(
+            "- [A & B](#a--b)\n",
+            "  - [Two](#two)\n",
+            "    - [Two](#two-1)\n",
+            "\n# A & B\n## Two\n#### Four\n### Two\n",
+        ));
+
+        let html = compile("{$ toc \"2\" $}\n# A & B\n## Two\n# C\n", FileType::CommonMark, FileType::Html);
+        assert_eq!(html.unwrap(), concat!(
+            "<nav><ul><li><a href=\"#a--b\">A &amp; B</a><ul><li><a href=\"#two\">Two</a>",
+            "</li></ul></li><li><a href=\"#c\">C</a></li></ul></nav>\n# A & B\n## Two\n# C\n",
+        ));
+
+        let asciidoc = compile("{$ toc $}\n\n= Title\n\n== First Part\n", FileType::AsciiDoctor, FileType::AsciiDoctor);
+        assert_eq!(asciidoc.unwrap(), "* <<_title,Title>>\n** <<_first_part,First Part>>\n\n= Title\n\n== First Part\n");
+
+        let latex = compile("{$ toc \"2\" $}", FileType::CommonMark, FileType::LaTeX);
+        assert_eq!(latex.unwrap(), "\\setcounter{tocdepth}{2}\n\\tableofcontents");
+
+        // No headings, or no analyser for the input to find any
+        assert_eq!(compile("a\n{$ toc $}", FileType::CommonMark, FileType::CommonMark), Ok("a\n".to_string()));
+        assert_eq!(compile("a\n{$ toc $}", FileType::CommonMark, FileType::Html), Ok("a\n".to_string()));
+        assert_eq!(compile("# A\n{$ toc $}", FileType::Default, FileType::Html), Ok("# A\n".to_string()));
+        // Text that only looks like a placeholder is left alone
+        let source = "\u{E000}toc:x {$ toc $}\n# A\n";
+        let markdown = compile(source, FileType::CommonMark, FileType::CommonMark);
+        assert_eq!(markdown, Ok("\u{E000}toc:x - [A](#a)\n# A\n".to_string()));
+        assert!(compile("{$ toc \"zero\" $}", FileType::CommonMark, FileType::Html).is_err());
+    }
+
+    #[test]
+    fn numbering() {
+        // Forward and backward references
+        let source = concat!(
+            "See {$ ref \"fig:b\" $}. ",
+            "{$ number \"figure\", \"fig:a\" $} {$ number \"table\" $} {$ number \"figure\", \"fig:b\" $} ",
+            "{$ ref \"fig:a\" $}",
+        );
+        assert_eq!(
+            compile(source, FileType::CommonMark, FileType::Html),
+            Ok(concat!(
+                "See <a href=\"#fig:b\">Figure 2</a>. ",
+                "<span id=\"fig:a\">Figure 1</span> Table 1 <span id=\"fig:b\">Figure 2</span> ",
+                "<a href=\"#fig:a\">Figure 1</a>",
+            ).to_string()),
+        );
+        assert_eq!(
+            compile(source, FileType::CommonMark, FileType::LaTeX),
+            Ok(concat!(
+                "See \\hyperlink{fig:b}{Figure 2}. ",
+                "\\hypertarget{fig:a}{Figure 1} Table 1 \\hypertarget{fig:b}{Figure 2} ",
+                "\\hyperlink{fig:a}{Figure 1}",
+            ).to_string()),
+        );
+
+        let special = "{$ number \"a&b\", \"t<1>_#}\" $} {$ ref \"t<1>_#}\" $}";
+        // Labels and kinds are escaped for where they are output
+        assert_eq!(
+            compile(special, FileType::CommonMark, FileType::Html),
+            Ok("<span id=\"t&lt;1&gt;_#}\">A&amp;b 1</span> <a href=\"#t&lt;1&gt;_#}\">A&amp;b 1</a>".to_string()),
+        );
+        assert_eq!(
+            compile(special, FileType::CommonMark, FileType::LaTeX),
+            Ok("\\hypertarget{t<1>\\_\\#\\}}{A\\&b 1} \\hyperlink{t<1>\\_\\#\\}}{A\\&b 1}".to_string()),
+        );
+        assert_eq!(
+            compile(special, FileType::CommonMark, FileType::CommonMark),
+            Ok("<span id=\"t&lt;1&gt;_#}\">A&b 1</span> [A&b 1](#t\\<1>\\_#})".to_string()),
+        );
+
+        let config = (FileType::CommonMark, FileType::CommonMark);
+        assert_eq!(
+            compile("{$ ref \"nowhere\" $}", config.0, config.1),
+            Err(concat!(
+                "   |\n",
+                " 1 | {$ ref \"nowhere\" $}\n",
+                "   |    ^^^ There is no 'number' with the label \"nowhere\" to reference",
+            ).to_string()),
+        );
+        let twice = "{$ number \"table\", \"t\" $}{$ number \"table\", \"t\" $}";
+        assert_eq!(
+            compile(twice, config.0, config.1),
+            Err(format!("   |\n 1 | {}\n   |    ^^^^^^ The label \"t\" is used more than once", twice)),
+        );
+    }
+
+    #[test]
+    fn footnotes() {
+        let source = "A{$ footnote \"one\" $} B{$ footnote \"two\" $}\n\n{$ footnotes $}";
+        let html = compile(source, FileType::CommonMark, FileType::Html).unwrap();
+        assert_eq!(html, concat!(
+            "A<sup id=\"fnref1\"><a href=\"#fn1\" role=\"doc-noteref\">1</a></sup> ",
+            "B<sup id=\"fnref2\"><a href=\"#fn2\" role=\"doc-noteref\">2</a></sup>\n\n",
+            "<section class=\"footnotes\" role=\"doc-endnotes\"><ol>",
+            "<li id=\"fn1\">one <a href=\"#fnref1\" role=\"doc-backlink\">↩</a></li>",
+            "<li id=\"fn2\">two <a href=\"#fnref2\" role=\"doc-backlink\">↩</a></li>",
+            "</ol></section>",
+        ));
+        assert_eq!(
+            compile(source, FileType::CommonMark, FileType::CommonMark),
+            Ok("A[^1] B[^2]\n\n[^1]: one\n[^2]: two".to_string()),
+        );
+        assert_eq!(
+            compile(source, FileType::CommonMark, FileType::LaTeX),
+            Ok("A\\footnote{one} B\\footnote{two}\n\n".to_string()),
+        );
+        assert_eq!(
+            compile(source, FileType::AsciiDoctor, FileType::AsciiDoctor),
+            Ok("Afootnote:[one] Bfootnote:[two]\n\n".to_string()),
+        );
+        // The listing can come first and there need not be any footnotes
+        assert_eq!(
+            compile("{$ footnotes $}|{$ footnote \"x\" $}", FileType::CommonMark, FileType::Default),
+            Ok("[1] x|[1]".to_string()),
+        );
+        assert_eq!(compile("{$ footnotes $}", FileType::CommonMark, FileType::Html), Ok(String::new()));
+        // Notes that are never listed would be lost, unless the output places them
+        let unlisted = "A{$ footnote \"one\" $} B{$ footnote \"two\" $}";
+        assert_eq!(
+            compile(unlisted, FileType::CommonMark, FileType::Html),
+            Err(concat!(
+                "   |\n",
+                " 1 | A{$ footnote \"one\" $} B{$ footnote \"two\" $}\n",
+                "   |     ^^^^^^^^ The footnote \"one\" is never listed. Call 'footnotes' where the notes should go",
+            ).to_string()),
+        );
+        assert!(compile(unlisted, FileType::CommonMark, FileType::CommonMark).is_err());
+        assert_eq!(
+            compile(unlisted, FileType::CommonMark, FileType::LaTeX),
+            Ok("A\\footnote{one} B\\footnote{two}".to_string()),
+        );
+
+        // Each listing has the notes since the previous one, and the ids stay unique
+        let source = "A{$ footnote \"one\" $}\n{$ footnotes $}\nB{$ footnote \"two\" $}\n{$ footnotes $}";
+        let html = compile(source, FileType::CommonMark, FileType::Html).unwrap();
+        assert_eq!(html, concat!(
+            "A<sup id=\"fnref1\"><a href=\"#fn1\" role=\"doc-noteref\">1</a></sup>\n",
+            "<section class=\"footnotes\" role=\"doc-endnotes\"><ol>",
+            "<li id=\"fn1\">one <a href=\"#fnref1\" role=\"doc-backlink\">↩</a></li>",
+            "</ol></section>\n",
+            "B<sup id=\"fnref2\"><a href=\"#fn2\" role=\"doc-noteref\">2</a></sup>\n",
+            "<section class=\"footnotes\" role=\"doc-endnotes\"><ol start=\"2\">",
+            "<li id=\"fn2\">two <a href=\"#fnref2\" role=\"doc-backlink\">↩</a></li>",
+            "</ol></section>",
+        ));
+        assert_eq!(
+            compile(source, FileType::CommonMark, FileType::Default),
+            Ok("A[1]\n[1] one\nB[2]\n[2] two".to_string()),
+        );
+    }
+
+    #[test]
+    fn includes() {
+        let fixture = Fixture::new("tetra_includes", &[
+            ("one.md", "{$ number \"table\" $} {$ include(\"two.md\") $}"),
+            ("two.md", "{$ number \"table\", \"t\" $}"),
+            ("cycle.md", "{$ include \"cycle2.md\" $}"),
+            ("cycle2.md", "x {$ include \"cycle.md\" $}"),
+            ("broken.md", "{$ number \"table\" "),
+            ("parts/intro.md", "{$ include \"../two.md\" $}"),
+            ("parts/broken.md", "Text\n{$ ref \"nowhere\" $}\n"),
+        ]);
+        let run = |source: &str| fixture.compile(source, FileType::Default);
+        let path = |path: &str| fixture.path(path).display().to_string();
+
+        // Included files run with the document, so numbering carries on
+        assert_eq!(run("{$ ref \"t\" $}: {$ include \"one.md\" $}."), Ok("Table 2: Table 1 Table 2.".to_string()));
+        // Computed paths are only known at run time, so are output as is
+        assert_eq!(run("{$ include concat(\"two.md\") $}"), Ok("{$ number \"table\", \"t\" $}".to_string()));
+        // Relative to the file that includes them
+        assert_eq!(run("{$ include \"parts/intro.md\" $} {$ ref \"t\" $}"), Ok("Table 1 Table 1".to_string()));
+
+        let cycle = run("{$ include \"cycle.md\" $}").unwrap_err();
+        assert!(cycle.contains("already being included"), "{}", cycle);
+        assert!(cycle.ends_with(&format!("{} -> {} -> {}", path("cycle.md"), path("cycle2.md"), path("cycle.md"))));
+        let broken = run("{$ include \"broken.md\" $}").unwrap_err();
+        assert!(broken.starts_with(&format!("In the included file {:?}:\n", path("broken.md"))), "{}", broken);
+        let missing = run("{$ include \"missing.md\" $}").unwrap_err();
+        assert!(missing.starts_with("Could not include"), "{}", missing);
+
+        // Errors in included files name them and number rows as in them
+        let broken = run("A\n{$ include \"parts/broken.md\" $}").unwrap_err();
+        assert!(broken.starts_with(&format!("In the included file {:?}:\n", path("parts/broken.md"))), "{}", broken);
+        assert!(broken.contains(" 2 | {$ ref \"nowhere\" $}"), "{}", broken);
+        let broken = run("{$ include \"parts/broken.md\", lines: \"2\" $}").unwrap_err();
+        assert!(broken.contains(" 2 | {$ ref \"nowhere\" $}"), "{}", broken);
+    }
+
+    #[test]
+    fn transclusion() {
+        let fixture = Fixture::new("tetra_transclusion", &[
+            ("notes.md", concat!(
+                "# Notes\n\n## Method\n\nM\n\n## Results\n\nR {$ number \"table\" $}\n### Detail\n\nD\n",
+                "<!-- tag::summary[] -->\nS1\n<!-- tag::other[] -->\nS2\n<!-- end::other[] -->\n<!-- end::summary[] -->\n",
+                "## End\n",
+            )),
+            ("notes.adoc", "== A\n\nA\n\n=== B\n\nB\n\n== C\n// tag::c[]\nC\n// end::c[]\n"),
+        ]);
+        let include = |path: &str, keywords: &str| {
+            fixture.compile(&format!("{{$ include \"{}\", {} $}}", path, keywords), FileType::Default)
+        };
+        let (notes, adoc) = ("notes.md", "notes.adoc");
+
+        // The cells of the section run
+        assert_eq!(
+            include(notes, "section: \"Results\""),
+            Ok("## Results\n\nR Table 1\n### Detail\n\nD\n<!-- tag::summary[] -->\nS1\n<!-- tag::other[] -->\nS2\n<!-- end::other[] -->\n<!-- end::summary[] -->\n".to_string()),
+        );
+        assert_eq!(include(notes, "section: \"End\""), Ok("## End\n".to_string()));
+        assert_eq!(include(adoc, "section: \"A\""), Ok("== A\n\nA\n\n=== B\n\nB\n\n".to_string()));
+        assert_eq!(include(notes, "lines: \"3-5\""), Ok("## Method\n\nM\n".to_string()));
+        assert_eq!(include(adoc, "lines: \"12-\""), Ok("// end::c[]\n".to_string()));
+        assert_eq!(include(adoc, "lines: \"1\""), Ok("== A\n".to_string()));
+        assert_eq!(include(notes, "tag: \"summary\""), Ok("S1\nS2\n".to_string()));
+        assert_eq!(include(adoc, "tag: \"c\""), Ok("C\n".to_string()));
+        // Computed paths too
+        let source = "{$ include concat(\"notes.adoc\"), tag: \"c\" $}";
+        assert_eq!(fixture.compile(source, FileType::Default), Ok("C\n".to_string()));
+
+        assert!(include(notes, "section: \"Nowhere\"").unwrap_err().ends_with("Expected one of: Notes, Method, Results, Detail, End"));
+        assert!(include(notes, "lines: \"5-3\"").unwrap_err().contains("not a range of lines"));
+        assert!(include(adoc, "lines: \"20\"").unwrap_err().contains("does not have a line 20"));
+        assert!(include(adoc, "tag: \"d\"").unwrap_err().contains("tag::d[]"));
+        assert!(include(adoc, "tag: \"c\", lines: \"1\"").unwrap_err().ends_with("Only one of section, lines and tag can be given"));
+        assert!(include(adoc, "page: \"1\"").unwrap_err().contains("is not a keyword parameter"));
+    }
+
+    #[test]
+    fn tables() {
+        let fixture = Fixture::new("tetra_tables", &[
+            ("scores.csv", "name,score,note\r\ntetra,97.5,\"a, \"\"b\"\"\"\r\nx|y,3,\r\n\r\n"),
+            ("rows.json", "[{\"name\": \"a_b\", \"n\": 1}, {\"n\": null, \"ok\": true}]"),
+            ("nested.json", "[{\"a\": [1]}]"),
+            ("ragged.csv", "a,b\n1\n"),
+        ]);
+        // Read from the working directory, unlike included files
+        let table = |path: &str, keywords: &str, output: FileType| {
+            let source = format!("{{$ table \"{}\"{} $}}", fixture.path(path).display(), keywords);
+            fixture.compile(&source, output)
+        };
+
+        assert_eq!(
+            table("scores.csv", ", columns: \"name, score\", headers: \"Name, Score\", align: \"lr\"", FileType::CommonMark),
+            Ok(concat!(
+                "| Name  | Score |\n",
+                "|:------|------:|\n",
+                "| tetra |  97.5 |\n",
+                "| x\\|y  |     3 |\n",
+            ).to_string()),
+        );
+        assert_eq!(
+            table("rows.json", "", FileType::LaTeX),
+            Ok(concat!(
+                "\\begin{tabular}{lll}\n\\hline\n",
+                "name & n & ok \\\\\n\\hline\n",
+                "a\\_b & 1 &  \\\\\n",
+                " &  & true \\\\\n",
+                "\\hline\n\\end{tabular}\n",
+            ).to_string()),
+        );
+        assert_eq!(
+            table("scores.csv", ", columns: \"note\", align: \"c\"", FileType::Html),
+            Ok(concat!(
+                "<table>\n<thead>\n<tr><th style=\"text-align: center\">note</th></tr>\n</thead>\n<tbody>\n",
+                "<tr><td style=\"text-align: center\">a, &quot;b&quot;</td></tr>\n",
+                "<tr><td style=\"text-align: center\"></td></tr>\n</tbody>\n</table>\n",
+            ).to_string()),
+        );
+        assert_eq!(
+            table("rows.json", ", columns: \"n, name\", align: \"rl\"", FileType::AsciiDoctor),
+            Ok("[cols=\">,<\",options=\"header\"]\n|===\n|n |name\n\n|1 |a_b\n| |\n|===\n".to_string()),
+        );
+        assert_eq!(
+            table("scores.csv", ", columns: \"score, name\"", FileType::Default),
+            Ok("score  name\n-----  -----\n97.5   tetra\n3      x|y\n".to_string()),
+        );
+
+        // What follows the carets, as their column depends on the path
+        let error = |path: &str, keywords: &str| {
+            let error = table(path, keywords, FileType::Html).unwrap_err();
+            error.split_once("^ ").map(|(_, message)| message.to_string()).unwrap_or(error)
+        };
+        let path = |path: &str| fixture.path(path).display().to_string();
+        let cases = [
+            ("scores.csv", ", columns: \"Name\"", "There is no column \"Name\". Expected one of: name, score, note".to_string()),
+            ("scores.csv", ", headers: \"A, B\"", "has 2 headers but the table has 3 columns".to_string()),
+            ("scores.csv", ", align: \"lx\"", "'x' is not an alignment. Expected 'l', 'c' or 'r'".to_string()),
+            ("scores.csv", ", align: \"lr\"", "aligns 2 columns but the table has 3".to_string()),
+            ("nested.json", "", format!(
+                "{}: The field \"a\" of item 1 is a list or an object, which cannot be shown in a table",
+                path("nested.json"),
+            )),
+            ("ragged.csv", "", format!("{}: line 2: Expected 2 fields as in the header, but there are 1", path("ragged.csv"))),
+            ("nested.json", ", section: \"a\"", "is not a keyword parameter. Expected one of: columns, headers, align".to_string()),
+        ];
+        for (file, keywords, message) in cases {
+            assert_eq!(error(file, keywords), message);
+        }
+        // The rest is up to the OS
+        let missing = error("missing.csv", "");
+        assert!(missing.starts_with(&format!("Could not read file {:?}: ", path("missing.csv"))), "{}", missing);
+    }
+
+    #[test]
+    fn figures() {
+        let fixture = Fixture::new("tetra_figures", &[]);
+        let figure = |source: &str, output: FileType| fixture.compile(source, output);
+
+        // Named by a hash of the contents and linked relative to the output
+        let svg = "{| figure \"sh\", caption: \"A & B\", label: \"fig:a\" |}printf '<svg/>'";
+        let html = figure(svg, FileType::Html).unwrap();
+        let link = html.split('"').find(|part| part.ends_with(".svg")).unwrap().to_string();
+        assert!(link.starts_with("assets/") && link.len() == "assets/.svg".len() + 16, "{}", link);
+        assert_eq!(std::fs::read_to_string(fixture.path(&link)).unwrap(), "<svg/>");
+        assert_eq!(html, format!(
+            "<figure id=\"fig:a\"><img src=\"{}\" alt=\"A &amp; B\"><figcaption>A & B</figcaption></figure>",
+            link
+        ));
+        assert_eq!(figure(svg, FileType::Markdown), Ok(format!("![A & B]({}){{#fig:a}}", link)));
+        assert_eq!(
+            figure(svg, FileType::LaTeX),
+            Ok(format!("\\begin{{figure}}\n\\centering\n\\includesvg{{{}}}\n\\caption{{A & B}}\n\\label{{fig:a}}\n\\end{{figure}}", link)),
+        );
+
+        // Binary outputs are written as is
+        let png = "{| figure \"sh\" |}printf '\\211PNG\\r\\n\\032\\n\\377'";
+        let adoc = figure(png, FileType::AsciiDoctor).unwrap();
+        let link = adoc.strip_prefix("image::").and_then(|rest| rest.strip_suffix("[]")).unwrap();
+        assert!(link.ends_with(".png"), "{}", adoc);
+        assert_eq!(std::fs::read(fixture.path(link)).unwrap(), b"\x89PNG\r\n\x1a\n\xff");
+
+        let text = "{| figure \"sh\", extension: \"txt\" |}printf 'plain'";
+        assert!(figure(text, FileType::CommonMark).unwrap().ends_with(".txt)"));
+        let unknown = figure("{| figure \"sh\" |}printf 'plain'", FileType::CommonMark).unwrap_err();
+        assert!(unknown.contains("unknown type"), "{}", unknown);
+        // Unlike 'run', which can only embed images in HTML
+        let binary = figure("{| run \"sh\" |}printf '\\377'", FileType::CommonMark).unwrap_err();
+        assert!(binary.contains("Use 'figure'"), "{}", binary);
+    }
+
+    // One test, as the environment is shared between threads
+    #[test]
+    fn native_citations() {
+        let fixture = Fixture::new("tetra_native_citations", &[
+            ("a.bib", "@book{a, author = {Capper, Daniel}, title = {A}, year = 2012}"),
+            ("b.json", r#"[{"id": "b", "title": "B", "author": [{"literal": "WHO"}]}]"#),
+            ("ieee.csl", r#"<info><category citation-format="numeric"/></info>"#),
+            ("note.csl", r#"<category citation-format="note"/>"#),
+            ("parts/chapter.md", "{$ include \"sub/section.md\" $}"),
+            ("parts/sub/section.md", "{$ bibliography \"c.bib\" $}{$ csl \"../../ieee.csl\" $}{$ cite \"c\" $}"),
+            ("parts/sub/c.bib", "@book{c, author = {Doe, Jane}, title = {C}, year = 2020}"),
+        ]);
+        std::env::set_var("CITATION_BACKEND", "native");
+
+        // Paths set by the document are relative to it
+        let run = |source: &str, output: FileType| fixture.compile(source, output);
+        let cite = |source: &str, output: FileType| run(&format!("{{$ bibliography \"a.bib\" $}}{}", source), output);
+
+        // 'references' can come first
+        let source = "{$ references $}\n\n{$ cite \"@a\" $} and {$ cite \"[@a]\" $}";
+        std::env::set_var("CITATION_STYLE", "numeric");
+        assert_eq!(
+            cite(source, FileType::Default),
+            Ok("[1] Capper, Daniel. A. 2012.\n\nCapper [1] and [1]".to_string()),
+        );
+        std::env::remove_var("CITATION_STYLE");
+        assert_eq!(
+            cite(source, FileType::Default),
+            Ok("Capper, Daniel. 2012. A.\n\nCapper (2012) and (Capper 2012)".to_string()),
+        );
+        assert!(cite("{$ cite \"c\" $}", FileType::Html).unwrap_err().contains("\"c\""));
+
+        // Structured citations
+        let structured = "{$ cite \"a\", page: \"3-5\", prefix: \"see\" $}, {$ cite \"@a\", narrative: false $}, \
+            {$ cite \"a\", page: \"7\", narrative: true $}, {$ cite \"a\", suppress_author: true $}";
+        assert_eq!(
+            cite(structured, FileType::Default),
+            Ok("(see Capper 2012, pp. 3-5), (Capper 2012), Capper (2012, p. 7), (2012)".to_string()),
+        );
+        assert_eq!(
+            cite("{$ cite \"a\", suppress_author: true $}", FileType::Html),
+            Ok("(<a href=\"#ref-a\">2012</a>)".to_string()),
+        );
+        let error = cite("{$ cite \"a\", narrative: true, suppress_author: true $}", FileType::Html);
+        assert!(error.unwrap_err().ends_with("^^^^ A narrative citation cannot leave out the authors"));
+        let error = cite("{$ cite \"a\", narrative: \"yes\" $}", FileType::Html);
+        assert!(error.unwrap_err().contains("Expected a Bool"));
+
+        // Configured anywhere in the document
+        assert_eq!(
+            run("{$ cite \"[@b; @a]\" $}{$ csl \"ieee.csl\" $}{$ bibliography \"a.bib\", \"b.json\" $}", FileType::Default),
+            Ok("[1, 2]".to_string()),
+        );
+        assert_eq!(
+            run("---\nbibliography: [b.json]\n---\n{$ cite \"b\" $}", FileType::Default),
+            Ok("---\nbibliography: [b.json]\n---\n(WHO n.d.)".to_string()),
+        );
+        assert!(cite("{$ cite \"b\" $}", FileType::Default).unwrap_err().contains("\"b\""));
+        assert!(cite("{$ cite \"a\" $}{$ csl \"note.csl\" $}", FileType::Default).unwrap_err().contains("note"));
+        assert!(cite("{$ cite \"a\" $}{$ csl \"numeric\" $}{$ csl \"numeric\" $}", FileType::Default).unwrap_err().contains("once"));
+        assert!(run("---\nlang: de-DE\n---\n{$ bibliography \"a.bib\" $}{$ cite \"a\" $}", FileType::Default).unwrap_err().contains("English"));
+        // Or in included files, relative to them
+        assert_eq!(run("{$ include \"parts/chapter.md\" $}", FileType::Default), Ok("[1]".to_string()));
+        // Settings alone output nothing
+        assert_eq!(run("{$ bibliography \"missing.bib\" $}", FileType::Default), Ok(String::new()));
+        std::env::remove_var("CITATION_BACKEND");
+    }
+}
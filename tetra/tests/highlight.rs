@@ -0,0 +1,90 @@
+//run: cargo test -- --nocapture
+
+// Syntax highlighting without pygmentize
+
+#[cfg(test)]
+mod tests {
+    use tetra::api::FileType;
+    use tetra::highlight::{grammar, highlight, tokenize, Kind, GRAMMARS};
+
+    // Only the highlighted tokens
+    fn tokens(lang: &str, code: &str) -> Vec<(Kind, String)> {
+        tokenize(grammar(lang).unwrap(), code)
+            .into_iter()
+            .filter(|(kind, _)| *kind != Kind::Text)
+            .map(|(kind, text)| (kind, text.to_string()))
+            .collect()
+    }
+
+    fn token(kind: Kind, text: &str) -> (Kind, String) {
+        (kind, text.to_string())
+    }
+
+    #[test]
+    fn tokenizing() {
+        assert_eq!(tokens("rust", "fn f<'a>(c: char) -> u8 { 'a' as u8 + b'\\'' // 1.5\n}"), vec![
+            token(Kind::Keyword, "fn"),
+            token(Kind::Builtin, "char"),
+            token(Kind::Builtin, "u8"),
+            token(Kind::String, "'a'"),
+            token(Kind::Keyword, "as"),
+            token(Kind::Builtin, "u8"),
+            token(Kind::String, "'\\''"),
+            token(Kind::Comment, "// 1.5"),
+        ]);
+        assert_eq!(tokens("sh", "echo \"$# ${HOME}\" $1 $PATH_2#x # done\nls 'a\\'"), vec![
+            token(Kind::Builtin, "echo"),
+            token(Kind::String, "\"$# ${HOME}\""),
+            token(Kind::Variable, "$1"),
+            token(Kind::Variable, "$PATH_2"),
+            token(Kind::Comment, "# done"),
+            token(Kind::String, "'a\\'"),
+        ]);
+        assert_eq!(tokens("py", "def f(x2):\n    return \"\"\"a\"b\"\"\" if x2 else 0x1F"), vec![
+            token(Kind::Keyword, "def"),
+            token(Kind::Keyword, "return"),
+            token(Kind::String, "\"\"\"a\"b\"\"\""),
+            token(Kind::Keyword, "if"),
+            token(Kind::Keyword, "else"),
+            token(Kind::Number, "0x1F"),
+        ]);
+        assert_eq!(tokens("dot", "digraph { a -> b [label=\"x\"] /* end"), vec![
+            token(Kind::Keyword, "digraph"),
+            token(Kind::Builtin, "label"),
+            token(Kind::String, "\"x\""),
+            token(Kind::Comment, "/* end"),
+        ]);
+
+        // The tokens cover all of the code
+        for grammar in GRAMMARS {
+            let code = "if (x) { return \"a\\\"b\" } # c // d /* e */ 'f' $g 1.2é\n";
+            assert_eq!(tokenize(grammar, code).iter().map(|(_, text)| *text).collect::<String>(), code);
+        }
+    }
+
+    #[test]
+    fn filetypes() {
+        assert_eq!(
+            highlight("Rust", "let s = \"<a>\";", FileType::Html),
+            Ok("<div class=\"highlight\"><pre><span></span><span class=\"k\">let</span> s = <span class=\"s\">&quot;&lt;a&gt;&quot;</span>;\n</pre></div>\n".to_string()),
+        );
+        assert_eq!(
+            highlight("rust", "/* {\n} */ f()", FileType::LaTeX),
+            Ok("\\begin{Verbatim}[commandchars=\\\\\\{\\}]\n\\PY{c}{/* \\PYZob{}}\n\\PY{c}{\\PYZcb{} */} f()\n\\end{Verbatim}\n".to_string()),
+        );
+        assert_eq!(
+            highlight("sh", "echo $HOME", FileType::Default),
+            Ok("\x1b[36mecho\x1b[0m \x1b[33m$HOME\x1b[0m".to_string()),
+        );
+        assert!(highlight("cobol", "", FileType::Html).unwrap_err().contains("sh, rust, python"));
+    }
+
+    #[test]
+    fn syntax_highlight() {
+        let config = tetra::api::Config::new(FileType::Markdown, FileType::Html);
+        assert_eq!(
+            tetra::default_context().compile("{| syntax_highlight \"python\" |}pass", config),
+            Ok("<div class=\"highlight\"><pre><span></span><span class=\"k\">pass</span>\n</pre></div>\n".to_string()),
+        );
+    }
+}
@@ -3,7 +3,10 @@
 
 #[cfg(test)]
 mod tests {
-    use tetra::api::{FileType, Config};
+    use std::borrow::Cow;
+
+    use tetra::api::{Api, FileType, Config};
+    use tetra::run::{Bindings, Dirty, StatefulResult, Value, Variables, LIMITED};
 
     macro_rules! compare_eq {
         ($ctx:ident, $( $source:literal => $answer:literal )*) => {
@@ -26,8 +29,93 @@ mod tests {
             "{| .; |} a"        => ""
 
             "{| a = |}b{| a |}" => "bb"
+
+            // Finished commands are not re-run on later iterations
+            "{$ label \"x\" $}{$ label_set \"x\", \"y\" $}" => "yy"
             // TODO: This should report back an error that it is not defined yet
             //"{| ; a = . |} b {$ a $}" => ""
         }
     }
+
+    #[test]
+    fn streaming_matches_compile() {
+        let ctx = tetra::default_context();
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        for source in [
+            "",
+            "a",
+            "{| ; . |} a",
+            "{| . |} a",
+            "{| .; |} a",
+            "{| a = |}b{| a |}",
+            "{$ label \"x\" $}{$ label_set \"x\", \"y\" $}",
+        ] {
+            let ast = tetra::run::Bindings::<(), ()>::build(source).unwrap();
+            let mut buffer = Vec::new();
+            ctx.run_to_writer(&ast, config.clone(), source, &mut buffer).unwrap();
+            assert_eq!(
+                ctx.compile(source, config.clone()).as_deref(),
+                Ok(String::from_utf8(buffer).unwrap().as_str()),
+                "\nSource: {:?}\n",
+                source,
+            );
+        }
+    }
+
+    #[test]
+    fn dot_graph() {
+        let source = "{| a = |}b{| concat a, \"c\" |}";
+        let ast = tetra::run::Bindings::<(), ()>::build(source).unwrap();
+        let dot = ast.to_dot(source);
+        assert!(dot.starts_with("digraph tetra {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("Ident concat\\nline 1: concat a, \\\"c"));
+
+        // Every reference between commands is an edge
+        let edge_count = ast.0.iter()
+            .flat_map(|cmd| &ast.1[cmd.args.0..cmd.args.1])
+            .filter(|arg| matches!(arg.me, tetra::parser::Param::Reference(_)))
+            .count();
+        assert_eq!(edge_count, dot.matches(" -> ").count());
+    }
+
+    // Counts its own calls
+    fn tick<'a>(
+        _: &[Value<'a, ()>],
+        _: Api<'a>,
+        _: Value<'a, ()>,
+        storage: &mut Variables<'a, &'static str, ()>,
+    ) -> StatefulResult<'a, ()> {
+        let count = match storage.get(&"ticks") {
+            Some(Value::Usize(count)) => count + 1,
+            _ => 1,
+        };
+        storage.insert("ticks", Value::Usize(count));
+        Ok((Dirty::Ready, Value::Usize(count)))
+    }
+
+    // Needs a second iteration, like 'label'
+    fn wait<'a>(
+        _: &[Value<'a, ()>],
+        _: Api<'a>,
+        old_output: Value<'a, ()>,
+        _: &mut Variables<'a, &'static str, ()>,
+    ) -> StatefulResult<'a, ()> {
+        match old_output {
+            Value::Null => Ok((Dirty::Waiting, Value::Usize(0))),
+            _ => Ok((Dirty::Ready, Value::Text(Cow::Borrowed("w")))),
+        }
+    }
+
+    #[test]
+    fn finished_commands() {
+        let mut ctx: Bindings<&str, ()> = Bindings::new();
+        ctx.register_stateful_function("tick", &tick, LIMITED, &[]);
+        ctx.register_stateful_function("wait", &wait, LIMITED, &[]);
+        compare_eq! { ctx,
+            // Only 'wait' runs again, so the ticks keep their first output
+            "{$ tick $}{$ wait $}{$ tick $}" => "1w2"
+            "{$ wait $}{$ wait $}"           => "ww"
+        }
+    }
 }
@@ -0,0 +1,34 @@
+//run: cargo test -- --nocapture
+
+
+#[cfg(test)]
+mod tests {
+    use tetra::api::{FileType, Config};
+    use tetra::run::{Dirty, Trace};
+
+    #[test]
+    fn records_every_command() {
+        let ctx = tetra::default_context();
+        let config = Config::new(FileType::Markdown, FileType::Html);
+        let mut trace = Trace::new();
+        assert_eq!(
+            ctx.compile_with_tracer("{| a = |}b{| a |}", config, &mut trace),
+            Ok("bb".to_string()),
+        );
+
+        assert!(!trace.events.is_empty());
+        assert!(trace.events.iter().all(|e| e.iteration == 0));
+        assert!(trace.events.iter().all(|e| matches!(e.after, Dirty::Ready)));
+        assert!(trace.events.iter().any(|e| e.label == "="));
+
+        // The knit is the last command run and outputs the whole document
+        let knit = trace.events.last().unwrap();
+        assert_eq!("#Concat", knit.label);
+        assert_eq!(2, knit.output_len);
+
+        let json = trace.to_chrome_json();
+        assert!(json.starts_with("{\"traceEvents\":[{\"name\":"));
+        assert!(json.ends_with("}}]}"));
+        assert!(trace.to_log().contains("== Iteration 0"));
+    }
+}